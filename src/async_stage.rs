@@ -0,0 +1,445 @@
+use serde::{de::DeserializeOwned, Deserialize, Deserializer};
+use std::{
+    collections::BTreeMap,
+    fmt::Debug,
+    future::{self, Future},
+    pin::Pin,
+    sync::atomic::{AtomicBool, Ordering},
+    task::{Context, Poll},
+};
+
+use crate::{
+    registered, registry_key, resolve_name, BoxError, RegisterError, StageArgs, StageError,
+    StageFile, StageMeta, StageName,
+};
+
+pub type BoxFuture<'a, T> = Pin<Box<dyn Future<Output = T> + 'a>>;
+
+pub trait AsyncStage {
+    type C;
+
+    fn run<'a>(&'a self, c: &'a mut Self::C) -> BoxFuture<'a, Result<(), StageError>>;
+
+    // The manager calls this instead of run; override it to stop waiting
+    // once meta.cancel is set.
+    fn run_with_meta<'a>(
+        &'a self,
+        c: &'a mut Self::C,
+        _meta: StageMeta<'a>,
+    ) -> BoxFuture<'a, Result<(), StageError>> {
+        self.run(c)
+    }
+
+    fn setup(&mut self) {}
+
+    fn teardown(&mut self) {}
+}
+
+type FnDeserializeAsyncStage<C, V> = Box<dyn Fn(V) -> Result<Box<dyn AsyncStage<C = C>>, BoxError>>;
+
+// Mirrors StageManager for stages that await, running them one at a time in
+// file order.
+#[derive(Deserialize)]
+pub struct AsyncStageManager<C, V> {
+    #[serde(flatten)]
+    file: StageFile<V>,
+
+    #[serde(skip)]
+    deserialize_map: BTreeMap<String, FnDeserializeAsyncStage<C, V>>,
+}
+
+impl<C, V> Debug for AsyncStageManager<C, V>
+where
+    V: Debug,
+{
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(
+            f,
+            "AsyncStageManager{{file: {:?}, deserialize_map: {:?}}}",
+            self.file,
+            self.deserialize_map.keys()
+        )
+    }
+}
+
+impl<'de, C, V> AsyncStageManager<C, V>
+where
+    V: Deserialize<'de> + Deserializer<'de> + Clone,
+{
+    pub fn from_file(stage_file: StageFile<V>) -> Self {
+        Self {
+            file: stage_file,
+            deserialize_map: BTreeMap::new(),
+        }
+    }
+
+    pub fn register_named<S>(&mut self, name: &str) -> Result<&mut Self, RegisterError>
+    where
+        S: 'static + AsyncStage<C = C> + Deserialize<'de>,
+    {
+        if self.deserialize_map.contains_key(name) {
+            return Err(RegisterError::Duplicate(name.to_string()));
+        }
+        Ok(self.register_named_overwrite::<S>(name))
+    }
+
+    pub fn register<S>(&mut self) -> Result<&mut Self, RegisterError>
+    where
+        S: 'static + AsyncStage<C = C> + StageName + Deserialize<'de>,
+    {
+        self.register_named::<S>(&registry_key::<S>())
+    }
+
+    pub fn register_named_overwrite<S>(&mut self, name: &str) -> &mut Self
+    where
+        S: 'static + AsyncStage<C = C> + Deserialize<'de>,
+    {
+        self.deserialize_map.insert(
+            name.to_string(),
+            Box::new(|v| {
+                let stage = S::deserialize(v).map_err(|e| e.to_string())?;
+                Ok(Box::new(stage))
+            }),
+        );
+        self
+    }
+
+    pub fn register_overwrite<S>(&mut self) -> &mut Self
+    where
+        S: 'static + AsyncStage<C = C> + StageName + Deserialize<'de>,
+    {
+        self.register_named_overwrite::<S>(&registry_key::<S>())
+    }
+}
+
+impl<C, V> AsyncStageManager<C, V>
+where
+    V: Clone + DeserializeOwned,
+{
+    pub fn validate(&self) -> Result<(), Vec<String>> {
+        let missing = self
+            .file
+            .unregistered(|name| resolve_name(&self.deserialize_map, name).is_some());
+        if missing.is_empty() {
+            Ok(())
+        } else {
+            Err(missing)
+        }
+    }
+
+    pub async fn run_stages(&self, context: &mut C) -> Result<(), StageError> {
+        self.run_guarded(context, None).await
+    }
+
+    pub async fn run_into(&self, mut context: C) -> Result<C, StageError> {
+        self.run_stages(&mut context).await?;
+        Ok(context)
+    }
+
+    // Runs every context through the pipeline with at most `concurrency` in
+    // flight at once, yielding each result as its run finishes. The next
+    // context is only taken from `contexts` once a slot frees up.
+    pub fn run_stream<'m, I>(&'m self, contexts: I, concurrency: usize) -> RunStream<'m, C, V>
+    where
+        I: IntoIterator<Item = C>,
+        I::IntoIter: 'm,
+    {
+        RunStream {
+            manager: self,
+            pending: Box::new(contexts.into_iter()),
+            running: Vec::new(),
+            concurrency: concurrency.max(1),
+        }
+    }
+
+    // Stops with StageError::Cancelled once `cancel` is set, checked before
+    // each stage starts. Stages that wait, like Sleep, also stop early.
+    pub async fn run_stages_cancellable(
+        &self,
+        context: &mut C,
+        cancel: &AtomicBool,
+    ) -> Result<(), StageError> {
+        self.run_guarded(context, Some(cancel)).await
+    }
+
+    async fn run_guarded(
+        &self,
+        context: &mut C,
+        cancel: Option<&AtomicBool>,
+    ) -> Result<(), StageError> {
+        self.validate()
+            .map_err(|names| StageError::UnknownStage { names })?;
+
+        let mut active = Vec::new();
+        let result = self.run_active(context, cancel, &mut active).await;
+
+        active.iter_mut().rev().for_each(|s| s.teardown());
+        result
+    }
+
+    async fn run_active(
+        &self,
+        context: &mut C,
+        cancel: Option<&AtomicBool>,
+        active: &mut Vec<Box<dyn AsyncStage<C = C>>>,
+    ) -> Result<(), StageError> {
+        for (index, s) in self.file.enabled_stages()? {
+            if cancel.is_some_and(|c| c.load(Ordering::SeqCst)) {
+                return Err(StageError::Cancelled { next_index: index });
+            }
+            let mut stage = self.build_stage(index, s)?;
+            stage.setup();
+            active.push(stage);
+
+            let stage = &active[active.len() - 1];
+            let meta = StageMeta {
+                index,
+                total: self.file.stages.len(),
+                name: &s.name,
+                cancel,
+            };
+            for _ in 0..s.repeat {
+                stage.run_with_meta(context, meta).await?;
+            }
+        }
+        Ok(())
+    }
+
+    fn build_stage(
+        &self,
+        index: usize,
+        s: &StageArgs<V>,
+    ) -> Result<Box<dyn AsyncStage<C = C>>, StageError> {
+        let f = registered(&self.deserialize_map, &s.name);
+        s.args_or_empty()
+            .and_then(f)
+            .map_err(|source| StageError::Deserialize {
+                stage_name: s.name.clone(),
+                index,
+                position: None,
+                source,
+            })
+    }
+}
+
+impl<C, V> AsyncStage for AsyncStageManager<C, V>
+where
+    V: Clone + DeserializeOwned,
+{
+    type C = C;
+
+    fn run<'a>(&'a self, c: &'a mut Self::C) -> BoxFuture<'a, Result<(), StageError>> {
+        Box::pin(self.run_stages(c))
+    }
+
+    fn run_with_meta<'a>(
+        &'a self,
+        c: &'a mut Self::C,
+        meta: StageMeta<'a>,
+    ) -> BoxFuture<'a, Result<(), StageError>> {
+        Box::pin(self.run_guarded(c, meta.cancel))
+    }
+}
+
+// The runs started by AsyncStageManager::run_stream, polled like a stream.
+pub struct RunStream<'m, C, V> {
+    manager: &'m AsyncStageManager<C, V>,
+    pending: Box<dyn Iterator<Item = C> + 'm>,
+    running: Vec<BoxFuture<'m, Result<C, StageError>>>,
+    concurrency: usize,
+}
+
+impl<'m, C, V> RunStream<'m, C, V>
+where
+    V: Clone + DeserializeOwned,
+{
+    // Fills any free slots from the pending contexts and returns the first
+    // run to finish, or None once every context has been run.
+    pub fn poll_next(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<C, StageError>>> {
+        let this = self.get_mut();
+        while this.running.len() < this.concurrency {
+            match this.pending.next() {
+                Some(c) => this.running.push(Box::pin(this.manager.run_into(c))),
+                None => break,
+            }
+        }
+        if this.running.is_empty() {
+            return Poll::Ready(None);
+        }
+
+        for i in 0..this.running.len() {
+            if let Poll::Ready(result) = this.running[i].as_mut().poll(cx) {
+                drop(this.running.swap_remove(i));
+                return Poll::Ready(Some(result));
+            }
+        }
+        Poll::Pending
+    }
+
+    pub async fn next(&mut self) -> Option<Result<C, StageError>> {
+        future::poll_fn(|cx| Pin::new(&mut *self).poll_next(cx)).await
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod test {
+    use serde_yaml::Value;
+    use std::{
+        cell::Cell,
+        sync::Arc,
+        task::{Wake, Waker},
+        thread::{self, Thread},
+    };
+
+    use super::*;
+    use crate::test::{Add, CalcContext, Mul};
+
+    struct ThreadWaker(Thread);
+
+    impl Wake for ThreadWaker {
+        fn wake(self: Arc<Self>) {
+            self.0.unpark();
+        }
+    }
+
+    // Minimal executor so the tests don't need an async runtime.
+    pub(crate) fn block_on<F: Future>(fut: F) -> F::Output {
+        let mut fut = Box::pin(fut);
+        let waker = Waker::from(Arc::new(ThreadWaker(thread::current())));
+        let mut cx = Context::from_waker(&waker);
+        loop {
+            match fut.as_mut().poll(&mut cx) {
+                Poll::Ready(out) => return out,
+                Poll::Pending => thread::park(),
+            }
+        }
+    }
+
+    impl AsyncStage for Add {
+        type C = CalcContext;
+
+        fn run<'a>(&'a self, c: &'a mut Self::C) -> BoxFuture<'a, Result<(), StageError>> {
+            Box::pin(async move {
+                c.x += self.x;
+                Ok(())
+            })
+        }
+    }
+
+    impl AsyncStage for Mul {
+        type C = CalcContext;
+
+        fn run<'a>(&'a self, c: &'a mut Self::C) -> BoxFuture<'a, Result<(), StageError>> {
+            Box::pin(async move {
+                c.x *= self.x;
+                Ok(())
+            })
+        }
+    }
+
+    thread_local! {
+        static IN_FLIGHT: Cell<usize> = const { Cell::new(0) };
+        static MAX_IN_FLIGHT: Cell<usize> = const { Cell::new(0) };
+    }
+
+    // Yields once mid-stage, so runs overlap, and records how many overlap.
+    #[derive(Debug, Deserialize)]
+    struct Track {}
+
+    impl AsyncStage for Track {
+        type C = CalcContext;
+
+        fn run<'a>(&'a self, _c: &'a mut Self::C) -> BoxFuture<'a, Result<(), StageError>> {
+            Box::pin(async move {
+                let now = IN_FLIGHT.with(|n| {
+                    n.set(n.get() + 1);
+                    n.get()
+                });
+                MAX_IN_FLIGHT.with(|max| max.set(max.get().max(now)));
+                let mut yielded = false;
+                future::poll_fn(|cx| {
+                    if yielded {
+                        return Poll::Ready(());
+                    }
+                    yielded = true;
+                    cx.waker().wake_by_ref();
+                    Poll::Pending
+                })
+                .await;
+                IN_FLIGHT.with(|n| n.set(n.get() - 1));
+                Ok(())
+            })
+        }
+    }
+
+    #[test]
+    fn run_stream_bounds_concurrency() {
+        let yaml_str = r#"
+        stages:
+        - name: add
+          args:
+            x: 2
+        - name: track
+        - name: mul
+          args:
+            x: 3
+        "#;
+
+        let file: StageFile<Value> = serde_yaml::from_str(yaml_str).unwrap();
+        let mut m = AsyncStageManager::from_file(file);
+        m.register::<Add>()
+            .unwrap()
+            .register::<Mul>()
+            .unwrap()
+            .register_named::<Track>("track")
+            .unwrap();
+
+        IN_FLIGHT.with(|n| n.set(0));
+        MAX_IN_FLIGHT.with(|max| max.set(0));
+        let taken = Cell::new(0);
+        let contexts = (0..50).map(|x| {
+            taken.set(taken.get() + 1);
+            CalcContext { x }
+        });
+        let mut stream = m.run_stream(contexts, 4);
+
+        let mut xs = Vec::new();
+        while let Some(result) = block_on(stream.next()) {
+            // Only one more context is taken for each result handed back.
+            assert_eq!(taken.get(), (xs.len() + 4).min(50));
+            xs.push(result.unwrap().x);
+        }
+        xs.sort_unstable();
+        let expected: Vec<i64> = (0..50).map(|x| (x + 2) * 3).collect();
+        assert_eq!(xs, expected);
+        assert_eq!(MAX_IN_FLIGHT.with(Cell::get), 4);
+    }
+
+    #[test]
+    fn async_add_mul_pipeline() {
+        let yaml_str = r#"
+        stages:
+        - name: mul
+          args:
+            x: 1
+        - name: add
+          args:
+            x: 2
+        - name: mul
+          args:
+            x: 5
+        "#;
+
+        let file: StageFile<Value> = serde_yaml::from_str(yaml_str).unwrap();
+        let mut m = AsyncStageManager::from_file(file);
+        m.register::<Mul>().unwrap().register::<Add>().unwrap();
+
+        let mut c = CalcContext { x: 1 };
+        block_on(m.run_stages(&mut c)).unwrap();
+
+        assert_eq!(c.x, 15);
+    }
+}
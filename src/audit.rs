@@ -0,0 +1,59 @@
+use serde::{Deserialize, Deserializer, Serialize};
+
+use crate::StageManager;
+
+impl<'de, C, V> StageManager<C, V>
+where
+    V: Deserialize<'de> + Deserializer<'de> + Clone,
+{
+    // Serializes the context to YAML after every successful run and hands it
+    // to `sink`, for audit trails of what a pipeline produced.
+    pub fn log_final_context<F>(&mut self, sink: F) -> &mut Self
+    where
+        C: Serialize,
+        F: 'static + Fn(&str),
+    {
+        self.final_context = Some(Box::new(move |c| match serde_yaml::to_string(c) {
+            Ok(yaml) => sink(&yaml),
+            Err(e) => sink(&format!("failed to serialize final context: {}", e)),
+        }));
+        self
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use serde_yaml::Value;
+    use std::{cell::RefCell, rc::Rc};
+
+    use crate::{
+        test::{Add, CalcContext},
+        StageFile, StageManager,
+    };
+
+    #[test]
+    fn final_context_is_logged() {
+        let yaml_str = r#"
+        stages:
+        - name: add
+          args:
+            x: 2
+        "#;
+
+        let file: StageFile<Value> = serde_yaml::from_str(yaml_str).unwrap();
+        let logged = Rc::new(RefCell::new(Vec::new()));
+        let sink = Rc::clone(&logged);
+        let mut m = StageManager::from_file(file);
+        m.register::<Add>()
+            .unwrap()
+            .log_final_context(move |yaml| sink.borrow_mut().push(yaml.to_string()));
+
+        let mut c = CalcContext { x: 1 };
+        m.run_stages(&mut c).unwrap();
+
+        let logged = logged.borrow();
+        assert_eq!(logged.len(), 1);
+        let c: CalcContext = serde_yaml::from_str(&logged[0]).unwrap();
+        assert_eq!(c.x, 3);
+    }
+}
@@ -0,0 +1,122 @@
+use serde::{Deserialize, Deserializer};
+
+use crate::{registry_key, RegisterError, Stage, StageError, StageFile, StageManager, StageName};
+
+// Collects registrations and a stage file, checking at build time that every
+// stage the file references is registered. The first registration error is
+// kept and returned from build.
+pub struct StageManagerBuilder<C, V> {
+    manager: StageManager<C, V>,
+    error: Option<RegisterError>,
+}
+
+impl<'de, C, V> StageManagerBuilder<C, V>
+where
+    V: Deserialize<'de> + Deserializer<'de> + Clone,
+{
+    pub fn new() -> Self {
+        Self {
+            manager: StageManager::from_file(StageFile {
+                version: 0,
+                stages: Vec::new(),
+            }),
+            error: None,
+        }
+    }
+
+    pub fn with_file(mut self, stage_file: StageFile<V>) -> Self {
+        self.manager.file = stage_file;
+        self
+    }
+
+    pub fn register_named<S>(mut self, name: &str) -> Self
+    where
+        S: 'static + Stage<C = C> + Deserialize<'de>,
+    {
+        if let Err(e) = self.manager.register_named::<S>(name) {
+            self.error.get_or_insert(e);
+        }
+        self
+    }
+
+    pub fn register<S>(self) -> Self
+    where
+        S: 'static + Stage<C = C> + StageName + Deserialize<'de>,
+    {
+        self.register_named::<S>(&registry_key::<S>())
+    }
+
+    pub fn build(self) -> Result<StageManager<C, V>, StageError> {
+        if let Some(e) = self.error {
+            return Err(StageError::Register(e));
+        }
+        self.manager
+            .validate()
+            .map_err(|names| StageError::UnknownStage { names })?;
+        Ok(self.manager)
+    }
+}
+
+impl<'de, C, V> Default for StageManagerBuilder<C, V>
+where
+    V: Deserialize<'de> + Deserializer<'de> + Clone,
+{
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use serde_yaml::Value;
+
+    use super::*;
+    use crate::test::{Add, CalcContext, Mul};
+
+    fn file() -> StageFile<Value> {
+        let yaml_str = r#"
+        stages:
+        - name: add
+          args:
+            x: 2
+        - name: mul
+          args:
+            x: 5
+        "#;
+        serde_yaml::from_str(yaml_str).unwrap()
+    }
+
+    #[test]
+    fn builder_validates_file() {
+        let m = StageManagerBuilder::new()
+            .register::<Add>()
+            .register::<Mul>()
+            .with_file(file())
+            .build()
+            .unwrap();
+
+        let mut c = CalcContext { x: 1 };
+        m.run_stages(&mut c).unwrap();
+        assert_eq!(c.x, 15);
+
+        let err = StageManagerBuilder::<CalcContext, Value>::new()
+            .with_file(file())
+            .register::<Add>()
+            .build()
+            .unwrap_err();
+        match err {
+            StageError::UnknownStage { names } => assert_eq!(names, vec!["mul"]),
+            other => panic!("expected UnknownStage, got {:?}", other),
+        }
+
+        let err = StageManagerBuilder::<CalcContext, Value>::new()
+            .register::<Add>()
+            .register::<Add>()
+            .build()
+            .unwrap_err();
+        match err {
+            StageError::Register(e) => assert_eq!(e, RegisterError::Duplicate("add".to_string())),
+            other => panic!("expected Register, got {:?}", other),
+        }
+    }
+}
@@ -0,0 +1,224 @@
+use serde::{Deserialize, Deserializer};
+use std::{
+    cell::RefCell,
+    collections::{hash_map::DefaultHasher, HashMap},
+    hash::{Hash, Hasher},
+};
+
+use crate::{ActiveStage, BoxedStage, StageArgs, StageError, StageManager};
+
+pub(crate) type CacheKey = (String, u64);
+
+type CachedStages<C> = HashMap<CacheKey, Vec<BoxedStage<C>>>;
+
+// Stage instances kept between runs, keyed by registry key and a hash of the
+// args they were built from. Several entries with the same args each get
+// their own instance.
+pub(crate) struct InstanceCache<C, V> {
+    hash: fn(&V) -> u64,
+    stages: RefCell<CachedStages<C>>,
+}
+
+fn hash_args<V: Hash>(args: &V) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    args.hash(&mut hasher);
+    hasher.finish()
+}
+
+impl<'de, C, V> StageManager<C, V>
+where
+    V: Deserialize<'de> + Deserializer<'de> + Clone + Hash,
+{
+    // Reuses stage instances across runs instead of deserializing them again
+    // when a stage's args are unchanged. Cached stages still have setup and
+    // teardown called on every run. Args are hashed after any preprocessing,
+    // and mappings hash in their written key order.
+    pub fn with_instance_cache(&mut self, enabled: bool) -> &mut Self {
+        self.instance_cache = if enabled {
+            Some(InstanceCache {
+                hash: hash_args::<V>,
+                stages: RefCell::new(HashMap::new()),
+            })
+        } else {
+            None
+        };
+        self
+    }
+}
+
+impl<C, V> StageManager<C, V> {
+    pub(crate) fn return_to_cache(&self, active: Vec<ActiveStage<C>>) {
+        if let Some(cache) = &self.instance_cache {
+            let mut stages = cache.stages.borrow_mut();
+            for entry in active {
+                if let Some(key) = entry.cache_key {
+                    stages.entry(key).or_default().push(entry.stage);
+                }
+            }
+        }
+    }
+}
+
+impl<'de, C, V> StageManager<C, V>
+where
+    V: Deserialize<'de> + Deserializer<'de> + Clone,
+{
+    pub(crate) fn cached_stage(
+        &self,
+        index: usize,
+        s: &StageArgs<V>,
+    ) -> Result<ActiveStage<C>, StageError> {
+        let args = self.stage_args(index, s)?;
+        let cache = match &self.instance_cache {
+            Some(cache) => cache,
+            None => {
+                return Ok(ActiveStage {
+                    stage: self.deserialize_stage(index, s, args)?,
+                    cache_key: None,
+                })
+            }
+        };
+
+        // Keyed on the registry key the name resolves to, so a newer version
+        // registered under the same base name isn't served stale instances.
+        let name = self.resolve(&s.name).unwrap_or(&s.name).to_string();
+        let key = (name, (cache.hash)(&args));
+        let cached = cache.stages.borrow_mut().get_mut(&key).and_then(Vec::pop);
+        let stage = match cached {
+            Some(stage) => stage,
+            None => self.deserialize_stage(index, s, args)?,
+        };
+        Ok(ActiveStage {
+            stage,
+            cache_key: Some(key),
+        })
+    }
+
+    // Drops cached instances built by the factory registered as `key`, once
+    // it has been replaced.
+    pub(crate) fn evict_cached(&mut self, key: &str) {
+        if let Some(cache) = &mut self.instance_cache {
+            cache.stages.get_mut().retain(|(n, _), _| n != key);
+        }
+    }
+
+    // Drops cached instances the fallback built, once it has been replaced.
+    pub(crate) fn evict_unregistered(&mut self) {
+        if let Some(cache) = &mut self.instance_cache {
+            let registered = &self.deserialize_map;
+            cache
+                .stages
+                .get_mut()
+                .retain(|(n, _), _| registered.contains_key(n));
+        }
+    }
+
+    pub(crate) fn clear_cached(&mut self) {
+        if let Some(cache) = &mut self.instance_cache {
+            cache.stages.get_mut().clear();
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use serde_yaml::Value;
+    use std::{cell::Cell, rc::Rc};
+
+    use crate::{
+        test::{Add, CalcContext, Mul},
+        StageFile, StageManager,
+    };
+
+    #[test]
+    fn cached_instances_are_reused() {
+        let yaml_str = r#"
+        stages:
+        - name: add
+          args:
+            x: 1
+        - name: add
+          args:
+            x: 2
+        - name: add
+          args:
+            x: 1
+        "#;
+
+        let file: StageFile<Value> = serde_yaml::from_str(yaml_str).unwrap();
+        let mut m = StageManager::from_file(file);
+        let calls = Rc::new(Cell::new(0));
+        let counter = calls.clone();
+        m.register_fn("add", move |v| {
+            counter.set(counter.get() + 1);
+            Ok(serde_yaml::from_value::<Add>(v)?)
+        })
+        .unwrap();
+        m.with_instance_cache(true);
+
+        let mut c = CalcContext { x: 0 };
+        m.run_stages(&mut c).unwrap();
+        assert_eq!(calls.get(), 3);
+        m.run_stages(&mut c).unwrap();
+        assert_eq!(calls.get(), 3);
+        assert_eq!(c.x, 8);
+
+        m.with_instance_cache(false);
+        m.run_stages(&mut c).unwrap();
+        assert_eq!(calls.get(), 6);
+    }
+
+    #[test]
+    fn newer_versions_are_not_served_cached_instances() {
+        let yaml_str = r#"
+        stages:
+        - name: add
+          args:
+            x: 3
+        "#;
+
+        let file: StageFile<Value> = serde_yaml::from_str(yaml_str).unwrap();
+        let mut m = StageManager::from_file(file);
+        m.register_named::<Add>("add@1").unwrap();
+        m.with_instance_cache(true);
+
+        let mut c = CalcContext { x: 1 };
+        m.run_stages(&mut c).unwrap();
+        assert_eq!(c.x, 4);
+
+        m.register_named_overwrite::<Mul>("add@2");
+        m.run_stages(&mut c).unwrap();
+        assert_eq!(c.x, 12);
+    }
+
+    #[test]
+    fn aliases_and_fallbacks_are_not_served_cached_instances() {
+        let yaml_str = r#"
+        stages:
+        - name: plus
+          args:
+            x: 3
+        - name: minus
+          args:
+            x: 1
+        "#;
+
+        let file: StageFile<Value> = serde_yaml::from_str(yaml_str).unwrap();
+        let mut m = StageManager::from_file(file);
+        m.register::<Mul>().unwrap();
+        m.register_default(|_, v| Ok(serde_yaml::from_value::<Add>(v)?));
+        m.with_instance_cache(true);
+
+        let mut c = CalcContext { x: 1 };
+        m.run_stages(&mut c).unwrap();
+        assert_eq!(c.x, 5);
+
+        m.alias("mul", "plus").unwrap();
+        m.run_stages(&mut c).unwrap();
+        assert_eq!(c.x, 16);
+
+        m.register_default(|_, v| Ok(serde_yaml::from_value::<Mul>(v)?));
+        m.run_stages(&mut c).unwrap();
+        assert_eq!(c.x, 48);
+    }
+}
@@ -0,0 +1,167 @@
+use serde::{Deserialize, Deserializer};
+use std::collections::HashSet;
+
+use crate::{RegisterError, StageManager};
+
+// The case StageManager::with_name_normalization brings stage names to before
+// comparing them, so `addOne`, `AddOne` and `add-one` all find `add_one`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NameCase {
+    Snake,
+    Kebab,
+}
+
+impl NameCase {
+    // Rewrites the name part of `name`, leaving any `@version` as is.
+    pub fn normalize(self, name: &str) -> String {
+        let (base, version) = match name.split_once('@') {
+            Some((base, version)) => (base, Some(version)),
+            None => (name, None),
+        };
+        let sep = match self {
+            NameCase::Snake => "_",
+            NameCase::Kebab => "-",
+        };
+        let mut out = words(base).join(sep);
+        if let Some(version) = version {
+            out.push('@');
+            out.push_str(version);
+        }
+        out
+    }
+}
+
+// Splits on `_`, `-` and spaces, and where the case changes, keeping runs of
+// capitals such as `HTTP` in `HTTPRequest` together.
+fn words(name: &str) -> Vec<String> {
+    let chars: Vec<char> = name.chars().collect();
+    let mut words = Vec::new();
+    let mut word = String::new();
+    for (i, &c) in chars.iter().enumerate() {
+        if matches!(c, '_' | '-' | ' ') {
+            if !word.is_empty() {
+                words.push(std::mem::take(&mut word));
+            }
+            continue;
+        }
+        if c.is_uppercase() && !word.is_empty() {
+            let prev = chars[i - 1];
+            let next_lower = chars.get(i + 1).is_some_and(|n| n.is_lowercase());
+            if prev.is_lowercase() || prev.is_numeric() || next_lower {
+                words.push(std::mem::take(&mut word));
+            }
+        }
+        word.extend(c.to_lowercase());
+    }
+    if !word.is_empty() {
+        words.push(word);
+    }
+    words
+}
+
+impl<'de, C, V> StageManager<C, V>
+where
+    V: Deserialize<'de> + Deserializer<'de> + Clone,
+{
+    // Matches stage names in the file against registered names after
+    // bringing both to `case`. Fails if two registered names already become
+    // the same, and from then on registering a name that normalizes to one
+    // already taken fails with RegisterError::Duplicate.
+    pub fn with_name_normalization(&mut self, case: NameCase) -> Result<&mut Self, RegisterError> {
+        let mut seen = HashSet::new();
+        for name in self.deserialize_map.keys() {
+            let normalized = case.normalize(name);
+            if !seen.insert(normalized.clone()) {
+                return Err(RegisterError::Duplicate(normalized));
+            }
+        }
+        self.name_case = Some(case);
+        self.prepared = None;
+        Ok(self)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use serde_yaml::Value;
+
+    use super::NameCase;
+    use crate::{
+        test::{Add, CalcContext, Mul},
+        RegisterError, StageFile, StageManager,
+    };
+
+    #[test]
+    fn normalizes_common_styles() {
+        for name in ["add_one", "addOne", "AddOne", "add-one", "ADD_ONE"] {
+            assert_eq!(NameCase::Snake.normalize(name), "add_one");
+        }
+        assert_eq!(NameCase::Kebab.normalize("HTTPRequest@2"), "http-request@2");
+    }
+
+    #[test]
+    fn config_names_resolve_after_normalization() {
+        let yaml_str = r#"
+        stages:
+        - name: AddOne
+          args:
+            x: 1
+        - name: addOne
+          args:
+            x: 2
+        "#;
+
+        let file: StageFile<Value> = serde_yaml::from_str(yaml_str).unwrap();
+        let mut m = StageManager::from_file(file);
+        m.register_named::<Add>("add_one").unwrap();
+        assert!(m.validate().is_err());
+
+        m.with_name_normalization(NameCase::Snake).unwrap();
+        let mut c = CalcContext { x: 1 };
+        m.run_stages(&mut c).unwrap();
+        assert_eq!(c.x, 4);
+
+        assert!(matches!(
+            m.register_named::<Add>("Add-One"),
+            Err(RegisterError::Duplicate(_))
+        ));
+    }
+
+    #[test]
+    fn colliding_registrations_are_rejected() {
+        let file: StageFile<Value> = serde_yaml::from_str("stages: []").unwrap();
+        let mut m = StageManager::<CalcContext, Value>::from_file(file);
+        m.register_named::<Add>("add_one")
+            .unwrap()
+            .register_named::<Add>("AddOne")
+            .unwrap();
+
+        assert!(matches!(
+            m.with_name_normalization(NameCase::Snake),
+            Err(RegisterError::Duplicate(name)) if name == "add_one"
+        ));
+    }
+
+    #[test]
+    fn overwrite_replaces_the_colliding_name() {
+        let yaml_str = r#"
+        stages:
+        - name: add_one
+          args:
+            x: 2
+        "#;
+
+        let file: StageFile<Value> = serde_yaml::from_str(yaml_str).unwrap();
+        let mut m = StageManager::<CalcContext, Value>::from_file(file);
+        m.with_name_normalization(NameCase::Snake)
+            .unwrap()
+            .register_named::<Add>("add_one")
+            .unwrap()
+            .register_named_overwrite::<Mul>("AddOne");
+
+        assert_eq!(m.registry().names().collect::<Vec<_>>(), ["AddOne"]);
+        let mut c = CalcContext { x: 5 };
+        m.run_stages(&mut c).unwrap();
+        assert_eq!(c.x, 10);
+    }
+}
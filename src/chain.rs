@@ -0,0 +1,84 @@
+use serde::{Deserialize, Deserializer};
+use std::{any::Any, fmt};
+
+use crate::{Stage, StageError, StageFlow, StageManager};
+
+// Two managers run one after the other as a single stage. Build one with
+// StageManager::then.
+pub struct ChainedManager<C, V> {
+    first: StageManager<C, V>,
+    second: StageManager<C, V>,
+}
+
+impl<'de, C, V> StageManager<C, V>
+where
+    V: Deserialize<'de> + Deserializer<'de> + Clone,
+{
+    // Runs this manager's stages and then `other`'s, each with its own file
+    // and registrations. The second doesn't run if the first fails.
+    pub fn then(self, other: StageManager<C, V>) -> ChainedManager<C, V> {
+        ChainedManager {
+            first: self,
+            second: other,
+        }
+    }
+}
+
+impl<'de, C, V> Stage for ChainedManager<C, V>
+where
+    V: Deserialize<'de> + Deserializer<'de> + Clone,
+{
+    type C = C;
+
+    fn run(&self, c: &mut Self::C) -> Result<(), StageError> {
+        self.first.run_stages(c)?;
+        self.second.run_stages(c)
+    }
+
+    fn run_shared(&self, c: &mut Self::C, shared: &dyn Any) -> Result<StageFlow, StageError> {
+        self.first.run_shared(c, shared)?;
+        self.second.run_shared(c, shared)
+    }
+
+    fn undo(&self, c: &mut Self::C) -> Result<(), StageError> {
+        self.second.undo_stages(c)?;
+        self.first.undo_stages(c)
+    }
+}
+
+impl<C, V: fmt::Debug> fmt::Debug for ChainedManager<C, V> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "ChainedManager{{first: {:?}, second: {:?}}}",
+            self.first, self.second
+        )
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use serde_yaml::Value;
+
+    use crate::{
+        test::{Add, CalcContext, Mul},
+        Stage, StageFile, StageManager,
+    };
+
+    #[test]
+    fn chained_managers_run_in_order() {
+        let adds: StageFile<Value> =
+            serde_yaml::from_str("[{name: add, args: {x: 1}}, {name: add, args: {x: 2}}]").unwrap();
+        let muls: StageFile<Value> = serde_yaml::from_str("[{name: mul, args: {x: 5}}]").unwrap();
+
+        let mut first = StageManager::from_file(adds);
+        first.register::<Add>().unwrap();
+        let mut second = StageManager::from_file(muls);
+        second.register::<Mul>().unwrap();
+        let chained = first.then(second);
+
+        let mut c = CalcContext { x: 1 };
+        chained.run(&mut c).unwrap();
+        assert_eq!(c.x, 20);
+    }
+}
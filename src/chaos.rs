@@ -0,0 +1,105 @@
+use serde::{Deserialize, Deserializer};
+
+use crate::StageManager;
+
+impl<'de, C, V> StageManager<C, V>
+where
+    V: Deserialize<'de> + Deserializer<'de> + Clone,
+{
+    // Turns on chaos mode: a stage with a `probability` only runs that
+    // fraction of the time, decided by an RNG seeded with `seed` at the start
+    // of every run so the same seed skips the same stages.
+    pub fn with_chaos_seed(&mut self, seed: u64) -> &mut Self {
+        self.chaos_seed = Some(seed);
+        self
+    }
+}
+
+// A splitmix64 generator; good enough to pick stages, and needs no
+// dependency.
+pub(crate) struct Chaos(u64);
+
+impl Chaos {
+    pub(crate) fn new(seed: u64) -> Self {
+        Chaos(seed)
+    }
+
+    fn next_f64(&mut self) -> f64 {
+        self.0 = self.0.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^= z >> 31;
+        (z >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    // Whether a stage with `probability` runs this time. Stages without one
+    // always run and don't advance the generator.
+    pub(crate) fn keep(&mut self, probability: Option<f64>) -> bool {
+        match probability {
+            Some(p) => self.next_f64() < p,
+            None => true,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use serde_yaml::Value;
+
+    use super::*;
+    use crate::{test::CalcContext, Stage, StageError, StageFile};
+
+    #[derive(Debug, Deserialize)]
+    struct Bit {
+        bit: u32,
+    }
+
+    impl Stage for Bit {
+        type C = CalcContext;
+
+        fn run(&self, c: &mut Self::C) -> Result<(), StageError> {
+            c.x |= 1 << self.bit;
+            Ok(())
+        }
+    }
+
+    fn run(seed: u64) -> i64 {
+        let yaml_str = r#"
+        stages:
+        - name: bit
+          args: {bit: 0}
+          probability: 0.5
+        - name: bit
+          args: {bit: 1}
+          probability: 0.5
+        - name: bit
+          args: {bit: 2}
+        - name: bit
+          args: {bit: 3}
+          probability: 0.5
+        - name: bit
+          args: {bit: 4}
+          probability: 0.0
+        - name: bit
+          args: {bit: 5}
+          probability: 0.5
+        "#;
+
+        let file: StageFile<Value> = serde_yaml::from_str(yaml_str).unwrap();
+        let mut m = StageManager::from_file(file);
+        m.register_named::<Bit>("bit").unwrap();
+        m.with_chaos_seed(seed);
+
+        let mut c = CalcContext { x: 0 };
+        m.run_stages(&mut c).unwrap();
+        c.x
+    }
+
+    #[test]
+    fn seeded_chaos_skips_the_same_stages() {
+        // Bits 2 and 4 always and never run; the rest are down to the seed.
+        assert_eq!(run(7), 0b10_0111);
+        assert_eq!(run(7), run(7));
+    }
+}
@@ -0,0 +1,216 @@
+use std::cmp::Reverse;
+
+use crate::{StageArgs, StageError, StageFile};
+
+impl<V> StageArgs<V> {
+    // The key other stages use in `depends_on`.
+    pub(crate) fn key(&self) -> &str {
+        self.id.as_deref().unwrap_or(&self.name)
+    }
+}
+
+impl<V> StageFile<V> {
+    // Every stage in an order that runs dependencies first. Among stages that
+    // are ready at the same time the highest `priority` goes first, then file
+    // order, so a file without `depends_on` or `priority` runs exactly as
+    // written. Depending on a key shared by several stages depends on all of
+    // them.
+    pub(crate) fn ordered_stages(&self) -> Result<Vec<(usize, &StageArgs<V>)>, StageError> {
+        if self.stages.iter().all(|s| s.depends_on.is_empty()) {
+            let mut order: Vec<_> = self.stages.iter().enumerate().collect();
+            order.sort_by_key(|(_, s)| Reverse(s.priority));
+            return Ok(order);
+        }
+
+        let mut deps = Vec::with_capacity(self.stages.len());
+        for s in &self.stages {
+            let mut indices = Vec::new();
+            for dep in &s.depends_on {
+                let len = indices.len();
+                indices.extend(
+                    self.stages
+                        .iter()
+                        .enumerate()
+                        .filter(|(_, other)| other.key() == dep)
+                        .map(|(i, _)| i),
+                );
+                if indices.len() == len {
+                    return Err(StageError::UnknownDependency {
+                        stage: s.key().to_string(),
+                        depends_on: dep.clone(),
+                    });
+                }
+            }
+            deps.push(indices);
+        }
+
+        let mut done = vec![false; self.stages.len()];
+        let mut order = Vec::with_capacity(self.stages.len());
+        while order.len() < self.stages.len() {
+            let ready = (0..self.stages.len())
+                .filter(|&i| !done[i] && deps[i].iter().all(|&d| done[d]))
+                .max_by_key(|&i| (self.stages[i].priority, Reverse(i)));
+            match ready {
+                Some(i) => {
+                    done[i] = true;
+                    order.push((i, &self.stages[i]));
+                }
+                None => {
+                    let stages = (0..self.stages.len())
+                        .filter(|&i| !done[i])
+                        .map(|i| self.stages[i].key().to_string())
+                        .collect();
+                    return Err(StageError::DependencyCycle { stages });
+                }
+            }
+        }
+        Ok(order)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use serde_yaml::Value;
+
+    use crate::{
+        test::{Add, CalcContext, Mul},
+        StageError, StageFile, StageManager,
+    };
+
+    fn manager(yaml_str: &str) -> StageManager<CalcContext, Value> {
+        let file: StageFile<Value> = serde_yaml::from_str(yaml_str).unwrap();
+        let mut m = StageManager::from_file(file);
+        m.register::<Add>().unwrap().register::<Mul>().unwrap();
+        m
+    }
+
+    #[test]
+    fn diamond_dependencies() {
+        // double runs last even though it is listed first, and start runs
+        // before both branches.
+        let yaml_str = r#"
+        stages:
+        - name: mul
+          id: double
+          depends_on: [left, right]
+          args:
+            x: 2
+        - name: add
+          id: left
+          depends_on: [start]
+          args:
+            x: 1
+        - name: mul
+          id: right
+          depends_on: [start]
+          args:
+            x: 3
+        - name: add
+          id: start
+          args:
+            x: 4
+        "#;
+
+        let m = manager(yaml_str);
+        let mut c = CalcContext { x: 1 };
+        m.run_stages(&mut c).unwrap();
+
+        // ((1 + 4) + 1) * 3 * 2
+        assert_eq!(c.x, 36);
+    }
+
+    #[test]
+    fn priority_reorders_stages() {
+        let yaml_str = r#"
+        stages:
+        - name: add
+          args:
+            x: 1
+        - name: mul
+          priority: 10
+          args:
+            x: 3
+        - name: add
+          priority: -1
+          args:
+            x: 4
+        - name: add
+          args:
+            x: 2
+        "#;
+
+        // mul, add 1, add 2, add 4
+        let m = manager(yaml_str);
+        let mut c = CalcContext { x: 1 };
+        m.run_stages(&mut c).unwrap();
+        assert_eq!(c.x, 10);
+
+        let yaml_str = r#"
+        stages:
+        - name: add
+          id: first
+          args:
+            x: 1
+        - name: mul
+          priority: 10
+          depends_on: [first]
+          args:
+            x: 3
+        - name: add
+          priority: 5
+          args:
+            x: 2
+        "#;
+
+        // add 2, add 1, mul 3
+        let m = manager(yaml_str);
+        let mut c = CalcContext { x: 1 };
+        m.run_stages(&mut c).unwrap();
+        assert_eq!(c.x, 12);
+    }
+
+    #[test]
+    fn dependency_cycle() {
+        let yaml_str = r#"
+        stages:
+        - name: add
+          args:
+            x: 1
+        - name: mul
+          id: a
+          depends_on: [b]
+          args:
+            x: 2
+        - name: mul
+          id: b
+          depends_on: [a]
+          args:
+            x: 3
+        "#;
+
+        let m = manager(yaml_str);
+        let mut c = CalcContext { x: 1 };
+        match m.run_stages(&mut c) {
+            Err(StageError::DependencyCycle { stages }) => assert_eq!(stages, vec!["a", "b"]),
+            other => panic!("expected DependencyCycle, got {:?}", other),
+        }
+        assert_eq!(c.x, 1);
+
+        let m = manager(
+            r#"
+        stages:
+        - name: add
+          depends_on: [missing]
+          args:
+            x: 1
+        "#,
+        );
+        match m.run_stages(&mut c) {
+            Err(StageError::UnknownDependency { stage, depends_on }) => {
+                assert_eq!(stage, "add");
+                assert_eq!(depends_on, "missing");
+            }
+            other => panic!("expected UnknownDependency, got {:?}", other),
+        }
+    }
+}
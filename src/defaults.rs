@@ -0,0 +1,75 @@
+use serde::de::DeserializeOwned;
+use std::rc::Rc;
+
+use crate::{RegisterError, Stage, StageManager, YamlValue};
+
+impl<C> StageManager<C, YamlValue> {
+    // Registers `S` under `name` with `defaults` merged beneath each entry's
+    // args before it is deserialized. Args from the file win, and nested
+    // mappings are merged key by key.
+    pub fn register_with_defaults<S>(
+        &mut self,
+        name: &str,
+        defaults: YamlValue,
+    ) -> Result<&mut Self, RegisterError>
+    where
+        S: 'static + Stage<C = C> + DeserializeOwned,
+    {
+        if self.is_taken(name) {
+            return Err(RegisterError::Duplicate(name.to_string()));
+        }
+        Ok(self.insert_factory::<S>(
+            name,
+            Rc::new(move |_, v| {
+                let args = merge(defaults.clone(), v);
+                let stage = S::deserialize(args).map_err(|e| e.to_string())?;
+                Ok(Box::new(stage))
+            }),
+        ))
+    }
+}
+
+// Lays `over` on top of `under`.
+fn merge(under: YamlValue, over: YamlValue) -> YamlValue {
+    match (under, over) {
+        (YamlValue::Mapping(mut under), YamlValue::Mapping(over)) => {
+            for (k, v) in over {
+                let merged = match under.remove(&k) {
+                    Some(u) => merge(u, v),
+                    None => v,
+                };
+                under.insert(k, merged);
+            }
+            YamlValue::Mapping(under)
+        }
+        (under, YamlValue::Null) => under,
+        (_, over) => over,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{
+        test::{Add, CalcContext},
+        StageManager,
+    };
+
+    #[test]
+    fn file_args_override_defaults() {
+        let yaml_str = r#"
+        stages:
+        - name: add
+        - name: add
+          args:
+            x: 1
+        "#;
+
+        let mut m = StageManager::<CalcContext, _>::from_yaml_str(yaml_str).unwrap();
+        let defaults = serde_yaml::from_str("x: 100").unwrap();
+        m.register_with_defaults::<Add>("add", defaults).unwrap();
+
+        let mut c = CalcContext { x: 0 };
+        m.run_stages(&mut c).unwrap();
+        assert_eq!(c.x, 101);
+    }
+}
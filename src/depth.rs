@@ -0,0 +1,118 @@
+use serde::{Deserialize, Deserializer};
+use std::cell::Cell;
+
+use crate::{StageError, StageManager};
+
+thread_local! {
+    // How many managers are running on this thread, and the tightest
+    // max_depth any of them set.
+    static DEPTH: Cell<usize> = const { Cell::new(0) };
+    static LIMIT: Cell<Option<usize>> = const { Cell::new(None) };
+}
+
+impl<'de, C, V> StageManager<C, V>
+where
+    V: Deserialize<'de> + Deserializer<'de> + Clone,
+{
+    // Fails with StageError::MaxDepthExceeded once more than `max_depth`
+    // managers are running inside one another, counting this one. Managers
+    // nested inside this one inherit the limit.
+    pub fn with_max_depth(&mut self, max_depth: usize) -> &mut Self {
+        self.max_depth = Some(max_depth);
+        self
+    }
+}
+
+// Restores the enclosing manager's depth and limit when dropped.
+pub(crate) struct DepthScope {
+    limit: Option<usize>,
+}
+
+impl Drop for DepthScope {
+    fn drop(&mut self) {
+        DEPTH.with(|depth| depth.set(depth.get() - 1));
+        LIMIT.with(|limit| limit.set(self.limit));
+    }
+}
+
+pub(crate) fn enter(max_depth: Option<usize>) -> Result<DepthScope, StageError> {
+    let outer = LIMIT.with(Cell::get);
+    let limit = match (outer, max_depth) {
+        (Some(outer), Some(max_depth)) => Some(outer.min(max_depth)),
+        (outer, max_depth) => outer.or(max_depth),
+    };
+    let depth = DEPTH.with(|depth| depth.get()) + 1;
+    if let Some(max_depth) = limit.filter(|&max_depth| depth > max_depth) {
+        return Err(StageError::MaxDepthExceeded { max_depth });
+    }
+
+    DEPTH.with(|d| d.set(depth));
+    LIMIT.with(|l| l.set(limit));
+    Ok(DepthScope { limit: outer })
+}
+
+#[cfg(test)]
+mod test {
+    use serde_yaml::Value;
+
+    use crate::{
+        test::{Add, CalcContext},
+        Stage, StageError, StageFile, StageManager, StageName,
+    };
+
+    #[derive(serde::Deserialize)]
+    struct Nest {
+        #[serde(flatten)]
+        stages: StageManager<CalcContext, Value>,
+    }
+
+    impl Stage for Nest {
+        type C = CalcContext;
+
+        fn setup(&mut self) {
+            self.stages
+                .register::<Add>()
+                .unwrap()
+                .register::<Nest>()
+                .unwrap();
+        }
+
+        fn run(&self, c: &mut Self::C) -> Result<(), StageError> {
+            self.stages.run(c)
+        }
+    }
+
+    impl StageName for Nest {
+        fn stage_name() -> &'static str {
+            "nest"
+        }
+    }
+
+    fn nested(levels: usize) -> StageManager<CalcContext, Value> {
+        let mut yaml_str = "stages: [{name: add, args: {x: 1}}]".to_string();
+        for _ in 0..levels {
+            yaml_str = format!("stages: [{{name: nest, args: {{{}}}}}]", yaml_str);
+        }
+        let file: StageFile<Value> = serde_yaml::from_str(&yaml_str).unwrap();
+        let mut m = StageManager::from_file(file);
+        m.register::<Add>().unwrap().register::<Nest>().unwrap();
+        m
+    }
+
+    #[test]
+    fn nesting_past_max_depth_fails() {
+        let mut m = nested(6);
+        m.with_max_depth(3);
+
+        let mut c = CalcContext { x: 0 };
+        match m.run_stages(&mut c) {
+            Err(StageError::MaxDepthExceeded { max_depth }) => assert_eq!(max_depth, 3),
+            other => panic!("unexpected result: {:?}", other),
+        }
+        assert_eq!(c.x, 0);
+
+        m.with_max_depth(7);
+        m.run_stages(&mut c).unwrap();
+        assert_eq!(c.x, 1);
+    }
+}
@@ -0,0 +1,145 @@
+use serde::{Deserialize, Deserializer};
+use std::{collections::HashSet, iter::FromIterator};
+
+use crate::{StageArgs, StageError, StageManager};
+
+// The features active for a run, checked against each stage's `requires`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct FeatureSet {
+    active: HashSet<String>,
+}
+
+impl FeatureSet {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with(mut self, feature: &str) -> Self {
+        self.insert(feature);
+        self
+    }
+
+    pub fn insert(&mut self, feature: &str) {
+        self.active.insert(feature.to_string());
+    }
+
+    pub fn contains(&self, feature: &str) -> bool {
+        self.active.contains(feature)
+    }
+}
+
+impl<'a> FromIterator<&'a str> for FeatureSet {
+    fn from_iter<I: IntoIterator<Item = &'a str>>(iter: I) -> Self {
+        Self {
+            active: iter.into_iter().map(str::to_string).collect(),
+        }
+    }
+}
+
+// A stage run_stages_with_features left out, and why.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Skipped {
+    pub index: usize,
+    pub name: String,
+    pub reason: String,
+}
+
+impl<'de, C, V> StageManager<C, V>
+where
+    V: Deserialize<'de> + Deserializer<'de> + Clone,
+{
+    // Runs the pipeline, skipping stages that require a feature not in
+    // `features`. Unlike a TagFilter this never selects stages; it only rules
+    // out the ones that can't run here. Each skip is also reported to
+    // Observer::on_stage_skipped_because with its reason.
+    pub fn run_stages_with_features(
+        &self,
+        context: &mut C,
+        features: &FeatureSet,
+    ) -> Result<Vec<Skipped>, StageError> {
+        let mut skipped = Vec::new();
+        let select = |index, s: &StageArgs<V>| {
+            let missing: Vec<&str> = s
+                .requires
+                .iter()
+                .map(String::as_str)
+                .filter(|f| !features.contains(f))
+                .collect();
+            if missing.is_empty() {
+                return Ok(());
+            }
+            let reason = format!("missing features: {}", missing.join(", "));
+            skipped.push(Skipped {
+                index,
+                name: s.name.clone(),
+                reason: reason.clone(),
+            });
+            Err(reason)
+        };
+        self.run_with(context, &(), select, &mut ())?;
+        Ok(skipped)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use serde_yaml::Value;
+    use std::{cell::RefCell, rc::Rc};
+
+    use super::{FeatureSet, Skipped};
+    use crate::{
+        test::{Add, CalcContext, Mul},
+        Observer, StageFile, StageManager,
+    };
+
+    struct Reasons(Rc<RefCell<Vec<(usize, String)>>>);
+
+    impl Observer for Reasons {
+        fn on_stage_skipped_because(&self, _name: &str, index: usize, reason: &str) {
+            self.0.borrow_mut().push((index, reason.to_string()));
+        }
+    }
+
+    #[test]
+    fn stages_missing_features_are_skipped() {
+        let yaml_str = r#"
+        stages:
+        - name: add
+          requires: [fast]
+          args:
+            x: 2
+        - name: mul
+          requires: [fast, gpu]
+          args:
+            x: 3
+        - name: add
+          args:
+            x: 5
+        "#;
+
+        let file: StageFile<Value> = serde_yaml::from_str(yaml_str).unwrap();
+        let mut m = StageManager::from_file(file);
+        let reasons = Rc::new(RefCell::new(Vec::new()));
+        m.register::<Mul>().unwrap().register::<Add>().unwrap();
+        m.with_observer(Box::new(Reasons(reasons.clone())));
+
+        let mut c = CalcContext { x: 1 };
+        let skipped = m
+            .run_stages_with_features(&mut c, &FeatureSet::new().with("fast"))
+            .unwrap();
+
+        assert_eq!(c.x, 8);
+        assert_eq!(
+            skipped,
+            [Skipped {
+                index: 1,
+                name: "mul".to_string(),
+                reason: "missing features: gpu".to_string(),
+            }]
+        );
+        assert_eq!(
+            *reasons.borrow(),
+            [(1, "missing features: gpu".to_string())]
+        );
+    }
+}
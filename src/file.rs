@@ -0,0 +1,147 @@
+use serde::{
+    de::{self, IgnoredAny, MapAccess, SeqAccess, Visitor},
+    Deserialize, Deserializer,
+};
+use std::{fmt, marker::PhantomData};
+
+use crate::{StageArgs, StageFile};
+
+// Accepts either `stages: [...]` or a bare sequence of stages at the root,
+// which is what most config generators emit.
+impl<'de, V> Deserialize<'de> for StageFile<V>
+where
+    V: Deserialize<'de>,
+{
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        deserializer.deserialize_any(StageFileVisitor(PhantomData))
+    }
+}
+
+struct StageFileVisitor<V>(PhantomData<V>);
+
+impl<'de, V> Visitor<'de> for StageFileVisitor<V>
+where
+    V: Deserialize<'de>,
+{
+    type Value = StageFile<V>;
+
+    fn expecting(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("a map with a `stages` key or a sequence of stages")
+    }
+
+    fn visit_seq<A>(self, mut seq: A) -> Result<Self::Value, A::Error>
+    where
+        A: SeqAccess<'de>,
+    {
+        let mut stages = Vec::with_capacity(seq.size_hint().unwrap_or(0));
+        while let Some(stage) = seq.next_element::<StageArgs<V>>()? {
+            stages.push(stage);
+        }
+        Ok(StageFile { version: 0, stages })
+    }
+
+    fn visit_map<A>(self, mut map: A) -> Result<Self::Value, A::Error>
+    where
+        A: MapAccess<'de>,
+    {
+        let mut stages = None;
+        let mut version = None;
+        while let Some(key) = map.next_key::<String>()? {
+            match key.as_str() {
+                "stages" if stages.is_some() => {
+                    return Err(de::Error::duplicate_field("stages"));
+                }
+                "stages" => stages = Some(map.next_value()?),
+                "version" if version.is_some() => {
+                    return Err(de::Error::duplicate_field("version"));
+                }
+                "version" => version = Some(map.next_value()?),
+                _ => {
+                    map.next_value::<IgnoredAny>()?;
+                }
+            }
+        }
+        let stages = stages.ok_or_else(|| de::Error::missing_field("stages"))?;
+        Ok(StageFile {
+            version: version.unwrap_or(0),
+            stages,
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use serde_yaml::Value;
+
+    use crate::{
+        test::{Add, CalcContext, Mul},
+        StageError, StageFile, StageManager,
+    };
+
+    #[test]
+    fn wrapped_and_bare_files_match() {
+        let wrapped: StageFile<Value> = serde_yaml::from_str(
+            r#"
+            stages:
+            - name: add
+              args:
+                x: 2
+            - name: mul
+              args:
+                x: 3
+            "#,
+        )
+        .unwrap();
+        let bare: StageFile<Value> = serde_yaml::from_str(
+            r#"
+            - name: add
+              args:
+                x: 2
+            - name: mul
+              args:
+                x: 3
+            "#,
+        )
+        .unwrap();
+        assert_eq!(format!("{:?}", wrapped), format!("{:?}", bare));
+
+        let mut m = StageManager::from_file(bare);
+        m.register::<Add>().unwrap().register::<Mul>().unwrap();
+
+        let mut c = CalcContext { x: 1 };
+        m.run_stages(&mut c).unwrap();
+        assert_eq!(c.x, 9);
+    }
+
+    #[test]
+    fn missing_stages_key_is_an_error() {
+        let err = serde_yaml::from_str::<StageFile<Value>>("steps: []").unwrap_err();
+        assert!(err.to_string().contains("missing field `stages`"));
+    }
+
+    #[test]
+    fn newer_file_versions_are_rejected() {
+        let yaml_str = r#"
+        version: 99
+        stages:
+        - name: add
+          args:
+            x: 2
+        "#;
+
+        let file: StageFile<Value> = serde_yaml::from_str(yaml_str).unwrap();
+        let mut m = StageManager::from_file(file);
+        m.register::<Add>().unwrap();
+
+        let mut c = CalcContext { x: 1 };
+        let err = m.run_stages(&mut c).unwrap_err();
+        assert!(matches!(
+            err,
+            StageError::UnsupportedFileVersion { version: 99 }
+        ));
+        assert_eq!(c.x, 1);
+    }
+}
@@ -0,0 +1,80 @@
+use std::collections::HashSet;
+
+use crate::{StageError, StageFile};
+
+impl<V> StageFile<V> {
+    // Fails if two enabled stages share a group that any of its stages marks
+    // `exclusive`.
+    pub(crate) fn check_exclusive(&self) -> Result<(), StageError> {
+        let exclusive: HashSet<&str> = self
+            .stages
+            .iter()
+            .filter(|s| s.exclusive)
+            .filter_map(|s| s.group.as_deref())
+            .collect();
+
+        let mut enabled = HashSet::new();
+        for s in &self.stages {
+            let group = match s.group.as_deref() {
+                Some(group) if exclusive.contains(group) => group,
+                _ => continue,
+            };
+            if s.enabled && s.repeat > 0 && !enabled.insert(group) {
+                return Err(StageError::ExclusiveConflict {
+                    group: group.to_string(),
+                });
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use serde_yaml::Value;
+
+    use crate::{
+        test::{Add, CalcContext},
+        StageError, StageFile, StageManager,
+    };
+
+    fn run(yaml_str: &str) -> Result<(), StageError> {
+        let file: StageFile<Value> = serde_yaml::from_str(yaml_str).unwrap();
+        let mut m = StageManager::from_file(file);
+        m.register::<Add>().unwrap();
+
+        let mut c = CalcContext { x: 1 };
+        m.run_stages(&mut c)
+    }
+
+    #[test]
+    fn one_stage_per_exclusive_group() {
+        let conflict = r#"
+        stages:
+        - name: add
+          args: {x: 1}
+          group: output
+          exclusive: true
+        - name: add
+          args: {x: 2}
+          group: output
+        "#;
+        match run(conflict) {
+            Err(StageError::ExclusiveConflict { group }) => assert_eq!(group, "output"),
+            other => panic!("unexpected result: {:?}", other),
+        }
+
+        let disabled = r#"
+        stages:
+        - name: add
+          args: {x: 1}
+          group: output
+          exclusive: true
+        - name: add
+          args: {x: 2}
+          group: output
+          enabled: false
+        "#;
+        run(disabled).unwrap();
+    }
+}
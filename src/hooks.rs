@@ -0,0 +1,180 @@
+use serde::{Deserialize, Deserializer};
+use std::{cell::RefCell, rc::Rc};
+
+use crate::{StageError, StageFlow, StageManager};
+
+type FnBeforeEach = dyn Fn(&str, usize);
+type FnAfterEach = dyn Fn(&str, usize, &Result<StageFlow, StageError>);
+
+#[derive(Clone, Default)]
+pub(crate) struct EachHooks {
+    before: Option<Rc<FnBeforeEach>>,
+    after: Option<Rc<FnAfterEach>>,
+}
+
+thread_local! {
+    // Hooks of every manager currently running on this thread, outermost
+    // first, so nested managers run their parents' hooks too.
+    static ACTIVE: RefCell<Vec<EachHooks>> = const { RefCell::new(Vec::new()) };
+}
+
+impl<'de, C, V> StageManager<C, V>
+where
+    V: Deserialize<'de> + Deserializer<'de> + Clone,
+{
+    // Called with the stage's name and index before every stage runs,
+    // including the stages of managers nested inside this one.
+    pub fn before_each(&mut self, hook: Box<FnBeforeEach>) -> &mut Self {
+        self.hooks.before = Some(Rc::from(hook));
+        self
+    }
+
+    // Called with each stage's result after it runs. Hooks of nested managers
+    // run before their parents'.
+    pub fn after_each(&mut self, hook: Box<FnAfterEach>) -> &mut Self {
+        self.hooks.after = Some(Rc::from(hook));
+        self
+    }
+}
+
+// Keeps a manager's hooks active until dropped.
+pub(crate) struct HookScope(bool);
+
+impl Drop for HookScope {
+    fn drop(&mut self) {
+        if self.0 {
+            ACTIVE.with(|active| active.borrow_mut().pop());
+        }
+    }
+}
+
+impl EachHooks {
+    pub(crate) fn enter(&self) -> HookScope {
+        let set = self.before.is_some() || self.after.is_some();
+        if set {
+            ACTIVE.with(|active| active.borrow_mut().push(self.clone()));
+        }
+        HookScope(set)
+    }
+}
+
+// The active hooks are cloned out before calling them so a hook may itself
+// run a pipeline.
+fn active() -> Vec<EachHooks> {
+    ACTIVE.with(|active| active.borrow().clone())
+}
+
+pub(crate) fn before(name: &str, index: usize) {
+    for hooks in active() {
+        if let Some(before) = &hooks.before {
+            before(name, index);
+        }
+    }
+}
+
+pub(crate) fn after(name: &str, index: usize, result: &Result<StageFlow, StageError>) {
+    for hooks in active().iter().rev() {
+        if let Some(after) = &hooks.after {
+            after(name, index, result);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use serde::Deserialize;
+    use serde_yaml::Value;
+    use std::{cell::RefCell, rc::Rc};
+
+    use crate::{
+        test::{Add, CalcContext, Mul},
+        Stage, StageError, StageFile, StageManager, StageName,
+    };
+
+    #[derive(Deserialize)]
+    struct Nested {
+        #[serde(flatten)]
+        stages: StageManager<CalcContext, Value>,
+    }
+
+    impl StageName for Nested {
+        fn stage_name() -> &'static str {
+            "nested"
+        }
+    }
+
+    impl Stage for Nested {
+        type C = CalcContext;
+
+        fn setup(&mut self) {
+            self.stages.register::<Add>().unwrap();
+        }
+
+        fn run(&self, c: &mut Self::C) -> Result<(), StageError> {
+            self.stages.run_stages(c)
+        }
+    }
+
+    #[test]
+    fn hooks_wrap_every_stage() {
+        let yaml_str = r#"
+        stages:
+        - name: add
+          args:
+            x: 1
+        - name: nested
+          args:
+            stages:
+            - name: add
+              args:
+                x: 2
+        - name: mul
+          args:
+            x: 0
+        "#;
+
+        let file: StageFile<Value> = serde_yaml::from_str(yaml_str).unwrap();
+        let mut m = StageManager::from_file(file);
+        let events = Rc::new(RefCell::new(Vec::new()));
+        let (before, after) = (events.clone(), events.clone());
+        m.register::<Add>()
+            .unwrap()
+            .register::<Mul>()
+            .unwrap()
+            .register::<Nested>()
+            .unwrap()
+            .before_each(Box::new(move |name, index| {
+                before
+                    .borrow_mut()
+                    .push(format!("before {} {}", name, index))
+            }))
+            .after_each(Box::new(move |name, index, result| {
+                let ok = result.is_ok();
+                after
+                    .borrow_mut()
+                    .push(format!("after {} {} {}", name, index, ok))
+            }));
+
+        let mut c = CalcContext { x: 1 };
+        m.run_stages(&mut c).unwrap();
+
+        assert_eq!(
+            *events.borrow(),
+            vec![
+                "before add 0",
+                "after add 0 true",
+                "before nested 1",
+                "before add 0",
+                "after add 0 true",
+                "after nested 1 true",
+                "before mul 2",
+                "after mul 2 true",
+            ]
+        );
+
+        events.borrow_mut().clear();
+        let mut c = CalcContext { x: 1 };
+        m.run_stages(&mut c).unwrap();
+        assert_eq!(events.borrow().len(), 8);
+    }
+}
@@ -0,0 +1,176 @@
+use serde::Deserialize;
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use crate::{StageError, StageFile, StageManager, YamlValue};
+
+// Stage entries with this name are replaced by the stages of the file at
+// `args.path`, resolved relative to the including file.
+const INCLUDE: &str = "include";
+
+#[derive(Deserialize)]
+struct IncludeArgs {
+    path: PathBuf,
+}
+
+impl<C> StageManager<C, YamlValue> {
+    // Loads a stage file, splicing in any `include` entries. Includes are only
+    // resolved at the top level of each file, not inside nested managers.
+    pub fn from_yaml_path<P: AsRef<Path>>(path: P) -> Result<Self, StageError> {
+        let file = load(path.as_ref(), &mut Vec::new())?;
+        Ok(Self::from_file(file))
+    }
+}
+
+fn load(path: &Path, stack: &mut Vec<PathBuf>) -> Result<StageFile<YamlValue>, StageError> {
+    let include_err = |source| StageError::Include {
+        path: path.to_path_buf(),
+        source,
+    };
+
+    let path = path.canonicalize().map_err(|e| include_err(e.into()))?;
+    if stack.contains(&path) {
+        return Err(StageError::IncludeCycle { path });
+    }
+
+    let text = fs::read_to_string(&path).map_err(|e| include_err(e.into()))?;
+    let file: StageFile<YamlValue> =
+        serde_yaml::from_str(&text).map_err(|e| include_err(e.into()))?;
+    // Every file is held to the version check, not only the one the spliced
+    // result takes its version from.
+    file.check_version().map_err(|e| include_err(e.into()))?;
+
+    stack.push(path.clone());
+    let dir = path.parent().unwrap_or_else(|| Path::new(""));
+    let mut stages = Vec::with_capacity(file.stages.len());
+    for s in file.stages {
+        if s.name != INCLUDE {
+            stages.push(s);
+            continue;
+        }
+        if !s.enabled {
+            continue;
+        }
+
+        let args = IncludeArgs::deserialize(s.args.unwrap_or(YamlValue::Null))
+            .map_err(|e| include_err(e.into()))?;
+        stages.extend(load(&dir.join(args.path), stack)?.stages);
+    }
+    stack.pop();
+
+    Ok(StageFile {
+        version: file.version,
+        stages,
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use std::env;
+
+    use super::*;
+    use crate::{
+        test::{Add, CalcContext, Mul},
+        Stage,
+    };
+
+    fn write_files(test: &str, files: &[(&str, &str)]) -> PathBuf {
+        let dir = env::temp_dir().join(format!("stage_fright_{}_{}", test, std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        for (name, contents) in files {
+            fs::write(dir.join(name), contents).unwrap();
+        }
+        dir
+    }
+
+    #[test]
+    fn include_splices_stages() {
+        let dir = write_files(
+            "include",
+            &[
+                (
+                    "main.yaml",
+                    "stages:\n- name: add\n  args: {x: 1}\n- name: include\n  args: {path: sub.yaml}\n- name: mul\n  args: {x: 2}\n",
+                ),
+                (
+                    "sub.yaml",
+                    "stages:\n- name: add\n  args: {x: 2}\n- name: add\n  args: {x: 3}\n",
+                ),
+            ],
+        );
+
+        let mut m = StageManager::from_yaml_path(dir.join("main.yaml")).unwrap();
+        m.register::<Add>().unwrap().register::<Mul>().unwrap();
+
+        let mut c = CalcContext { x: 1 };
+        m.run(&mut c).unwrap();
+
+        assert_eq!(c.x, 14);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn include_cycle_is_rejected() {
+        let dir = write_files(
+            "include_cycle",
+            &[
+                (
+                    "a.yaml",
+                    "stages:\n- name: include\n  args: {path: b.yaml}\n",
+                ),
+                (
+                    "b.yaml",
+                    "stages:\n- name: include\n  args: {path: a.yaml}\n",
+                ),
+            ],
+        );
+
+        let err =
+            StageManager::<CalcContext, YamlValue>::from_yaml_path(dir.join("a.yaml")).unwrap_err();
+        match err {
+            StageError::IncludeCycle { path } => {
+                assert_eq!(path, dir.join("a.yaml").canonicalize().unwrap())
+            }
+            e => panic!("unexpected error: {}", e),
+        }
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn included_file_version_is_checked() {
+        let dir = write_files(
+            "include_version",
+            &[
+                (
+                    "main.yaml",
+                    "version: 1
+stages:
+- name: include
+  args: {path: sub.yaml}
+",
+                ),
+                (
+                    "sub.yaml",
+                    "version: 2
+stages:
+- name: add
+  args: {x: 2}
+",
+                ),
+            ],
+        );
+
+        let err = StageManager::<CalcContext, YamlValue>::from_yaml_path(dir.join("main.yaml"))
+            .unwrap_err();
+        match err {
+            StageError::Include { path, source } => {
+                assert_eq!(path, dir.join("sub.yaml"));
+                assert!(source.to_string().contains("version 2"));
+            }
+            e => panic!("unexpected error: {}", e),
+        }
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
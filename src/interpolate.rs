@@ -0,0 +1,157 @@
+use std::{env, rc::Rc};
+
+use crate::{StageError, StageManager, YamlValue};
+
+impl<C> StageManager<C, YamlValue> {
+    // Substitutes `${VAR}` and `${VAR:-default}` in string args from the
+    // environment before each stage is deserialized. As in the shell, the
+    // default also replaces a variable that is set but empty. A string that
+    // is exactly one reference is re-parsed so `x: ${X}` can produce a
+    // number, boolean or null; anything else stays a string.
+    pub fn with_env_interpolation(&mut self, enabled: bool) -> &mut Self {
+        self.preprocess = if enabled {
+            Some(Rc::new(|v| interpolate(v, &|var| env::var(var).ok())))
+        } else {
+            None
+        };
+        self
+    }
+}
+
+fn interpolate(
+    value: YamlValue,
+    lookup: &dyn Fn(&str) -> Option<String>,
+) -> Result<YamlValue, StageError> {
+    Ok(match value {
+        YamlValue::String(s) => interpolate_str(&s, lookup)?,
+        YamlValue::Sequence(seq) => YamlValue::Sequence(
+            seq.into_iter()
+                .map(|v| interpolate(v, lookup))
+                .collect::<Result<_, _>>()?,
+        ),
+        YamlValue::Mapping(map) => YamlValue::Mapping(
+            map.into_iter()
+                .map(|(k, v)| Ok((k, interpolate(v, lookup)?)))
+                .collect::<Result<_, StageError>>()?,
+        ),
+        v => v,
+    })
+}
+
+fn interpolate_str(
+    s: &str,
+    lookup: &dyn Fn(&str) -> Option<String>,
+) -> Result<YamlValue, StageError> {
+    let mut out = String::with_capacity(s.len());
+    let mut rest = s;
+    let mut references = 0;
+    while let Some(start) = rest.find("${") {
+        let end = match rest[start..].find('}') {
+            Some(end) => start + end,
+            None => break,
+        };
+
+        let reference = &rest[start + 2..end];
+        let (var, default) = match reference.find(":-") {
+            Some(i) => (&reference[..i], Some(&reference[i + 2..])),
+            None => (reference, None),
+        };
+        let value = match (lookup(var), default) {
+            (Some(value), Some(default)) if value.is_empty() => default.to_string(),
+            (Some(value), _) => value,
+            (None, Some(default)) => default.to_string(),
+            (None, None) => {
+                return Err(StageError::UnsetEnv {
+                    var: var.to_string(),
+                })
+            }
+        };
+
+        out.push_str(&rest[..start]);
+        out.push_str(&value);
+        rest = &rest[end + 1..];
+        references += 1;
+    }
+    out.push_str(rest);
+
+    let whole = references == 1 && s.starts_with("${") && s.ends_with('}');
+    if whole {
+        let parsed = serde_yaml::from_str(&out);
+        if let Ok(value @ (YamlValue::Number(_) | YamlValue::Bool(_) | YamlValue::Null)) = parsed {
+            return Ok(value);
+        }
+    }
+    Ok(YamlValue::String(out))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        test::{Add, CalcContext},
+        Stage,
+    };
+
+    fn vars(var: &str) -> Option<String> {
+        match var {
+            "HOST" => Some("example.com".to_string()),
+            "PORT" => Some("8080".to_string()),
+            "EMPTY" => Some(String::new()),
+            "LIST" => Some("[1, 2]".to_string()),
+            "FLAG" => Some("true".to_string()),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn interpolates_references() {
+        let s = |s: &str| interpolate_str(s, &vars).unwrap();
+
+        assert_eq!(s("${PORT}"), YamlValue::from(8080));
+        assert_eq!(
+            s("http://${HOST}:${PORT}/"),
+            YamlValue::from("http://example.com:8080/")
+        );
+        assert_eq!(s("${MISSING:-fallback}"), YamlValue::from("fallback"));
+        assert_eq!(s("${EMPTY:-fallback}"), YamlValue::from("fallback"));
+        assert_eq!(s("${FLAG}"), YamlValue::from(true));
+        assert_eq!(s("${LIST}"), YamlValue::from("[1, 2]"));
+        assert_eq!(s("[${EMPTY}]"), YamlValue::from("[]"));
+        assert_eq!(
+            s("no refs ${unterminated"),
+            YamlValue::from("no refs ${unterminated")
+        );
+        assert!(matches!(
+            interpolate_str("${MISSING}", &vars),
+            Err(StageError::UnsetEnv { var }) if var == "MISSING"
+        ));
+    }
+
+    #[test]
+    fn env_interpolation_feeds_stage_args() {
+        env::set_var("STAGE_FRIGHT_TEST_X", "41");
+        let yaml_str = r#"
+        stages:
+        - name: add
+          args:
+            x: ${STAGE_FRIGHT_TEST_X}
+        - name: add
+          args:
+            x: ${STAGE_FRIGHT_TEST_UNSET:-1}
+        "#;
+
+        let mut m = StageManager::from_yaml_str(yaml_str).unwrap();
+        m.register::<Add>().unwrap().with_env_interpolation(true);
+
+        let mut c = CalcContext { x: 0 };
+        m.run(&mut c).unwrap();
+        assert_eq!(c.x, 42);
+
+        let mut m = StageManager::from_yaml_str(
+            "stages: [{name: add, args: {x: '${STAGE_FRIGHT_TEST_UNSET}'}}]",
+        )
+        .unwrap();
+        m.register::<Add>().unwrap().with_env_interpolation(true);
+        assert!(matches!(m.run(&mut c), Err(StageError::UnsetEnv { .. })));
+    }
+}
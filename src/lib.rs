@@ -1,183 +1,4319 @@
-use serde::{Deserialize, Deserializer};
-use std::{collections::HashMap, fmt::Debug};
+use serde::{de::value::MapDeserializer, Deserialize, Deserializer, Serialize};
+use std::{
+    any::Any,
+    collections::{BTreeMap, BTreeSet, HashMap, HashSet},
+    error::Error,
+    fmt,
+    fmt::Debug,
+    panic::{self, AssertUnwindSafe},
+    path::PathBuf,
+    rc::Rc,
+    sync::atomic::{AtomicBool, Ordering},
+    time::{Duration, Instant},
+};
+
+#[cfg(feature = "yaml")]
+pub use serde_yaml::Value as YamlValue;
+
+#[cfg(feature = "derive")]
+pub use stage_fright_derive::StageName;
+
+#[cfg(feature = "async")]
+pub mod async_stage;
+#[cfg(feature = "yaml")]
+mod audit;
+mod builder;
+mod cache;
+mod case;
+mod chain;
+mod chaos;
+mod dag;
+#[cfg(feature = "yaml")]
+mod defaults;
+mod depth;
+mod features;
+mod file;
+mod group;
+mod hooks;
+#[cfg(feature = "yaml")]
+mod include;
+#[cfg(feature = "yaml")]
+mod interpolate;
+mod locate;
+mod metrics;
+mod path;
+mod registry;
+mod reload;
+mod schema;
+mod seal;
+pub mod stages;
+mod stateful;
+mod step;
+mod stream;
+mod strict;
+pub mod testing;
+mod trace;
+pub mod transform;
+
+pub use builder::StageManagerBuilder;
+use cache::{CacheKey, InstanceCache};
+pub use case::NameCase;
+pub use chain::ChainedManager;
+pub use features::{FeatureSet, Skipped};
+use hooks::EachHooks;
+pub use locate::Position;
+pub use metrics::{MetricsObserver, MetricsRecorder};
+pub use registry::StageRegistry;
+pub use reload::ReloadDiff;
+pub use schema::{ArgType, StageSchema};
+use schema::{LazyProbe, SchemaProbes};
+pub use seal::SealedStageManager;
+use stages::RetryBudget;
+pub use stateful::{Stateful, StatefulStage};
+pub use step::{StageIter, StageStep};
+pub use stream::StageSet;
+pub use trace::{RunTrace, TraceEntry, TraceOutcome};
+
+pub type BoxError = Box<dyn Error + Send + Sync>;
+
+// The newest stage file `version` this crate understands.
+pub const MAX_SUPPORTED_VERSION: u32 = 1;
+
+#[derive(Debug)]
+pub enum StageError {
+    Custom(BoxError),
+    Deserialize {
+        stage_name: String,
+        index: usize,
+        // Known when the manager kept the text its file was parsed from.
+        position: Option<Position>,
+        source: BoxError,
+    },
+    UnknownStage {
+        names: Vec<String>,
+    },
+    Timeout {
+        name: String,
+    },
+    Include {
+        path: PathBuf,
+        source: BoxError,
+    },
+    IncludeCycle {
+        path: PathBuf,
+    },
+    UnsetEnv {
+        var: String,
+    },
+    UnknownField {
+        stage_name: String,
+        field: String,
+    },
+    Register(RegisterError),
+    DependencyCycle {
+        stages: Vec<String>,
+    },
+    UnknownDependency {
+        stage: String,
+        depends_on: String,
+    },
+    Panicked {
+        name: String,
+        payload: String,
+    },
+    // StageManager::run_at was given a path with no stage file at it.
+    InvalidPath {
+        path: String,
+        source: BoxError,
+    },
+    ExclusiveConflict {
+        group: String,
+    },
+    // run_prepared was called before setup_all.
+    NotPrepared,
+    EmptyPipeline,
+    // No stage in a compiled pipeline came from the entry at `index`.
+    NoStageAt {
+        index: usize,
+    },
+    // A stages::Command program exited unsuccessfully; `code` is None if it
+    // was killed by a signal.
+    CommandFailed {
+        code: Option<i32>,
+    },
+    // Every stage failure from a run under ErrorPolicy::ContinueCollect.
+    Collected {
+        failures: Vec<(String, StageError)>,
+    },
+    UndoUnsupported,
+    // A stages::Assert check didn't hold; `actual` is None if the context
+    // had no such field.
+    AssertionFailed {
+        assertion: String,
+        actual: Option<String>,
+    },
+    UnsupportedFileVersion {
+        version: u32,
+    },
+    MaxDepthExceeded {
+        max_depth: usize,
+    },
+    // The run was cancelled before the stage at `next_index` started.
+    Cancelled {
+        next_index: usize,
+    },
+}
+
+impl StageError {
+    pub fn custom<E: Into<BoxError>>(e: E) -> Self {
+        StageError::Custom(e.into())
+    }
+}
+
+impl fmt::Display for StageError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StageError::Custom(e) => write!(f, "stage failed: {}", e),
+            StageError::Deserialize {
+                stage_name,
+                index,
+                position: None,
+                source,
+            } => write!(
+                f,
+                "invalid args for stage `{}` at index {}: {}",
+                stage_name, index, source
+            ),
+            StageError::Deserialize {
+                stage_name,
+                index,
+                position: Some(position),
+                source,
+            } => write!(
+                f,
+                "invalid args for stage `{}` at index {} ({}): {}",
+                stage_name, index, position, source
+            ),
+            StageError::UnknownStage { names } => {
+                write!(f, "no stage registered for: {}", names.join(", "))
+            }
+            StageError::Timeout { name } => write!(f, "stage `{}` timed out", name),
+            StageError::Include { path, source } => {
+                write!(f, "failed to include {}: {}", path.display(), source)
+            }
+            StageError::IncludeCycle { path } => {
+                write!(f, "{} includes itself", path.display())
+            }
+            StageError::UnsetEnv { var } => {
+                write!(f, "environment variable `{}` is not set", var)
+            }
+            StageError::UnknownField { stage_name, field } => {
+                write!(
+                    f,
+                    "unknown field `{}` in args for stage `{}`",
+                    field, stage_name
+                )
+            }
+            StageError::Register(e) => write!(f, "{}", e),
+            StageError::DependencyCycle { stages } => {
+                write!(f, "dependency cycle between: {}", stages.join(", "))
+            }
+            StageError::UnknownDependency { stage, depends_on } => write!(
+                f,
+                "stage `{}` depends on unknown stage `{}`",
+                stage, depends_on
+            ),
+            StageError::Panicked { name, payload } => {
+                write!(f, "stage `{}` panicked: {}", name, payload)
+            }
+            StageError::InvalidPath { path, source } => {
+                write!(f, "no stage file at `{}`: {}", path, source)
+            }
+            StageError::ExclusiveConflict { group } => {
+                write!(
+                    f,
+                    "more than one stage enabled in exclusive group `{}`",
+                    group
+                )
+            }
+            StageError::NotPrepared => write!(f, "stages have not been set up"),
+            StageError::EmptyPipeline => write!(f, "stage file has no stages"),
+            StageError::NoStageAt { index } => write!(f, "no stage at index {}", index),
+            StageError::CommandFailed { code: Some(code) } => {
+                write!(f, "command exited with status {}", code)
+            }
+            StageError::CommandFailed { code: None } => {
+                write!(f, "command was terminated by a signal")
+            }
+            StageError::Collected { failures } => {
+                write!(f, "{} stages failed", failures.len())?;
+                for (name, e) in failures {
+                    write!(f, "; `{}`: {}", name, e)?;
+                }
+                Ok(())
+            }
+            StageError::UndoUnsupported => write!(f, "stage does not support undo"),
+            StageError::AssertionFailed {
+                assertion,
+                actual: Some(actual),
+            } => write!(f, "assertion `{}` failed, found {}", assertion, actual),
+            StageError::AssertionFailed {
+                assertion,
+                actual: None,
+            } => write!(f, "assertion `{}` failed, no such field", assertion),
+            StageError::UnsupportedFileVersion { version } => write!(
+                f,
+                "stage file version {} is newer than the supported version {}",
+                version, MAX_SUPPORTED_VERSION
+            ),
+            StageError::MaxDepthExceeded { max_depth } => {
+                write!(f, "stages nested more than {} deep", max_depth)
+            }
+            StageError::Cancelled { next_index } => {
+                write!(f, "cancelled before stage at index {}", next_index)
+            }
+        }
+    }
+}
+
+impl Error for StageError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            StageError::Custom(e) => Some(e.as_ref()),
+            StageError::Deserialize { source, .. } => Some(source.as_ref()),
+            StageError::Include { source, .. } => Some(source.as_ref()),
+            StageError::InvalidPath { source, .. } => Some(source.as_ref()),
+            StageError::Register(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RegisterError {
+    Duplicate(String),
+    NotRegistered(String),
+}
+
+impl fmt::Display for RegisterError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RegisterError::Duplicate(name) => write!(f, "stage `{}` is already registered", name),
+            RegisterError::NotRegistered(name) => write!(f, "stage `{}` is not registered", name),
+        }
+    }
+}
+
+impl Error for RegisterError {}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StageFlow {
+    Continue,
+    // Ends the pipeline early without an error.
+    Stop,
+    // Skips the next n enabled stages.
+    Skip(usize),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StageSetupCtx<'a> {
+    pub index: usize,
+    pub name: &'a str,
+    // The number of entries in the stage file.
+    pub total: usize,
+    // Shared by every stage in the run; see StageManager::with_retry_budget.
+    pub retry_budget: Option<&'a RetryBudget>,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct StageMeta<'a> {
+    pub index: usize,
+    pub total: usize,
+    pub name: &'a str,
+    // The flag run_stages_cancellable was given; None on other runs.
+    pub cancel: Option<&'a AtomicBool>,
+}
+
+impl StageMeta<'_> {
+    // Whether the run has been asked to stop. Long-running stages can poll
+    // this and return StageError::Cancelled.
+    pub fn is_cancelled(&self) -> bool {
+        self.cancel.is_some_and(|c| c.load(Ordering::SeqCst))
+    }
+}
 
 pub trait Stage {
     type C;
 
-    fn run(&self, c: &mut Self::C);
+    fn run(&self, c: &mut Self::C) -> Result<(), StageError>;
+
+    // The manager calls this instead of run; override it to stop or skip
+    // ahead in the pipeline.
+    fn run_flow(&self, c: &mut Self::C) -> Result<StageFlow, StageError> {
+        self.run(c).map(|()| StageFlow::Continue)
+    }
+
+    // Called by StageManager::run_with_shared with read-only config that
+    // lives outside the context; downcast `shared` to the expected type. Plain
+    // runs pass `&()`.
+    fn run_shared(&self, c: &mut Self::C, _shared: &dyn Any) -> Result<StageFlow, StageError> {
+        self.run_flow(c)
+    }
+
+    // The entry point the manager calls on every run, with where the stage
+    // sits in the pipeline.
+    fn run_with_meta(
+        &self,
+        c: &mut Self::C,
+        shared: &dyn Any,
+        _meta: &StageMeta<'_>,
+    ) -> Result<StageFlow, StageError> {
+        self.run_shared(c, shared)
+    }
+
+    // setup is infallible; failures should be reported from run.
     fn setup(&mut self) {}
+
+    // The manager calls this instead of setup, passing where the stage sits
+    // in the pipeline.
+    fn setup_with(&mut self, _ctx: StageSetupCtx<'_>) {
+        self.setup();
+    }
+
+    // Called by run_stages right before run, for filling in defaults that
+    // depend on the context. Compiled pipelines don't call it.
+    fn setup_with_context(&mut self, _c: &Self::C) {}
+
+    // A value for StageManager::run_stages_collect to gather, asked for once
+    // the stage has run successfully. Downcast it to the expected type.
+    fn result(&self) -> Option<Box<dyn Any>> {
+        None
+    }
+
+    // A hint that the stage may change the context. Stages returning false
+    // are assumed to leave it alone by StageManager::run_stages_dirty.
+    fn is_mutating(&self) -> bool {
+        true
+    }
+
+    // Reverses the effect of run on the context, for use by
+    // StageManager::undo_stages.
+    fn undo(&self, _c: &mut Self::C) -> Result<(), StageError> {
+        Err(StageError::UndoUnsupported)
+    }
+
+    // Called once the pipeline finishes, in reverse order of setup, even if a
+    // stage failed.
+    fn teardown(&mut self) {}
+
+    // A human readable summary used by StageManager::plan. Defaults to the
+    // stage's registered name.
+    fn describe(&self) -> Option<String> {
+        None
+    }
+}
+
+// How stages are stored once the manager has built them.
+pub type BoxedStage<C> = Box<dyn Stage<C = C>>;
+
+pub fn box_stage<S>(stage: S) -> BoxedStage<S::C>
+where
+    S: 'static + Stage,
+{
+    Box::new(stage)
+}
+
+// For stages registered with register_indexed that want to know which entry
+// in the stage file they were built from.
+pub trait WithIndex {
+    fn with_index(self, index: usize) -> Self;
+}
+
+pub trait StageName {
+    fn stage_name() -> &'static str;
+
+    // Bumped when a stage's args change incompatibly. Stages with a version
+    // above 0 register as `name@version`.
+    fn version() -> u32 {
+        0
+    }
+}
+
+// The key `register` files a stage under.
+pub(crate) fn registry_key<S: StageName>() -> String {
+    match S::version() {
+        0 => S::stage_name().to_string(),
+        version => format!("{}@{}", S::stage_name(), version),
+    }
+}
+
+// Finds the registry key a stage file name refers to. `name@version` must
+// match exactly, while a bare name picks the highest registered version,
+// counting a plain `name` as version 0 so `name@0` finds it too.
+pub(crate) fn resolve_name<'a, T>(
+    registry: &'a BTreeMap<String, T>,
+    name: &str,
+) -> Option<&'a str> {
+    resolve_name_as(registry, name, None)
+}
+
+// resolve_name, comparing names in `case` when one is given.
+pub(crate) fn resolve_name_as<'a, T>(
+    registry: &'a BTreeMap<String, T>,
+    name: &str,
+    case: Option<NameCase>,
+) -> Option<&'a str> {
+    let canon = |n: &str| match case {
+        Some(case) => case.normalize(n),
+        None => n.to_string(),
+    };
+    if name.contains('@') {
+        let exact = |name: &str| match case {
+            None => registry.get_key_value(name).map(|(k, _)| k.as_str()),
+            Some(_) => {
+                let name = canon(name);
+                registry
+                    .keys()
+                    .find(|k| canon(k) == name)
+                    .map(String::as_str)
+            }
+        };
+        // A version 0 stage is filed under its plain name.
+        return exact(name).or_else(|| name.strip_suffix("@0").and_then(exact));
+    }
+    let name = canon(name);
+    registry
+        .keys()
+        .filter_map(|k| {
+            let canon_k = canon(k);
+            let version = match canon_k.split_once('@') {
+                None if canon_k == name => 0,
+                Some((base, version)) if base == name => version.parse().ok()?,
+                _ => return None,
+            };
+            Some((version, k))
+        })
+        .max_by_key(|&(version, _)| version)
+        .map(|(_, k)| k.as_str())
+}
+
+// The registry entry for a stage name that has passed validation.
+pub(crate) fn registered<'a, T>(registry: &'a BTreeMap<String, T>, name: &str) -> &'a T {
+    &registry[resolve_name(registry, name).unwrap_or(name)]
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct StageFile<V> {
+    // Unversioned files are version 0.
+    #[serde(skip_serializing_if = "is_default")]
+    version: u32,
+    stages: Vec<StageArgs<V>>,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct StageArgs<V> {
+    name: String,
+    // None when the entry has no `args`; the factory then gets an empty map.
+    #[serde(default = "no_args", skip_serializing_if = "Option::is_none")]
+    args: Option<V>,
+    #[serde(default = "default_true", skip_serializing_if = "is_true")]
+    enabled: bool,
+    #[serde(default = "default_repeat", skip_serializing_if = "is_default_repeat")]
+    repeat: u32,
+    // Names this entry in other stages' `depends_on`, defaulting to `name`.
+    #[serde(default, skip_serializing_if = "is_default")]
+    id: Option<String>,
+    #[serde(default, skip_serializing_if = "is_default")]
+    depends_on: Vec<String>,
+    #[serde(default, skip_serializing_if = "is_default")]
+    tags: Vec<String>,
+    // Stages with a higher priority run first; ties keep file order.
+    #[serde(default, skip_serializing_if = "is_default")]
+    priority: i32,
+    // The chance the stage runs at all when chaos mode is on.
+    #[serde(default, skip_serializing_if = "is_default")]
+    probability: Option<f64>,
+    // At most one enabled stage may be in a group marked `exclusive`.
+    #[serde(default, skip_serializing_if = "is_default")]
+    group: Option<String>,
+    #[serde(default, skip_serializing_if = "is_default")]
+    exclusive: bool,
+    // Features that must be active for run_stages_with_features to run it.
+    #[serde(default, skip_serializing_if = "is_default")]
+    requires: Vec<String>,
+    // Annotations such as `owner` or `ticket`, never passed to the stage.
+    #[serde(default = "HashMap::new", skip_serializing_if = "HashMap::is_empty")]
+    meta: HashMap<String, V>,
+}
+
+impl<V> StageArgs<V> {
+    // An enabled entry that runs once, as if written with only `name` and
+    // `args`.
+    pub fn new(name: impl Into<String>, args: V) -> Self {
+        Self {
+            name: name.into(),
+            args: Some(args),
+            enabled: true,
+            repeat: 1,
+            id: None,
+            depends_on: Vec::new(),
+            tags: Vec::new(),
+            priority: 0,
+            probability: None,
+            group: None,
+            exclusive: false,
+            requires: Vec::new(),
+            meta: HashMap::new(),
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn args(&self) -> Option<&V> {
+        self.args.as_ref()
+    }
+
+    pub fn args_mut(&mut self) -> &mut Option<V> {
+        &mut self.args
+    }
+
+    // The args handed to the stage's factory, standing in an empty map if
+    // the entry has none.
+    pub(crate) fn args_or_empty<'de>(&self) -> Result<V, BoxError>
+    where
+        V: Deserialize<'de> + Clone,
+    {
+        match &self.args {
+            Some(args) => Ok(args.clone()),
+            None => empty_args(),
+        }
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.enabled
+    }
+
+    pub fn set_enabled(&mut self, enabled: bool) {
+        self.enabled = enabled;
+    }
+}
+
+impl<V> StageFile<V> {
+    // Enabled stages in dependency order.
+    fn enabled_stages(&self) -> Result<Vec<(usize, &StageArgs<V>)>, StageError> {
+        self.check_version()?;
+        self.check_exclusive()?;
+        let mut order = self.ordered_stages()?;
+        order.retain(|(_, s)| s.enabled && s.repeat > 0);
+        Ok(order)
+    }
+
+    fn check_version(&self) -> Result<(), StageError> {
+        if self.version > MAX_SUPPORTED_VERSION {
+            return Err(StageError::UnsupportedFileVersion {
+                version: self.version,
+            });
+        }
+        Ok(())
+    }
+
+    // Stage names `is_registered` rejects, sorted.
+    fn unregistered<F: Fn(&str) -> bool>(&self, is_registered: F) -> Vec<String> {
+        let missing: BTreeSet<&String> = self
+            .stages
+            .iter()
+            .map(|s| &s.name)
+            .filter(|name| !is_registered(name))
+            .collect();
+        missing.into_iter().cloned().collect()
+    }
+}
+
+// The internally tagged form of a stage file, where each entry's args sit
+// beside a `type` key naming the stage:
+//
+//     stages:
+//     - type: add
+//       x: 1
+//
+// `enabled` and `repeat` keep their meaning and are not passed to the stage.
+#[derive(Debug, Deserialize)]
+pub struct TaggedStageFile<V> {
+    #[serde(default)]
+    version: u32,
+    stages: Vec<TaggedStageArgs<V>>,
+}
+
+#[derive(Debug, Deserialize)]
+struct TaggedStageArgs<V> {
+    #[serde(rename = "type")]
+    name: String,
+    #[serde(default = "default_true")]
+    enabled: bool,
+    #[serde(default = "default_repeat")]
+    repeat: u32,
+    #[serde(default)]
+    id: Option<String>,
+    #[serde(default)]
+    depends_on: Vec<String>,
+    #[serde(default)]
+    tags: Vec<String>,
+    #[serde(default)]
+    priority: i32,
+    #[serde(default)]
+    probability: Option<f64>,
+    #[serde(default)]
+    group: Option<String>,
+    #[serde(default)]
+    exclusive: bool,
+    #[serde(default)]
+    requires: Vec<String>,
+    #[serde(default = "HashMap::new")]
+    meta: HashMap<String, V>,
+    #[serde(flatten)]
+    args: V,
+}
+
+impl<V> From<TaggedStageFile<V>> for StageFile<V> {
+    fn from(file: TaggedStageFile<V>) -> Self {
+        let stages = file
+            .stages
+            .into_iter()
+            .map(|s| StageArgs {
+                name: s.name,
+                args: Some(s.args),
+                enabled: s.enabled,
+                repeat: s.repeat,
+                id: s.id,
+                depends_on: s.depends_on,
+                tags: s.tags,
+                priority: s.priority,
+                probability: s.probability,
+                group: s.group,
+                exclusive: s.exclusive,
+                requires: s.requires,
+                meta: s.meta,
+            })
+            .collect();
+        StageFile {
+            version: file.version,
+            stages,
+        }
+    }
+}
+
+fn no_args<V>() -> Option<V> {
+    None
+}
+
+// Stands in for omitted args, so stages whose fields are all optional can be
+// listed by name alone.
+fn empty_args<'de, V: Deserialize<'de>>() -> Result<V, BoxError> {
+    let empty = MapDeserializer::<_, serde::de::value::Error>::new(std::iter::empty::<((), ())>());
+    V::deserialize(empty).map_err(Into::into)
+}
+
+fn default_true() -> bool {
+    true
+}
+
+fn default_repeat() -> u32 {
+    1
+}
+
+// Lets serialized files leave out whatever parsing would fill in anyway.
+fn is_true(b: &bool) -> bool {
+    *b
+}
+
+fn is_default_repeat(repeat: &u32) -> bool {
+    *repeat == default_repeat()
+}
+
+fn is_default<T: Default + PartialEq>(t: &T) -> bool {
+    *t == T::default()
+}
+
+// Called with the index of the entry being built and its args.
+type FnDeserializeStage<C, V> = Rc<dyn Fn(usize, V) -> Result<BoxedStage<C>, BoxError>>;
+
+// Builds stages whose names have no factory of their own.
+type FnFallbackStage<C, V> = Rc<dyn Fn(&str, V) -> Result<BoxedStage<C>, BoxError>>;
+
+// Called with the context once a run succeeds.
+type FnFinalContext<C> = Box<dyn Fn(&C)>;
+
+// Rewrites a stage's args before they reach its factory.
+type FnPreprocessArgs<V> = Rc<dyn Fn(V) -> Result<V, StageError>>;
+
+// `V` is the format's owned value type holding each stage's args. It must be
+// `Deserialize + Deserializer + Clone`, which `serde_yaml::Value`,
+// `serde_json::Value` and `toml::Value` all satisfy. Borrowed representations
+// such as `serde_json::value::RawValue` do not work since every stage is built
+// from an owned clone of its args.
+#[derive(Deserialize)]
+pub struct StageManager<C, V> {
+    #[serde(flatten)]
+    file: StageFile<V>,
+
+    // Shared with other managers through StageRegistry, and only copied when
+    // one of them registers something new. Ordered so that listings and
+    // errors come out the same on every run.
+    #[serde(skip)]
+    deserialize_map: Rc<BTreeMap<String, FnDeserializeStage<C, V>>>,
+
+    #[serde(skip)]
+    fallback: Option<FnFallbackStage<C, V>>,
+
+    #[serde(skip)]
+    preprocess: Option<FnPreprocessArgs<V>>,
+
+    #[serde(skip)]
+    observer: Option<Rc<dyn Observer>>,
+
+    #[serde(skip)]
+    final_context: Option<FnFinalContext<C>>,
+
+    #[serde(skip)]
+    hooks: EachHooks,
+
+    #[serde(skip)]
+    strict: bool,
+
+    #[serde(skip)]
+    catch_panics: bool,
+
+    #[serde(skip)]
+    error_policy: ErrorPolicy,
+
+    #[serde(skip)]
+    max_depth: Option<usize>,
+
+    #[serde(skip)]
+    chaos_seed: Option<u64>,
+
+    #[serde(skip)]
+    require_non_empty: bool,
+
+    #[serde(skip)]
+    name_case: Option<NameCase>,
+
+    #[serde(skip)]
+    retry_budget: Option<RetryBudget>,
+
+    // Stages set up ahead of time by setup_all.
+    #[serde(skip)]
+    prepared: Option<CompiledPipeline<C>>,
+
+    // The text the file was parsed from, to locate args errors.
+    #[serde(skip)]
+    source: Option<String>,
+
+    #[serde(skip)]
+    instance_cache: Option<InstanceCache<C, V>>,
+
+    // Args schemas of stages registered by type, also used by strict mode.
+    #[serde(skip)]
+    schemas: Rc<SchemaProbes>,
+}
+
+// Runs a stage `repeat` times, returning early on anything but Continue.
+fn run_repeated<C>(
+    stage: &dyn Stage<C = C>,
+    repeat: u32,
+    context: &mut C,
+    shared: &dyn Any,
+    meta: &StageMeta<'_>,
+) -> Result<StageFlow, StageError> {
+    for _ in 0..repeat {
+        match stage.run_with_meta(context, shared, meta)? {
+            StageFlow::Continue => {}
+            flow => return Ok(flow),
+        }
+    }
+    Ok(StageFlow::Continue)
+}
+
+pub(crate) fn panic_message(payload: Box<dyn Any + Send>) -> String {
+    match payload.downcast::<String>() {
+        Ok(msg) => *msg,
+        Err(payload) => match payload.downcast::<&'static str>() {
+            Ok(msg) => msg.to_string(),
+            Err(_) => "non-string panic payload".to_string(),
+        },
+    }
+}
+
+// Observes stages as run_stages executes them. The unit impl is used by the
+// plain run path so it costs nothing.
+trait Listener<C> {
+    fn before_stage(&mut self, _index: usize, _name: &str) {}
+    // Called around the stage's run with the context it sees and leaves.
+    fn before_run(&mut self, _index: usize, _mutating: bool, _context: &C) {}
+    fn after_run(&mut self, _index: usize, _context: &C) {}
+    fn after_stage(&mut self, _index: usize, _name: &str, _result: &Result<StageFlow, StageError>) {
+    }
+    fn stage_result(&mut self, _index: usize, _value: Box<dyn Any>) {}
+}
+
+impl<C> Listener<C> for () {}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum StageFilter {
+    Only(HashSet<String>),
+    Range(std::ops::Range<usize>),
+    Except(HashSet<String>),
+}
+
+impl StageFilter {
+    pub fn matches(&self, index: usize, name: &str) -> bool {
+        match self {
+            StageFilter::Only(names) => names.contains(name),
+            StageFilter::Range(range) => range.contains(&index),
+            StageFilter::Except(names) => !names.contains(name),
+        }
+    }
+}
+
+// Where run_stages_from picks a pipeline back up: the first entry to run with
+// the given name, or the entry at the given index in the stage file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ResumePoint {
+    Name(String),
+    Index(usize),
 }
 
-pub trait StageName {
-    fn stage_name() -> &'static str;
-}
+// Selects stages by their `tags`. A stage with any excluded tag never runs;
+// otherwise it runs if it has an included tag, or if nothing is included.
+// Untagged stages run only when nothing is included unless set with
+// `untagged`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TagFilter {
+    include: HashSet<String>,
+    exclude: HashSet<String>,
+    untagged: Option<bool>,
+}
+
+impl TagFilter {
+    pub fn include(tag: &str) -> Self {
+        Self::default().and_include(tag)
+    }
+
+    pub fn exclude(tag: &str) -> Self {
+        Self::default().and_exclude(tag)
+    }
+
+    pub fn and_include(mut self, tag: &str) -> Self {
+        self.include.insert(tag.to_string());
+        self
+    }
+
+    pub fn and_exclude(mut self, tag: &str) -> Self {
+        self.exclude.insert(tag.to_string());
+        self
+    }
+
+    pub fn untagged(mut self, run: bool) -> Self {
+        self.untagged = Some(run);
+        self
+    }
+
+    pub fn matches(&self, tags: &[String]) -> bool {
+        if tags.is_empty() {
+            return self.untagged.unwrap_or(self.include.is_empty());
+        }
+        if tags.iter().any(|t| self.exclude.contains(t)) {
+            return false;
+        }
+        self.include.is_empty() || tags.iter().any(|t| self.include.contains(t))
+    }
+}
+
+// A read-only view of one entry in a stage file.
+#[derive(Debug)]
+pub struct StageRef<'a, V> {
+    index: usize,
+    stage: &'a StageArgs<V>,
+}
+
+impl<'a, V> StageRef<'a, V> {
+    pub fn index(&self) -> usize {
+        self.index
+    }
+
+    pub fn name(&self) -> &'a str {
+        &self.stage.name
+    }
+
+    pub fn args(&self) -> Option<&'a V> {
+        self.stage.args.as_ref()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StagePlanEntry {
+    pub index: usize,
+    pub name: String,
+    pub enabled: bool,
+    pub description: String,
+}
+
+#[derive(Debug, Default)]
+pub struct RunSummary {
+    pub executed: usize,
+    pub skipped: usize,
+    // The failing stage's name and its error.
+    pub failed: Option<(String, StageError)>,
+    // Failures the run carried on past under ErrorPolicy::ContinueCollect.
+    pub errors: Vec<(String, StageError)>,
+}
+
+impl RunSummary {
+    fn into_result(self) -> Result<(), StageError> {
+        match self.failed {
+            Some((_, e)) => Err(e),
+            None if !self.errors.is_empty() => Err(StageError::Collected {
+                failures: self.errors,
+            }),
+            None => Ok(()),
+        }
+    }
+
+    // Records a stage failure, returning whether the run should go on.
+    fn record_failure(&mut self, policy: ErrorPolicy, name: &str, e: StageError) -> bool {
+        match policy {
+            ErrorPolicy::Abort => {
+                self.failed = Some((name.to_string(), e));
+                false
+            }
+            ErrorPolicy::ContinueCollect => {
+                self.errors.push((name.to_string(), e));
+                true
+            }
+        }
+    }
+}
+
+// The error from run_stages_tracked: how many stages finished before the
+// pipeline stopped, and which entry the error came from when it came from
+// one at all.
+#[derive(Debug)]
+pub struct RunError {
+    pub completed: usize,
+    pub failed_index: Option<usize>,
+    pub failed_stage: Option<String>,
+    pub source: StageError,
+}
+
+impl fmt::Display for RunError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match (&self.failed_stage, self.failed_index) {
+            (Some(name), Some(index)) => write!(f, "stage `{}` at index {}", name, index)?,
+            (Some(name), None) => write!(f, "stage `{}`", name)?,
+            _ => write!(f, "pipeline")?,
+        }
+        write!(
+            f,
+            " failed after {} completed: {}",
+            self.completed, self.source
+        )
+    }
+}
+
+impl Error for RunError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        Some(&self.source)
+    }
+}
+
+// What run_stages does when a stage fails to build or run. Under
+// ContinueCollect every stage still runs and the failures are returned
+// together as StageError::Collected.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ErrorPolicy {
+    #[default]
+    Abort,
+    ContinueCollect,
+}
+
+pub trait Observer {
+    fn on_stage_start(&self, _name: &str, _index: usize) {}
+    fn on_stage_end(&self, _name: &str, _index: usize, _result: &Result<StageFlow, StageError>) {}
+    // Disabled, filtered out, or skipped by another stage's StageFlow::Skip.
+    fn on_stage_skipped(&self, _name: &str, _index: usize) {}
+    // Called instead of on_stage_skipped when the run left the stage out for
+    // a known reason, such as a feature it requires.
+    fn on_stage_skipped_because(&self, name: &str, index: usize, _reason: &str) {
+        self.on_stage_skipped(name, index);
+    }
+    fn on_pipeline_end(&self, _summary: &RunSummary) {}
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StageTiming {
+    pub name: String,
+    pub index: usize,
+    pub duration: Duration,
+}
+
+#[derive(Default)]
+struct TimingListener {
+    started: Option<Instant>,
+    timings: Vec<StageTiming>,
+}
+
+impl<C> Listener<C> for TimingListener {
+    fn before_stage(&mut self, _index: usize, _name: &str) {
+        self.started = Some(Instant::now());
+    }
+
+    fn after_stage(&mut self, index: usize, name: &str, _result: &Result<StageFlow, StageError>) {
+        if let Some(started) = self.started.take() {
+            self.timings.push(StageTiming {
+                name: name.to_string(),
+                index,
+                duration: started.elapsed(),
+            });
+        }
+    }
+}
+
+// What a run hands every stage besides the context.
+#[derive(Clone, Copy)]
+struct RunEnv<'a> {
+    shared: &'a dyn Any,
+    // Checked before each stage starts, and passed on in StageMeta.
+    cancel: Option<&'a AtomicBool>,
+}
+
+impl<'a> RunEnv<'a> {
+    fn new(shared: &'a dyn Any) -> Self {
+        Self {
+            shared,
+            cancel: None,
+        }
+    }
+}
+
+// Snapshots the context before each stage that may mutate it and records the
+// index of every stage that left it different.
+struct DirtyListener<C> {
+    before: Option<C>,
+    dirty: Vec<usize>,
+}
+
+impl<C: PartialEq + Clone> Listener<C> for DirtyListener<C> {
+    fn before_run(&mut self, _index: usize, mutating: bool, context: &C) {
+        self.before = Some(context).filter(|_| mutating).cloned();
+    }
+
+    fn after_run(&mut self, index: usize, context: &C) {
+        if let Some(before) = self.before.take() {
+            if before != *context {
+                self.dirty.push(index);
+            }
+        }
+    }
+}
+
+#[derive(Default)]
+struct ProgressListener {
+    completed: usize,
+    failed: Option<usize>,
+}
+
+impl<C> Listener<C> for ProgressListener {
+    fn after_stage(&mut self, index: usize, _name: &str, result: &Result<StageFlow, StageError>) {
+        match result {
+            Ok(_) => self.completed += 1,
+            Err(_) => {
+                self.failed.get_or_insert(index);
+            }
+        }
+    }
+}
+
+#[derive(Default)]
+struct ResultListener {
+    results: HashMap<usize, Box<dyn Any>>,
+}
+
+impl<C> Listener<C> for ResultListener {
+    fn stage_result(&mut self, index: usize, value: Box<dyn Any>) {
+        self.results.insert(index, value);
+    }
+}
+
+// A stage set up by run_with, and where it goes back to in the instance
+// cache once torn down.
+struct ActiveStage<C> {
+    stage: BoxedStage<C>,
+    cache_key: Option<CacheKey>,
+}
+
+struct CompiledStage<C> {
+    stage: BoxedStage<C>,
+    repeat: u32,
+    index: usize,
+    name: String,
+}
+
+pub struct CompiledPipeline<C> {
+    stages: Vec<CompiledStage<C>>,
+    // The number of entries in the stage file the pipeline was built from.
+    total: usize,
+}
+
+impl<C> CompiledPipeline<C> {
+    // Sets up stages built in code, without a stage file or any registered
+    // factories. Each stage runs once, named by its describe.
+    pub fn from_stages(stages: Vec<BoxedStage<C>>) -> Self {
+        let total = stages.len();
+        let stages = stages
+            .into_iter()
+            .enumerate()
+            .map(|(index, mut stage)| {
+                let name = stage.describe().unwrap_or_default();
+                stage.setup_with(StageSetupCtx {
+                    index,
+                    name: &name,
+                    total,
+                    retry_budget: None,
+                });
+                CompiledStage {
+                    stage,
+                    repeat: 1,
+                    index,
+                    name,
+                }
+            })
+            .collect();
+        Self { stages, total }
+    }
+
+    pub fn run(&self, context: &mut C) -> Result<(), StageError> {
+        let mut skip = 0;
+        for s in &self.stages {
+            if skip > 0 {
+                skip -= 1;
+                continue;
+            }
+
+            let meta = StageMeta {
+                index: s.index,
+                total: self.total,
+                name: &s.name,
+                cancel: None,
+            };
+            match run_repeated(s.stage.as_ref(), s.repeat, context, &(), &meta)? {
+                StageFlow::Continue => {}
+                StageFlow::Stop => break,
+                StageFlow::Skip(n) => skip = n,
+            }
+        }
+        Ok(())
+    }
+
+    // The stage built from the entry at `index` in the stage file, if it is
+    // part of the pipeline.
+    pub fn stage(&self, index: usize) -> Option<&dyn Stage<C = C>> {
+        self.compiled(index).map(|s| s.stage.as_ref())
+    }
+
+    // Runs just the stage at `index` in the stage file, `repeat` times as
+    // usual, ignoring any StageFlow it returns.
+    pub fn run_one(&self, index: usize, context: &mut C) -> Result<(), StageError> {
+        let s = self
+            .compiled(index)
+            .ok_or(StageError::NoStageAt { index })?;
+        let meta = StageMeta {
+            index,
+            total: self.total,
+            name: &s.name,
+            cancel: None,
+        };
+        run_repeated(s.stage.as_ref(), s.repeat, context, &(), &meta).map(|_| ())
+    }
+
+    fn compiled(&self, index: usize) -> Option<&CompiledStage<C>> {
+        self.stages.iter().find(|s| s.index == index)
+    }
+
+    // Runs the pipeline over each context in turn, returning one result per
+    // context. A failure only stops the run for its own context.
+    pub fn run_batch(&self, contexts: &mut [C]) -> Vec<Result<(), StageError>> {
+        contexts.iter_mut().map(|c| self.run(c)).collect()
+    }
+}
+
+impl<C> Drop for CompiledPipeline<C> {
+    fn drop(&mut self) {
+        self.stages
+            .iter_mut()
+            .rev()
+            .for_each(|s| s.stage.teardown());
+    }
+}
+
+impl<C> Debug for CompiledPipeline<C> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(f, "CompiledPipeline{{stages: {}}}", self.stages.len())
+    }
+}
+
+impl<C, V> Debug for StageManager<C, V> where V: Debug {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(f, "StateManager{{file: {:?}, deserialize_map: {:?}}}", self.file, self.deserialize_map.keys())
+    }
+}
+
+// Copies the stage file and run settings but none of the registered
+// factories, nor the observer, preprocessing or instance cache; the clone has
+// to be registered again before it can run. See clone_with_registry.
+impl<C, V> Clone for StageManager<C, V>
+where
+    V: Clone,
+{
+    fn clone(&self) -> Self {
+        Self {
+            file: self.file.clone(),
+            deserialize_map: Rc::default(),
+            fallback: None,
+            preprocess: None,
+            observer: None,
+            final_context: None,
+            hooks: self.hooks.clone(),
+            strict: self.strict,
+            catch_panics: self.catch_panics,
+            error_policy: self.error_policy,
+            max_depth: self.max_depth,
+            chaos_seed: self.chaos_seed,
+            require_non_empty: self.require_non_empty,
+            name_case: self.name_case,
+            retry_budget: self.retry_budget.as_ref().map(RetryBudget::fresh),
+            prepared: None,
+            source: self.source.clone(),
+            schemas: Rc::default(),
+            instance_cache: None,
+        }
+    }
+}
+
+impl<'de, C, V> StageManager<C, V>
+where
+    V: Deserialize<'de> + Deserializer<'de> + Clone,
+{
+    // Clones the manager and rebuilds its factories with `register`.
+    pub fn clone_with_registry<R>(&self, register: R) -> Result<Self, RegisterError>
+    where
+        R: FnOnce(&mut Self) -> Result<(), RegisterError>,
+    {
+        let mut clone = self.clone();
+        register(&mut clone)?;
+        Ok(clone)
+    }
+
+    pub fn from_file(stage_file: StageFile<V>) -> Self {
+        Self {
+            file: stage_file,
+            deserialize_map: Rc::default(),
+            fallback: None,
+            preprocess: None,
+            observer: None,
+            final_context: None,
+            hooks: EachHooks::default(),
+            strict: false,
+            catch_panics: false,
+            error_policy: ErrorPolicy::Abort,
+            max_depth: None,
+            chaos_seed: None,
+            require_non_empty: false,
+            name_case: None,
+            retry_budget: None,
+            prepared: None,
+            source: None,
+            schemas: Rc::default(),
+            instance_cache: None,
+        }
+    }
+
+    pub fn builder() -> StageManagerBuilder<C, V> {
+        StageManagerBuilder::new()
+    }
+
+    pub fn with_observer(&mut self, observer: Box<dyn Observer>) -> &mut Self {
+        self.observer = Some(Rc::from(observer));
+        self
+    }
+
+    // Turns a panic in a stage's run into StageError::Panicked. The context
+    // may have been left partially updated by the panicking stage.
+    pub fn catch_panics(&mut self, enabled: bool) -> &mut Self {
+        self.catch_panics = enabled;
+        self
+    }
+
+    // Makes running a file with no stages at all fail with
+    // StageError::EmptyPipeline instead of doing nothing.
+    pub fn require_non_empty(&mut self, required: bool) -> &mut Self {
+        self.require_non_empty = required;
+        self
+    }
+
+    // Caps the retries stages::Retry may make across all stages in a run at
+    // `retries`. Once they are used up, the next failure is returned as is.
+    pub fn with_retry_budget(&mut self, retries: u32) -> &mut Self {
+        self.retry_budget = Some(RetryBudget::new(retries));
+        self
+    }
+
+    pub fn with_error_policy(&mut self, policy: ErrorPolicy) -> &mut Self {
+        self.error_policy = policy;
+        self
+    }
+
+    // Every entry in the file in file order, registered or not.
+    pub fn stages(&self) -> impl Iterator<Item = StageRef<'_, V>> {
+        self.file
+            .stages
+            .iter()
+            .enumerate()
+            .map(|(index, stage)| StageRef { index, stage })
+    }
+
+    // Rewrites the parsed stages before anything runs, e.g. to insert a stage
+    // between every other or drop some.
+    pub fn transform_file<F>(&mut self, f: F) -> &mut Self
+    where
+        F: FnOnce(&mut Vec<StageArgs<V>>),
+    {
+        f(&mut self.file.stages);
+        self.source = None;
+        self.prepared = None;
+        self
+    }
+
+    // The `meta` annotations of the stage at `index` in the file.
+    pub fn stage_meta(&self, index: usize) -> Option<&HashMap<String, V>> {
+        self.file.stages.get(index).map(|s| &s.meta)
+    }
+
+    pub fn registered_names(&self) -> impl Iterator<Item = &str> {
+        self.deserialize_map.keys().map(String::as_str)
+    }
+
+    pub fn is_registered(&self, name: &str) -> bool {
+        self.resolve(name).is_some()
+    }
+
+    // The registry key a stage file name refers to.
+    pub(crate) fn resolve(&self, name: &str) -> Option<&str> {
+        resolve_name_as(&self.deserialize_map, name, self.name_case)
+    }
+
+    // Whether registering `name` would clash with a name already taken.
+    fn is_taken(&self, name: &str) -> bool {
+        self.taken_key(name).is_some()
+    }
+
+    // The registered key `name` clashes with, if any.
+    fn taken_key(&self, name: &str) -> Option<&str> {
+        match self.name_case {
+            None => self
+                .deserialize_map
+                .get_key_value(name)
+                .map(|(k, _)| k.as_str()),
+            Some(case) => {
+                let name = case.normalize(name);
+                self.deserialize_map
+                    .keys()
+                    .find(|k| case.normalize(k) == name)
+                    .map(String::as_str)
+            }
+        }
+    }
+
+    // Returns every stage name in the file that has no registered factory.
+    // Always passes once a fallback is registered.
+    pub fn validate(&self) -> Result<(), Vec<String>> {
+        if self.fallback.is_some() {
+            return Ok(());
+        }
+        let missing = self.file.unregistered(|name| self.is_registered(name));
+        if missing.is_empty() {
+            Ok(())
+        } else {
+            Err(missing)
+        }
+    }
+
+    pub fn run_stages(&self, context: &mut C) -> Result<(), StageError> {
+        self.run_with(context, &(), |_, _| Ok(()), &mut ())
+    }
+
+    // Takes the context by value and hands it back once the pipeline has run,
+    // for use in iterator chains. The context is dropped if a stage fails.
+    pub fn run_into(&self, mut context: C) -> Result<C, StageError> {
+        self.run_stages(&mut context)?;
+        Ok(context)
+    }
+
+    pub fn run_with_shared<S: Any>(&self, context: &mut C, shared: &S) -> Result<(), StageError> {
+        self.run_with(context, shared, |_, _| Ok(()), &mut ())
+    }
+
+    // Runs the pipeline and times each stage that ran. The timings are kept
+    // when a stage fails, ending with the stage that failed.
+    pub fn run_stages_timed(&self, context: &mut C) -> (Result<(), StageError>, Vec<StageTiming>) {
+        let mut listener = TimingListener::default();
+        let result = self.run_with(context, &(), |_, _| Ok(()), &mut listener);
+        (result, listener.timings)
+    }
+
+    // Runs the pipeline and returns the indices of the stages that changed
+    // the context. Stages whose is_mutating is false are taken at their word
+    // and never snapshotted.
+    pub fn run_stages_dirty(&self, context: &mut C) -> Result<Vec<usize>, StageError>
+    where
+        C: PartialEq + Clone,
+    {
+        let mut listener = DirtyListener {
+            before: None,
+            dirty: Vec::new(),
+        };
+        self.run_with(context, &(), |_, _| Ok(()), &mut listener)?;
+        Ok(listener.dirty)
+    }
+
+    // Stops with StageError::Cancelled once `cancel` is set, checked before
+    // each stage starts. A stage already running is left to finish unless it
+    // polls StageMeta::is_cancelled, as Sleep does.
+    pub fn run_stages_cancellable(
+        &self,
+        context: &mut C,
+        cancel: &AtomicBool,
+    ) -> Result<(), StageError> {
+        let env = RunEnv {
+            shared: &(),
+            cancel: Some(cancel),
+        };
+        self.run_summary(context, env, |_, _| Ok(()), &mut ())
+            .into_result()
+    }
+
+    // Runs the pipeline and gathers each stage's Stage::result, keyed by its
+    // index in the file.
+    pub fn run_stages_collect(
+        &self,
+        context: &mut C,
+    ) -> Result<HashMap<usize, Box<dyn Any>>, StageError> {
+        let mut listener = ResultListener::default();
+        self.run_with(context, &(), |_, _| Ok(()), &mut listener)?;
+        Ok(listener.results)
+    }
+
+    // Runs only the stages matching `filter`; the rest are skipped as if
+    // they were disabled.
+    pub fn run_stages_filtered(
+        &self,
+        context: &mut C,
+        filter: &StageFilter,
+    ) -> Result<(), StageError> {
+        let select = |index, s: &StageArgs<V>| match filter.matches(index, &s.name) {
+            true => Ok(()),
+            false => Err("filtered out".to_string()),
+        };
+        self.run_with(context, &(), select, &mut ())
+    }
+
+    // Skips every entry that runs before `start`, for resuming a pipeline
+    // that failed partway. Entries are counted in run order, so priorities
+    // and `depends_on` are taken into account. The context is assumed to be
+    // as it was before the failure.
+    pub fn run_stages_from(&self, context: &mut C, start: ResumePoint) -> Result<(), StageError> {
+        let order = self.file.ordered_stages()?;
+        let from = match start {
+            ResumePoint::Index(index) => order
+                .iter()
+                .position(|(i, _)| *i == index)
+                .ok_or(StageError::NoStageAt { index })?,
+            ResumePoint::Name(name) => order
+                .iter()
+                .position(|(_, s)| s.name == name)
+                .ok_or(StageError::UnknownStage { names: vec![name] })?,
+        };
+        let resumed: HashSet<usize> = order[from..].iter().map(|(i, _)| *i).collect();
+        let select = |index, _: &StageArgs<V>| match resumed.contains(&index) {
+            true => Ok(()),
+            false => Err("before the resume point".to_string()),
+        };
+        self.run_with(context, &(), select, &mut ())
+    }
+
+    pub fn run_stages_with_tags(
+        &self,
+        context: &mut C,
+        filter: &TagFilter,
+    ) -> Result<(), StageError> {
+        let select = |_, s: &StageArgs<V>| match filter.matches(&s.tags) {
+            true => Ok(()),
+            false => Err("filtered out by tags".to_string()),
+        };
+        self.run_with(context, &(), select, &mut ())
+    }
+
+    // Like run_stages, but on failure reports how many stages completed and
+    // where the failure happened, enough to pick the run back up with
+    // run_stages_from.
+    pub fn run_stages_tracked(&self, context: &mut C) -> Result<(), RunError> {
+        let mut listener = ProgressListener::default();
+        let summary = self.run_summary(context, RunEnv::new(&()), |_, _| Ok(()), &mut listener);
+        let failed_stage = summary
+            .failed
+            .as_ref()
+            .map(|(name, _)| name.clone())
+            .filter(|name| !name.is_empty());
+        summary.into_result().map_err(|source| {
+            let failed_index = match &source {
+                StageError::Deserialize { index, .. } => Some(*index),
+                StageError::Cancelled { next_index } => Some(*next_index),
+                _ => listener.failed,
+            };
+            RunError {
+                completed: listener.completed,
+                failed_index,
+                failed_stage,
+                source,
+            }
+        })
+    }
+
+    // Like run_stages, but reports how many stages ran and were skipped
+    // alongside the first error instead of returning it.
+    pub fn run_stages_summary(&self, context: &mut C) -> RunSummary {
+        self.run_summary(context, RunEnv::new(&()), |_, _| Ok(()), &mut ())
+    }
+
+    fn run_with<F, L>(
+        &self,
+        context: &mut C,
+        shared: &dyn Any,
+        select: F,
+        listener: &mut L,
+    ) -> Result<(), StageError>
+    where
+        F: FnMut(usize, &StageArgs<V>) -> Result<(), String>,
+        L: Listener<C>,
+    {
+        self.run_summary(context, RunEnv::new(shared), select, listener)
+            .into_result()
+    }
+
+    fn run_summary<F, L>(
+        &self,
+        context: &mut C,
+        env: RunEnv<'_>,
+        select: F,
+        listener: &mut L,
+    ) -> RunSummary
+    where
+        F: FnMut(usize, &StageArgs<V>) -> Result<(), String>,
+        L: Listener<C>,
+    {
+        let _hooks = self.hooks.enter();
+        if let Some(budget) = &self.retry_budget {
+            budget.reset();
+        }
+        let mut summary = RunSummary::default();
+        let depth = depth::enter(self.max_depth)
+            .and_then(|scope| self.file.check_version().map(|()| scope));
+        if let Err(e) = depth {
+            summary.failed = Some((String::new(), e));
+        } else if self.require_non_empty && self.file.stages.is_empty() {
+            summary.failed = Some((String::new(), StageError::EmptyPipeline));
+        } else if let Err(names) = self.validate() {
+            summary.failed = Some((names[0].clone(), StageError::UnknownStage { names }));
+        } else {
+            let mut active = Vec::new();
+            self.run_active(context, env, select, &mut active, listener, &mut summary);
+            active.iter_mut().rev().for_each(|s| s.stage.teardown());
+            self.return_to_cache(active);
+        }
+
+        if summary.failed.is_none() && summary.errors.is_empty() {
+            if let Some(log) = &self.final_context {
+                log(context);
+            }
+        }
+
+        if let Some(observer) = &self.observer {
+            observer.on_pipeline_end(&summary);
+        }
+        summary
+    }
+
+    fn run_active<F, L>(
+        &self,
+        context: &mut C,
+        env: RunEnv<'_>,
+        mut select: F,
+        active: &mut Vec<ActiveStage<C>>,
+        listener: &mut L,
+        summary: &mut RunSummary,
+    ) where
+        F: FnMut(usize, &StageArgs<V>) -> Result<(), String>,
+        L: Listener<C>,
+    {
+        let order = self
+            .file
+            .check_exclusive()
+            .and_then(|()| self.file.ordered_stages());
+        let order = match order {
+            Ok(order) => order,
+            Err(e) => {
+                let name = match &e {
+                    StageError::DependencyCycle { stages } => stages[0].clone(),
+                    StageError::UnknownDependency { stage, .. } => stage.clone(),
+                    StageError::ExclusiveConflict { group } => group.clone(),
+                    _ => String::new(),
+                };
+                summary.failed = Some((name, e));
+                return;
+            }
+        };
+
+        let observer = self.observer.as_deref();
+        let mut chaos = self.chaos_seed.map(chaos::Chaos::new);
+        let mut skip = 0;
+        for (index, s) in order {
+            let live = s.enabled && s.repeat > 0;
+            let reason = if live { select(index, s).err() } else { None };
+            let runnable =
+                live && reason.is_none() && chaos.as_mut().is_none_or(|c| c.keep(s.probability));
+            if !runnable || skip > 0 {
+                if runnable {
+                    skip -= 1;
+                }
+                summary.skipped += 1;
+                if let Some(observer) = observer {
+                    match &reason {
+                        Some(reason) => observer.on_stage_skipped_because(&s.name, index, reason),
+                        None => observer.on_stage_skipped(&s.name, index),
+                    }
+                }
+                continue;
+            }
+
+            if env.cancel.is_some_and(|c| c.load(Ordering::SeqCst)) {
+                let e = StageError::Cancelled { next_index: index };
+                summary.failed = Some((s.name.clone(), e));
+                return;
+            }
+
+            let entry = match self.start_stage(index, s, context) {
+                Ok(entry) => entry,
+                Err(e) => {
+                    if summary.record_failure(self.error_policy, &s.name, e) {
+                        continue;
+                    }
+                    return;
+                }
+            };
+            active.push(entry);
+
+            summary.executed += 1;
+            let stage = active[active.len() - 1].stage.as_ref();
+            let result = self.run_started(index, s, stage, context, env, listener);
+            match result {
+                Ok(StageFlow::Continue) => {}
+                Ok(StageFlow::Stop) => return,
+                Ok(StageFlow::Skip(n)) => skip = n,
+                Err(e) => {
+                    if !summary.record_failure(self.error_policy, &s.name, e) {
+                        return;
+                    }
+                }
+            }
+        }
+    }
+
+    // Builds the stage for entry `index`, from the instance cache if it has
+    // one, and sets it up to run against `context`.
+    fn start_stage(
+        &self,
+        index: usize,
+        s: &StageArgs<V>,
+        context: &C,
+    ) -> Result<ActiveStage<C>, StageError> {
+        let mut entry = self.cached_stage(index, s)?;
+        entry.stage.setup_with(self.setup_ctx(index, s));
+        entry.stage.setup_with_context(context);
+        Ok(entry)
+    }
+
+    // Runs a started stage, with the observer, hooks and listener told about
+    // it. Shared by run_active and StageIter.
+    fn run_started<L: Listener<C>>(
+        &self,
+        index: usize,
+        s: &StageArgs<V>,
+        stage: &dyn Stage<C = C>,
+        context: &mut C,
+        env: RunEnv<'_>,
+        listener: &mut L,
+    ) -> Result<StageFlow, StageError> {
+        let observer = self.observer.as_deref();
+        if let Some(observer) = observer {
+            observer.on_stage_start(&s.name, index);
+        }
+        hooks::before(&s.name, index);
+        listener.before_stage(index, &s.name);
+        let meta = StageMeta {
+            index,
+            total: self.file.stages.len(),
+            name: &s.name,
+            cancel: env.cancel,
+        };
+        listener.before_run(index, stage.is_mutating(), context);
+        let result = if self.catch_panics {
+            let run = || run_repeated(stage, s.repeat, context, env.shared, &meta);
+            panic::catch_unwind(AssertUnwindSafe(run)).unwrap_or_else(|payload| {
+                Err(StageError::Panicked {
+                    name: s.name.clone(),
+                    payload: panic_message(payload),
+                })
+            })
+        } else {
+            run_repeated(stage, s.repeat, context, env.shared, &meta)
+        };
+        listener.after_run(index, context);
+        listener.after_stage(index, &s.name, &result);
+        if result.is_ok() {
+            if let Some(value) = stage.result() {
+                listener.stage_result(index, value);
+            }
+        }
+        hooks::after(&s.name, index, &result);
+        if let Some(observer) = observer {
+            observer.on_stage_end(&s.name, index, &result);
+        }
+        result
+    }
+
+    // Calls undo on every enabled stage in reverse run order, once per
+    // repeat, stopping at the first error.
+    pub fn undo_stages(&self, context: &mut C) -> Result<(), StageError> {
+        let _hooks = self.hooks.enter();
+        self.validate()
+            .map_err(|names| StageError::UnknownStage { names })?;
+
+        let mut active = Vec::new();
+        let result = self.undo_active(context, &mut active);
+        active.iter_mut().rev().for_each(|s| s.teardown());
+        result
+    }
+
+    fn undo_active(
+        &self,
+        context: &mut C,
+        active: &mut Vec<BoxedStage<C>>,
+    ) -> Result<(), StageError> {
+        let mut repeats = Vec::new();
+        for (index, s) in self.file.enabled_stages()? {
+            let mut stage = self.build_stage(index, s)?;
+            stage.setup_with(self.setup_ctx(index, s));
+            active.push(stage);
+            repeats.push(s.repeat);
+        }
+
+        for (stage, repeat) in active.iter().zip(repeats).rev() {
+            for _ in 0..repeat {
+                stage.undo(context)?;
+            }
+        }
+        Ok(())
+    }
+
+    // Builds every stage in the file, disabled ones included, and discards
+    // it, collecting every error instead of stopping at the first. Unknown
+    // stage names are reported together ahead of any args errors.
+    pub fn check(&self) -> Result<(), Vec<StageError>> {
+        let mut errors = Vec::new();
+        if let Err(names) = self.validate() {
+            errors.push(StageError::UnknownStage { names });
+        }
+        if let Err(e) = self.file.check_exclusive() {
+            errors.push(e);
+        }
+        for (index, s) in self.file.stages.iter().enumerate() {
+            if self.is_registered(&s.name) || self.fallback.is_some() {
+                if let Err(e) = self.build_stage(index, s) {
+                    errors.push(e);
+                }
+            }
+        }
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors)
+        }
+    }
+
+    // Deserializes every enabled stage without setting up or running it, so
+    // config errors surface before anything executes.
+    pub fn plan(&self) -> Result<Vec<StagePlanEntry>, StageError> {
+        self.validate()
+            .map_err(|names| StageError::UnknownStage { names })?;
+
+        let mut entries = Vec::with_capacity(self.file.stages.len());
+        for (index, s) in self.file.stages.iter().enumerate() {
+            let enabled = s.enabled && s.repeat > 0;
+            let description = if enabled {
+                self.build_stage(index, s)?.describe()
+            } else {
+                None
+            };
+
+            entries.push(StagePlanEntry {
+                index,
+                name: s.name.clone(),
+                enabled,
+                description: description.unwrap_or_else(|| s.name.clone()),
+            });
+        }
+        Ok(entries)
+    }
+
+    // Deserializes and sets up every stage once so the pipeline can be run
+    // repeatedly. Stages are torn down when the pipeline is dropped.
+    pub fn build(&self) -> Result<CompiledPipeline<C>, StageError> {
+        self.validate()
+            .map_err(|names| StageError::UnknownStage { names })?;
+
+        let mut pipeline = CompiledPipeline {
+            stages: Vec::with_capacity(self.file.stages.len()),
+            total: self.file.stages.len(),
+        };
+        for (index, s) in self.file.enabled_stages()? {
+            let mut stage = self.build_stage(index, s)?;
+            stage.setup_with(self.setup_ctx(index, s));
+            pipeline.stages.push(CompiledStage {
+                stage,
+                repeat: s.repeat,
+                index,
+                name: s.name.clone(),
+            });
+        }
+        Ok(pipeline)
+    }
+
+    // Deserializes and sets up every enabled stage before any of them runs,
+    // keeping them for run_prepared. Stages set up by an earlier call are
+    // torn down first.
+    pub fn setup_all(&mut self) -> Result<(), StageError> {
+        self.prepared = None;
+        self.prepared = Some(self.build()?);
+        Ok(())
+    }
+
+    // Runs the stages set up by setup_all, without building any. Like a
+    // CompiledPipeline it ignores the error policy, observer, hooks,
+    // catch_panics and cancellation. Changing the file or registering a stage
+    // drops the prepared stages, and this fails with NotPrepared until
+    // setup_all is called again.
+    pub fn run_prepared(&self, context: &mut C) -> Result<(), StageError> {
+        match &self.prepared {
+            Some(pipeline) => pipeline.run(context),
+            None => Err(StageError::NotPrepared),
+        }
+    }
+
+    fn setup_ctx<'a>(&'a self, index: usize, s: &'a StageArgs<V>) -> StageSetupCtx<'a> {
+        StageSetupCtx {
+            index,
+            name: &s.name,
+            total: self.file.stages.len(),
+            retry_budget: self.retry_budget.as_ref(),
+        }
+    }
+
+    fn build_stage(&self, index: usize, s: &StageArgs<V>) -> Result<BoxedStage<C>, StageError> {
+        let args = self.stage_args(index, s)?;
+        self.deserialize_stage(index, s, args)
+    }
+
+    // The args handed to the stage's factory.
+    fn stage_args(&self, index: usize, s: &StageArgs<V>) -> Result<V, StageError> {
+        let args = s
+            .args_or_empty()
+            .map_err(|source| self.deserialize_error(index, s, source))?;
+        let args = match &self.preprocess {
+            Some(preprocess) => preprocess(args)?,
+            None => args,
+        };
+        self.check_fields(s, &args)?;
+        Ok(args)
+    }
+
+    fn deserialize_stage(
+        &self,
+        index: usize,
+        s: &StageArgs<V>,
+        args: V,
+    ) -> Result<BoxedStage<C>, StageError> {
+        let stage = match (self.resolve(&s.name), &self.fallback) {
+            (None, Some(fallback)) => fallback(&s.name, args),
+            (key, _) => registered(&self.deserialize_map, key.unwrap_or(&s.name))(index, args),
+        };
+        stage.map_err(|source| self.deserialize_error(index, s, source))
+    }
+
+    fn deserialize_error(&self, index: usize, s: &StageArgs<V>, source: BoxError) -> StageError {
+        StageError::Deserialize {
+            stage_name: s.name.clone(),
+            index,
+            position: self
+                .source
+                .as_deref()
+                .and_then(|text| locate::stage_position(text, index)),
+            source,
+        }
+    }
+
+    pub fn register_named<'a, S>(&mut self, name: &str) -> Result<&mut Self, RegisterError>
+    where
+        S: 'static + Stage<C = C> + Deserialize<'de>,
+    {
+        if self.is_taken(name) {
+            return Err(RegisterError::Duplicate(name.to_string()));
+        }
+        Ok(self.register_named_overwrite::<S>(name))
+    }
+
+    pub fn register<'a, S>(&mut self) -> Result<&mut Self, RegisterError>
+    where
+        S: 'static + Stage<C = C> + StageName + Deserialize<'de>,
+    {
+        self.register_named::<S>(&registry_key::<S>())
+    }
+
+    // Registers the stage as `prefix.name`, so stage sets from different
+    // libraries can share a file without their names colliding.
+    pub fn register_namespaced<'a, S>(&mut self, prefix: &str) -> Result<&mut Self, RegisterError>
+    where
+        S: 'static + Stage<C = C> + StageName + Deserialize<'de>,
+    {
+        self.register_named::<S>(&format!("{}.{}", prefix, registry_key::<S>()))
+    }
+
+    // Replaces any factory already registered under `name`.
+    pub fn register_named_overwrite<'a, S>(&mut self, name: &str) -> &mut Self
+    where
+        S: 'static + Stage<C = C> + Deserialize<'de>,
+    {
+        self.insert_factory::<S>(
+            name,
+            Rc::new(|_, v| {
+                let stage = S::deserialize(v).map_err(|e| e.to_string())?;
+                Ok(Box::new(stage))
+            }),
+        )
+    }
+
+    // Like register_named, but the stage is handed the index of its entry
+    // through WithIndex once deserialized.
+    pub fn register_named_indexed<'a, S>(&mut self, name: &str) -> Result<&mut Self, RegisterError>
+    where
+        S: 'static + Stage<C = C> + WithIndex + Deserialize<'de>,
+    {
+        if self.is_taken(name) {
+            return Err(RegisterError::Duplicate(name.to_string()));
+        }
+        Ok(self.insert_factory::<S>(
+            name,
+            Rc::new(|index, v| {
+                let stage = S::deserialize(v).map_err(|e| e.to_string())?;
+                Ok(Box::new(stage.with_index(index)))
+            }),
+        ))
+    }
+
+    pub fn register_indexed<'a, S>(&mut self) -> Result<&mut Self, RegisterError>
+    where
+        S: 'static + Stage<C = C> + StageName + WithIndex + Deserialize<'de>,
+    {
+        self.register_named_indexed::<S>(&registry_key::<S>())
+    }
+
+    fn insert_factory<S>(&mut self, name: &str, factory: FnDeserializeStage<C, V>) -> &mut Self
+    where
+        S: Deserialize<'de>,
+    {
+        // Under normalization an overwrite replaces whichever key `name`
+        // collides with, rather than sitting next to it.
+        if let Some(old) = self.taken_key(name).filter(|k| *k != name) {
+            let old = old.to_string();
+            Rc::make_mut(&mut self.deserialize_map).remove(&old);
+            Rc::make_mut(&mut self.schemas).remove(&old);
+            self.evict_cached(&old);
+        }
+        Rc::make_mut(&mut self.deserialize_map).insert(name.to_string(), factory);
+        self.prepared = None;
+        Rc::make_mut(&mut self.schemas)
+            .insert(name.to_string(), LazyProbe::new(schema::stage_schema::<S>));
+        self.evict_cached(name);
+        self
+    }
+
+    pub fn register_overwrite<'a, S>(&mut self) -> &mut Self
+    where
+        S: 'static + Stage<C = C> + StageName + Deserialize<'de>,
+    {
+        self.register_named_overwrite::<S>(&registry_key::<S>())
+    }
+
+    // Makes the stage registered as `existing` available as `alias` too.
+    pub fn alias(&mut self, existing: &str, alias: &str) -> Result<&mut Self, RegisterError> {
+        if self.is_taken(alias) {
+            return Err(RegisterError::Duplicate(alias.to_string()));
+        }
+        let key = self
+            .resolve(existing)
+            .ok_or_else(|| RegisterError::NotRegistered(existing.to_string()))?
+            .to_string();
+
+        let factory = Rc::clone(&self.deserialize_map[&key]);
+        Rc::make_mut(&mut self.deserialize_map).insert(alias.to_string(), factory);
+        self.prepared = None;
+        if let Some(schema) = self.schemas.get(&key).cloned() {
+            Rc::make_mut(&mut self.schemas).insert(alias.to_string(), schema);
+        }
+        self.evict_cached(alias);
+        Ok(self)
+    }
+
+    // Registers a closure that builds a stage from its raw args, for stages
+    // that don't deserialize themselves (see `stages::stage_fn`).
+    pub fn register_fn<S, F>(&mut self, name: &str, factory: F) -> Result<&mut Self, RegisterError>
+    where
+        S: 'static + Stage<C = C>,
+        F: 'static + Fn(V) -> Result<S, BoxError>,
+    {
+        if self.is_taken(name) {
+            return Err(RegisterError::Duplicate(name.to_string()));
+        }
+        Rc::make_mut(&mut self.deserialize_map).insert(
+            name.to_string(),
+            Rc::new(move |_, v| Ok(Box::new(factory(v)?))),
+        );
+        self.prepared = None;
+        Ok(self)
+    }
+
+    // Builds every stage whose name has no factory, given the name and args,
+    // instead of failing the run with StageError::UnknownStage. Replaces any
+    // earlier fallback.
+    pub fn register_default<S, F>(&mut self, factory: F) -> &mut Self
+    where
+        S: 'static + Stage<C = C>,
+        F: 'static + Fn(&str, V) -> Result<S, BoxError>,
+    {
+        self.fallback = Some(Rc::new(move |name, v| Ok(Box::new(factory(name, v)?))));
+        self.prepared = None;
+        self.evict_unregistered();
+        self
+    }
+}
+
+#[cfg(feature = "yaml")]
+impl<C> StageManager<C, YamlValue> {
+    // Keeps `s` so args errors can report their line and column.
+    pub fn from_yaml_str(s: &str) -> Result<Self, serde_yaml::Error> {
+        let mut m = Self::from_file(serde_yaml::from_str(s)?);
+        m.source = Some(s.to_string());
+        Ok(m)
+    }
+
+    pub fn from_yaml_reader<R: std::io::Read>(r: R) -> Result<Self, serde_yaml::Error> {
+        serde_yaml::from_reader(r).map(Self::from_file)
+    }
+}
+
+#[cfg(feature = "yaml")]
+impl<C, V: Serialize> StageManager<C, V> {
+    // Writes the stage file back out as YAML, including any changes made
+    // through transform_file. Fields left at their defaults are omitted.
+    pub fn to_yaml_string(&self) -> Result<String, StageError> {
+        serde_yaml::to_string(&self.file).map_err(StageError::custom)
+    }
+}
+
+impl<'de, C, V> Stage for StageManager<C, V>
+where
+    V: Deserialize<'de> + Deserializer<'de> + Clone
+{
+    type C = C;
+
+    fn run(&self, c: &mut Self::C) -> Result<(), StageError> {
+        self.run_stages(c)
+    }
+
+    // Nested managers hand the parent's shared config on to their stages.
+    fn run_shared(&self, c: &mut Self::C, shared: &dyn Any) -> Result<StageFlow, StageError> {
+        self.run_with(c, shared, |_, _| Ok(()), &mut ())
+            .map(|()| StageFlow::Continue)
+    }
+
+    fn undo(&self, c: &mut Self::C) -> Result<(), StageError> {
+        self.undo_stages(c)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use serde::Serialize;
+    use serde_yaml::Value;
+
+    use super::*;
+
+    #[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+    pub(crate) struct CalcContext {
+        pub(crate) x: i64,
+    }
+
+    #[derive(Debug, Deserialize)]
+    pub(crate) struct Add {
+        pub(crate) x: i64,
+    }
+
+    impl Stage for Add {
+        type C = CalcContext;
+
+        fn run(&self, c: &mut Self::C) -> Result<(), StageError> {
+            c.x += self.x;
+            Ok(())
+        }
+
+        fn undo(&self, c: &mut Self::C) -> Result<(), StageError> {
+            c.x -= self.x;
+            Ok(())
+        }
+
+        fn describe(&self) -> Option<String> {
+            Some(format!("add {}", self.x))
+        }
+    }
+
+    impl StageName for Add {
+        fn stage_name() -> &'static str {
+            "add"
+        }
+    }
+
+    #[derive(Debug, Deserialize)]
+    pub(crate) struct Mul {
+        pub(crate) x: i64,
+    }
+
+    impl StageName for Mul {
+        fn stage_name() -> &'static str {
+            "mul"
+        }
+    }
+
+    impl Stage for Mul {
+        type C = CalcContext;
+
+        fn run(&self, c: &mut Self::C) -> Result<(), StageError> {
+            c.x *= self.x;
+            Ok(())
+        }
+
+        fn undo(&self, c: &mut Self::C) -> Result<(), StageError> {
+            c.x /= self.x;
+            Ok(())
+        }
+    }
+
+    #[derive(Debug, Deserialize)]
+    struct Fail {
+        msg: String,
+    }
+
+    impl StageName for Fail {
+        fn stage_name() -> &'static str {
+            "fail"
+        }
+    }
+
+    impl Stage for Fail {
+        type C = CalcContext;
+
+        fn run(&self, _c: &mut Self::C) -> Result<(), StageError> {
+            Err(StageError::custom(self.msg.clone()))
+        }
+    }
+
+    #[derive(Debug, Deserialize)]
+    struct Gate {
+        max: i64,
+    }
+
+    impl StageName for Gate {
+        fn stage_name() -> &'static str {
+            "gate"
+        }
+    }
+
+    impl Stage for Gate {
+        type C = CalcContext;
+
+        fn run(&self, c: &mut Self::C) -> Result<(), StageError> {
+            self.run_flow(c).map(|_| ())
+        }
+
+        fn run_flow(&self, c: &mut Self::C) -> Result<StageFlow, StageError> {
+            if c.x > self.max {
+                Ok(StageFlow::Stop)
+            } else {
+                Ok(StageFlow::Continue)
+            }
+        }
+    }
+
+    #[derive(Debug, Deserialize)]
+    struct SkipNext {
+        n: usize,
+    }
+
+    impl StageName for SkipNext {
+        fn stage_name() -> &'static str {
+            "skip"
+        }
+    }
+
+    impl Stage for SkipNext {
+        type C = CalcContext;
+
+        fn run(&self, c: &mut Self::C) -> Result<(), StageError> {
+            self.run_flow(c).map(|_| ())
+        }
+
+        fn run_flow(&self, _c: &mut Self::C) -> Result<StageFlow, StageError> {
+            Ok(StageFlow::Skip(self.n))
+        }
+    }
+
+    thread_local! {
+        static LOG: std::cell::RefCell<Vec<String>> = Default::default();
+    }
+
+    fn log(event: String) {
+        LOG.with(|l| l.borrow_mut().push(event));
+    }
+
+    fn take_log() -> Vec<String> {
+        LOG.with(|l| l.borrow_mut().drain(..).collect())
+    }
+
+    #[derive(Debug, Deserialize)]
+    struct Lifecycle {
+        id: String,
+    }
+
+    impl StageName for Lifecycle {
+        fn stage_name() -> &'static str {
+            "lifecycle"
+        }
+    }
+
+    impl Stage for Lifecycle {
+        type C = CalcContext;
+
+        fn setup(&mut self) {
+            log(format!("setup {}", self.id));
+        }
+
+        fn run(&self, _c: &mut Self::C) -> Result<(), StageError> {
+            log(format!("run {}", self.id));
+            Ok(())
+        }
+
+        fn teardown(&mut self) {
+            log(format!("teardown {}", self.id));
+        }
+    }
+
+    #[test]
+    fn empty_pipeline_is_a_no_op() {
+        let file: StageFile<Value> = serde_yaml::from_str("stages: []").unwrap();
+        let m = StageManager::from_file(file);
+
+        let mut c = CalcContext { x: 1 };
+        m.run_stages(&mut c).unwrap();
+        assert_eq!(c.x, 1);
+    }
+
+    #[test]
+    fn empty_pipeline_can_be_required_to_fail() {
+        let file: StageFile<Value> = serde_yaml::from_str("stages: []").unwrap();
+        let mut m = StageManager::from_file(file);
+        m.require_non_empty(true);
+
+        let mut c = CalcContext { x: 1 };
+        assert!(matches!(
+            m.run_stages(&mut c),
+            Err(StageError::EmptyPipeline)
+        ));
+    }
+
+    #[test]
+    fn setup_all_runs_before_any_stage() {
+        let yaml_str = r#"
+        stages:
+        - name: lifecycle
+          args:
+            id: a
+        - name: lifecycle
+          args:
+            id: b
+        "#;
+
+        let file: StageFile<Value> = serde_yaml::from_str(yaml_str).unwrap();
+        let mut m = StageManager::from_file(file);
+        m.register::<Lifecycle>().unwrap();
+
+        let mut c = CalcContext::default();
+        assert!(matches!(
+            m.run_prepared(&mut c),
+            Err(StageError::NotPrepared)
+        ));
+
+        m.setup_all().unwrap();
+        m.run_prepared(&mut c).unwrap();
+        m.run_prepared(&mut c).unwrap();
+        m.transform_file(|stages| stages.truncate(1));
+        assert!(matches!(
+            m.run_prepared(&mut c),
+            Err(StageError::NotPrepared)
+        ));
+        drop(m);
+
+        assert_eq!(
+            take_log(),
+            vec![
+                "setup a",
+                "setup b",
+                "run a",
+                "run b",
+                "run a",
+                "run b",
+                "teardown b",
+                "teardown a"
+            ]
+        );
+    }
+
+    #[test]
+    fn calc_add_pipeline() {
+        let yaml_str = r#"
+        stages:
+        - name: add
+          args:
+            x: 1
+        - name: add
+          args:
+            x: 2
+        - name: add
+          args:
+            x: 5
+        "#;
+
+        let file: StageFile<Value> = serde_yaml::from_str(yaml_str).unwrap();
+        let mut m = StageManager::from_file(file);
+        m.register::<Add>().unwrap();
+
+        let mut c = CalcContext { x: 1 };
+        m.run(&mut c).unwrap();
+
+        assert_eq!(c.x, 9);
+    }
+
+    #[test]
+    fn calc_mul_pipeline() {
+        let yaml_str = r#"
+        stages:
+        - name: mul
+          args:
+            x: 1
+        - name: mul
+          args:
+            x: 2
+        - name: mul
+          args:
+            x: 5
+        "#;
+
+        let file: StageFile<Value> = serde_yaml::from_str(yaml_str).unwrap();
+        let mut m = StageManager::from_file(file);
+        m.register::<Mul>().unwrap();
+
+        let mut c = CalcContext { x: 1 };
+        m.run(&mut c).unwrap();
+
+        assert_eq!(c.x, 10);
+    }
+
+    #[test]
+    fn calc_add_mul_pipeline() {
+        let yaml_str = r#"
+        stages:
+        - name: mul
+          args:
+            x: 1
+        - name: add
+          args:
+            x: 2
+        - name: mul
+          args:
+            x: 5
+        "#;
+
+        let file: StageFile<Value> = serde_yaml::from_str(yaml_str).unwrap();
+        let mut m = StageManager::from_file(file);
+        m.register::<Mul>().unwrap().register::<Add>().unwrap();
+
+        let mut c = CalcContext { x: 1 };
+        m.run(&mut c).unwrap();
+
+        assert_eq!(c.x, 15);
+    }
+
+    #[test]
+    fn stage_recursive_pipeline() {
+        #[derive(Deserialize)]
+        struct Top {
+            #[serde(flatten)]
+            stages: StageManager<CalcContext, Value>,
+        }
+
+        impl Stage for Top {
+            type C = CalcContext;
+
+            fn setup(&mut self) {
+                self.stages
+                    .register::<Add>()
+                    .unwrap()
+                    .register::<Mul>()
+                    .unwrap();
+            }
+
+            fn run(&self, c: &mut Self::C) -> Result<(), StageError> {
+                self.stages.run(c)
+            }
+        }
+
+        impl StageName for Top {
+            fn stage_name() -> &'static str {
+                "top"
+            }
+        }
+
+        let yaml_str = r#"
+        stages:
+        - name: top 
+          args:
+            stages:
+            - name: add
+              args:
+                x: 1
+            - name: add
+              args:
+                x: 4
+        - name: mul
+          args:
+            x: 2
+        "#;
+
+        let file: StageFile<Value> = serde_yaml::from_str(yaml_str).unwrap();
+        let mut m = StageManager::from_file(file);
+        m.register::<Mul>()
+            .unwrap()
+            .register::<Add>()
+            .unwrap()
+            .register::<Top>()
+            .unwrap();
+
+        let mut c = CalcContext { x: 1 };
+        m.run(&mut c).unwrap();
+
+        assert_eq!(c.x, 12);
+    }
+
+    #[test]
+    fn failing_stage_aborts_pipeline() {
+        let yaml_str = r#"
+        stages:
+        - name: add
+          args:
+            x: 1
+        - name: fail
+          args:
+            msg: boom
+        - name: add
+          args:
+            x: 5
+        "#;
+
+        let file: StageFile<Value> = serde_yaml::from_str(yaml_str).unwrap();
+        let mut m = StageManager::from_file(file);
+        m.register::<Add>().unwrap().register::<Fail>().unwrap();
+
+        let mut c = CalcContext { x: 1 };
+        let err = m.run(&mut c).unwrap_err();
+
+        assert_eq!(err.to_string(), "stage failed: boom");
+        assert_eq!(c.x, 2);
+    }
+
+    #[test]
+    fn register_rejects_duplicate_name() {
+        let file: StageFile<Value> = serde_yaml::from_str("stages: []").unwrap();
+        let mut m = StageManager::<CalcContext, Value>::from_file(file);
+        m.register::<Add>().unwrap();
+
+        let err = m.register::<Add>().unwrap_err();
+        assert_eq!(err, RegisterError::Duplicate("add".to_string()));
+
+        m.register_overwrite::<Add>();
+    }
+
+    #[test]
+    fn bad_args_report_stage_and_index() {
+        let yaml_str = r#"
+        stages:
+        - name: add
+          args:
+            x: 1
+        - name: add
+          args:
+            x: "not a number"
+        "#;
+
+        let file: StageFile<Value> = serde_yaml::from_str(yaml_str).unwrap();
+        let mut m = StageManager::from_file(file);
+        m.register::<Add>().unwrap();
+
+        let mut c = CalcContext { x: 1 };
+        let err = m.run(&mut c).unwrap_err();
+
+        match &err {
+            StageError::Deserialize {
+                stage_name, index, ..
+            } => {
+                assert_eq!(stage_name, "add");
+                assert_eq!(*index, 1);
+            }
+            e => panic!("unexpected error: {}", e),
+        }
+        assert!(err
+            .to_string()
+            .starts_with("invalid args for stage `add` at index 1:"));
+        assert_eq!(c.x, 2);
+    }
+
+    #[test]
+    fn unknown_stage_is_reported() {
+        let yaml_str = r#"
+        stages:
+        - name: add
+          args:
+            x: 1
+        - name: sub
+          args:
+            x: 2
+        "#;
+
+        let file: StageFile<Value> = serde_yaml::from_str(yaml_str).unwrap();
+        let mut m = StageManager::from_file(file);
+        m.register::<Add>().unwrap();
+
+        assert_eq!(m.validate(), Err(vec!["sub".to_string()]));
+
+        let mut c = CalcContext { x: 1 };
+        match m.run(&mut c) {
+            Err(StageError::UnknownStage { names }) => assert_eq!(names, vec!["sub"]),
+            r => panic!("unexpected result: {:?}", r),
+        }
+        assert_eq!(c.x, 1);
+    }
+
+    #[test]
+    fn teardown_runs_in_reverse_order() {
+        let yaml_str = r#"
+        stages:
+        - name: lifecycle
+          args:
+            id: a
+        - name: lifecycle
+          args:
+            id: b
+        - name: fail
+          args:
+            msg: boom
+        - name: lifecycle
+          args:
+            id: c
+        "#;
+
+        let file: StageFile<Value> = serde_yaml::from_str(yaml_str).unwrap();
+        let mut m = StageManager::from_file(file);
+        m.register::<Lifecycle>()
+            .unwrap()
+            .register::<Fail>()
+            .unwrap();
+
+        let mut c = CalcContext { x: 1 };
+        assert!(m.run(&mut c).is_err());
+
+        assert_eq!(
+            take_log(),
+            vec![
+                "setup a",
+                "run a",
+                "setup b",
+                "run b",
+                "teardown b",
+                "teardown a"
+            ]
+        );
+    }
+
+    #[test]
+    fn compiled_pipeline_runs_repeatedly() {
+        let yaml_str = r#"
+        stages:
+        - name: mul
+          args:
+            x: 1
+        - name: add
+          args:
+            x: 2
+        - name: mul
+          args:
+            x: 5
+        "#;
+
+        let file: StageFile<Value> = serde_yaml::from_str(yaml_str).unwrap();
+        let mut m = StageManager::from_file(file);
+        m.register::<Mul>().unwrap().register::<Add>().unwrap();
+
+        let pipeline = m.build().unwrap();
+
+        let mut first = CalcContext { x: 1 };
+        pipeline.run(&mut first).unwrap();
+        let mut second = CalcContext { x: 1 };
+        pipeline.run(&mut second).unwrap();
+
+        assert_eq!(first.x, 15);
+        assert_eq!(second.x, first.x);
+    }
+
+    #[test]
+    fn compiled_pipeline_runs_batches() {
+        let yaml_str = r#"
+        stages:
+        - name: add
+          args:
+            x: 2
+        - name: mul
+          args:
+            x: 3
+        "#;
+
+        let file: StageFile<Value> = serde_yaml::from_str(yaml_str).unwrap();
+        let mut m = StageManager::from_file(file);
+        m.register::<Mul>().unwrap().register::<Add>().unwrap();
+        let pipeline = m.build().unwrap();
+
+        let mut contexts: Vec<_> = (0..100).map(|x| CalcContext { x }).collect();
+        let results = pipeline.run_batch(&mut contexts);
+
+        assert!(results.iter().all(Result::is_ok));
+        for (x, c) in contexts.iter().enumerate() {
+            assert_eq!(c.x, (x as i64 + 2) * 3);
+        }
+    }
+
+    #[cfg(feature = "yaml")]
+    #[test]
+    fn from_yaml_str_loads_pipeline() {
+        let yaml_str = r#"
+        stages:
+        - name: add
+          args:
+            x: 1
+        - name: add
+          args:
+            x: 2
+        - name: add
+          args:
+            x: 5
+        "#;
+
+        let mut m = StageManager::from_yaml_str(yaml_str).unwrap();
+        m.register::<Add>().unwrap();
+
+        let mut c = CalcContext { x: 1 };
+        m.run(&mut c).unwrap();
+
+        assert_eq!(c.x, 9);
+    }
+
+    #[cfg(feature = "yaml")]
+    #[test]
+    fn from_yaml_reader_loads_pipeline() {
+        let yaml_str = "stages:\n- name: add\n  args:\n    x: 4\n";
+
+        let mut m = StageManager::from_yaml_reader(yaml_str.as_bytes()).unwrap();
+        m.register::<Add>().unwrap();
+
+        let mut c = CalcContext { x: 1 };
+        m.run(&mut c).unwrap();
+
+        assert_eq!(c.x, 5);
+    }
+
+    #[test]
+    fn disabled_stage_is_skipped() {
+        let yaml_str = r#"
+        stages:
+        - name: add
+          args:
+            x: 2
+        - name: mul
+          enabled: false
+          args:
+            x: 10
+        - name: add
+          enabled: true
+          args:
+            x: 1
+        "#;
+
+        let file: StageFile<Value> = serde_yaml::from_str(yaml_str).unwrap();
+        let mut m = StageManager::from_file(file);
+        m.register::<Mul>().unwrap().register::<Add>().unwrap();
+
+        let mut c = CalcContext { x: 1 };
+        m.run(&mut c).unwrap();
+        assert_eq!(c.x, 4);
+
+        let mut c = CalcContext { x: 1 };
+        m.build().unwrap().run(&mut c).unwrap();
+        assert_eq!(c.x, 4);
+    }
+
+    #[test]
+    fn gate_stops_pipeline() {
+        let yaml_str = r#"
+        stages:
+        - name: add
+          args:
+            x: 5
+        - name: gate
+          args:
+            max: 3
+        - name: mul
+          args:
+            x: 10
+        - name: add
+          args:
+            x: 1
+        "#;
+
+        let file: StageFile<Value> = serde_yaml::from_str(yaml_str).unwrap();
+        let mut m = StageManager::from_file(file);
+        m.register::<Add>()
+            .unwrap()
+            .register::<Mul>()
+            .unwrap()
+            .register::<Gate>()
+            .unwrap();
+
+        let mut c = CalcContext { x: 1 };
+        m.run(&mut c).unwrap();
+        assert_eq!(c.x, 6);
+
+        let mut c = CalcContext { x: -10 };
+        m.build().unwrap().run(&mut c).unwrap();
+        assert_eq!(c.x, -49);
+    }
+
+    #[test]
+    fn skip_flow_skips_next_stages() {
+        let yaml_str = r#"
+        stages:
+        - name: skip
+          args:
+            n: 2
+        - name: add
+          args:
+            x: 5
+        - name: mul
+          enabled: false
+          args:
+            x: 3
+        - name: mul
+          args:
+            x: 10
+        - name: add
+          args:
+            x: 1
+        "#;
+
+        let file: StageFile<Value> = serde_yaml::from_str(yaml_str).unwrap();
+        let mut m = StageManager::from_file(file);
+        m.register::<Add>()
+            .unwrap()
+            .register::<Mul>()
+            .unwrap()
+            .register::<SkipNext>()
+            .unwrap();
+
+        let mut c = CalcContext { x: 1 };
+        m.run(&mut c).unwrap();
+        assert_eq!(c.x, 2);
+
+        let mut c = CalcContext { x: 1 };
+        m.build().unwrap().run(&mut c).unwrap();
+        assert_eq!(c.x, 2);
+    }
+
+    #[test]
+    fn repeat_runs_stage_multiple_times() {
+        let yaml_str = r#"
+        stages:
+        - name: lifecycle
+          repeat: 2
+          args:
+            id: a
+        - name: add
+          repeat: 3
+          args:
+            x: 2
+        - name: mul
+          repeat: 0
+          args:
+            x: 10
+        "#;
+
+        let file: StageFile<Value> = serde_yaml::from_str(yaml_str).unwrap();
+        let mut m = StageManager::from_file(file);
+        m.register::<Add>()
+            .unwrap()
+            .register::<Mul>()
+            .unwrap()
+            .register::<Lifecycle>()
+            .unwrap();
+
+        let mut c = CalcContext { x: 1 };
+        m.run(&mut c).unwrap();
+        assert_eq!(c.x, 7);
+        assert_eq!(take_log(), vec!["setup a", "run a", "run a", "teardown a"]);
+
+        let mut c = CalcContext { x: 1 };
+        m.build().unwrap().run(&mut c).unwrap();
+        assert_eq!(c.x, 7);
+    }
+
+    #[test]
+    fn validate_sorts_missing_names() {
+        let yaml_str = r#"
+        stages:
+        - name: sub
+        - name: add
+          args:
+            x: 1
+        - name: div
+        - name: sub
+        "#;
+
+        let file: StageFile<Value> = serde_yaml::from_str(yaml_str).unwrap();
+        let mut m = StageManager::<CalcContext, Value>::from_file(file);
+        m.register::<Add>().unwrap();
+
+        assert_eq!(m.validate().unwrap_err(), vec!["div", "sub"]);
+    }
+
+    #[test]
+    fn registered_names_lists_factories() {
+        let file: StageFile<Value> = serde_yaml::from_str("stages: []").unwrap();
+        let mut m = StageManager::<CalcContext, Value>::from_file(file);
+        m.register::<Add>().unwrap().register::<Mul>().unwrap();
+
+        let names: Vec<&str> = m.registered_names().collect();
+        assert_eq!(names, vec!["add", "mul"]);
+
+        assert!(m.is_registered("add"));
+        assert!(!m.is_registered("sub"));
+    }
+
+    #[test]
+    fn timed_run_reports_each_stage() {
+        let yaml_str = r#"
+        stages:
+        - name: mul
+          args:
+            x: 1
+        - name: add
+          enabled: false
+          args:
+            x: 2
+        - name: add
+          args:
+            x: 5
+        "#;
+
+        let file: StageFile<Value> = serde_yaml::from_str(yaml_str).unwrap();
+        let mut m = StageManager::from_file(file);
+        m.register::<Mul>().unwrap().register::<Add>().unwrap();
+
+        let mut c = CalcContext { x: 1 };
+        let (result, timings) = m.run_stages_timed(&mut c);
+        result.unwrap();
+
+        let executed: Vec<(usize, &str)> =
+            timings.iter().map(|t| (t.index, t.name.as_str())).collect();
+        assert_eq!(executed, vec![(0, "mul"), (2, "add")]);
+        assert_eq!(c.x, 6);
+    }
+
+    #[test]
+    fn timed_run_keeps_timings_on_failure() {
+        let yaml_str = r#"
+        stages:
+        - name: add
+          args:
+            x: 1
+        - name: fail
+          args:
+            msg: boom
+        - name: add
+          args:
+            x: 5
+        "#;
+
+        let file: StageFile<Value> = serde_yaml::from_str(yaml_str).unwrap();
+        let mut m = StageManager::from_file(file);
+        m.register::<Add>().unwrap().register::<Fail>().unwrap();
+
+        let mut c = CalcContext { x: 1 };
+        let (result, timings) = m.run_stages_timed(&mut c);
+
+        assert!(result.is_err());
+        let executed: Vec<(usize, &str)> =
+            timings.iter().map(|t| (t.index, t.name.as_str())).collect();
+        assert_eq!(executed, vec![(0, "add"), (1, "fail")]);
+    }
+
+    #[test]
+    fn plan_lists_stages_without_running() {
+        let yaml_str = r#"
+        stages:
+        - name: mul
+          args:
+            x: 1
+        - name: add
+          args:
+            x: 2
+        - name: lifecycle
+          enabled: false
+          args:
+            id: a
+        "#;
+
+        let file: StageFile<Value> = serde_yaml::from_str(yaml_str).unwrap();
+        let mut m = StageManager::from_file(file);
+        m.register::<Mul>()
+            .unwrap()
+            .register::<Add>()
+            .unwrap()
+            .register::<Lifecycle>()
+            .unwrap();
+
+        let plan = m.plan().unwrap();
+
+        let summary: Vec<(usize, &str, bool, &str)> = plan
+            .iter()
+            .map(|e| (e.index, e.name.as_str(), e.enabled, e.description.as_str()))
+            .collect();
+        assert_eq!(
+            summary,
+            vec![
+                (0, "mul", true, "mul"),
+                (1, "add", true, "add 2"),
+                (2, "lifecycle", false, "lifecycle"),
+            ]
+        );
+        assert!(take_log().is_empty());
+    }
+
+    #[test]
+    fn plan_surfaces_bad_args() {
+        let yaml_str = r#"
+        stages:
+        - name: add
+          args:
+            x: nope
+        "#;
+
+        let file: StageFile<Value> = serde_yaml::from_str(yaml_str).unwrap();
+        let mut m = StageManager::<CalcContext, Value>::from_file(file);
+        m.register::<Add>().unwrap();
+
+        assert!(matches!(
+            m.plan(),
+            Err(StageError::Deserialize { index: 0, .. })
+        ));
+    }
+
+    fn names(names: &[&str]) -> HashSet<String> {
+        names.iter().map(|n| n.to_string()).collect()
+    }
+
+    #[test]
+    fn filtered_run_selects_stages() {
+        let yaml_str = r#"
+        stages:
+        - name: add
+          args:
+            x: 2
+        - name: mul
+          args:
+            x: 3
+        - name: add
+          args:
+            x: 5
+        "#;
+
+        let file: StageFile<Value> = serde_yaml::from_str(yaml_str).unwrap();
+        let mut m = StageManager::from_file(file);
+        m.register::<Mul>().unwrap().register::<Add>().unwrap();
+
+        let run = |filter: StageFilter| {
+            let mut c = CalcContext { x: 1 };
+            m.run_stages_filtered(&mut c, &filter).unwrap();
+            c.x
+        };
+
+        assert_eq!(run(StageFilter::Range(0..1)), 3);
+        assert_eq!(run(StageFilter::Only(names(&["mul"]))), 3);
+        assert_eq!(run(StageFilter::Except(names(&["mul"]))), 8);
+    }
+
+    #[test]
+    fn run_into_maps_contexts() {
+        let yaml_str = r#"
+        stages:
+        - name: add
+          args:
+            x: 2
+        - name: mul
+          args:
+            x: 3
+        "#;
+
+        let file: StageFile<Value> = serde_yaml::from_str(yaml_str).unwrap();
+        let mut m = StageManager::from_file(file);
+        m.register::<Mul>().unwrap().register::<Add>().unwrap();
+
+        let results: Vec<i64> = (0..3)
+            .map(|x| m.run_into(CalcContext { x }).map(|c| c.x))
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert_eq!(results, [6, 9, 12]);
+    }
+
+    #[test]
+    fn run_stages_tracked_reports_progress() {
+        let yaml_str = r#"
+        stages:
+        - name: add
+          args:
+            x: 2
+        - name: fail
+          args:
+            msg: boom
+        - name: add
+          args:
+            x: 5
+        "#;
+
+        let file: StageFile<Value> = serde_yaml::from_str(yaml_str).unwrap();
+        let mut m = StageManager::from_file(file);
+        m.register::<Add>().unwrap().register::<Fail>().unwrap();
+
+        let mut c = CalcContext { x: 1 };
+        let err = m.run_stages_tracked(&mut c).unwrap_err();
+        assert_eq!(err.completed, 1);
+        assert_eq!(err.failed_index, Some(1));
+        assert_eq!(err.failed_stage.as_deref(), Some("fail"));
+        assert_eq!(c.x, 3);
+    }
+
+    #[test]
+    fn run_stages_from_resumes() {
+        let yaml_str = r#"
+        stages:
+        - name: add
+          args:
+            x: 2
+        - name: mul
+          args:
+            x: 3
+        - name: add
+          args:
+            x: 5
+        "#;
+
+        let file: StageFile<Value> = serde_yaml::from_str(yaml_str).unwrap();
+        let mut m = StageManager::from_file(file);
+        m.register::<Mul>().unwrap().register::<Add>().unwrap();
+
+        let run = |start: ResumePoint| {
+            let mut c = CalcContext { x: 1 };
+            m.run_stages_from(&mut c, start).map(|()| c.x)
+        };
+
+        assert_eq!(run(ResumePoint::Index(1)).unwrap(), 8);
+        assert_eq!(run(ResumePoint::Name("mul".to_string())).unwrap(), 8);
+        assert!(matches!(
+            run(ResumePoint::Index(3)),
+            Err(StageError::NoStageAt { index: 3 })
+        ));
+    }
+
+    #[test]
+    fn run_stages_from_follows_run_order() {
+        let yaml_str = r#"
+        stages:
+        - name: add
+          args:
+            x: 2
+        - name: mul
+          priority: 1
+          args:
+            x: 3
+        - name: add
+          args:
+            x: 5
+        "#;
+
+        let file: StageFile<Value> = serde_yaml::from_str(yaml_str).unwrap();
+        let mut m = StageManager::from_file(file);
+        m.register::<Mul>().unwrap().register::<Add>().unwrap();
+
+        // mul runs first, so resuming at entry 0 runs both adds but not mul.
+        let mut c = CalcContext { x: 1 };
+        m.run_stages_from(&mut c, ResumePoint::Index(0)).unwrap();
+        assert_eq!(c.x, 8);
+
+        let mut c = CalcContext { x: 1 };
+        m.run_stages_from(&mut c, ResumePoint::Name("mul".to_string()))
+            .unwrap();
+        assert_eq!(c.x, 10);
+    }
+
+    #[test]
+    fn run_summary_counts_stages() {
+        let yaml_str = r#"
+        stages:
+        - name: add
+          args:
+            x: 2
+        - name: mul
+          enabled: false
+          args:
+            x: 3
+        - name: add
+          args:
+            x: 5
+        "#;
+
+        let file: StageFile<Value> = serde_yaml::from_str(yaml_str).unwrap();
+        let mut m = StageManager::from_file(file);
+        m.register::<Mul>().unwrap().register::<Add>().unwrap();
+
+        let mut c = CalcContext { x: 1 };
+        let summary = m.run_stages_summary(&mut c);
+        assert_eq!(summary.executed, 2);
+        assert_eq!(summary.skipped, 1);
+        assert!(summary.failed.is_none());
+        assert_eq!(c.x, 8);
+
+        m.register::<Fail>().unwrap();
+        let file: StageFile<Value> = serde_yaml::from_str(
+            r#"
+        stages:
+        - name: fail
+          args:
+            msg: boom
+        "#,
+        )
+        .unwrap();
+        m.file = file;
+        let summary = m.run_stages_summary(&mut c);
+        assert_eq!(summary.executed, 1);
+        match summary.failed {
+            Some((name, StageError::Custom(e))) => {
+                assert_eq!(name, "fail");
+                assert_eq!(e.to_string(), "boom");
+            }
+            other => panic!("expected a failure, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn caught_panics_become_errors() {
+        #[derive(Deserialize)]
+        struct Explode {}
+
+        impl Stage for Explode {
+            type C = CalcContext;
+
+            fn run(&self, _c: &mut Self::C) -> Result<(), StageError> {
+                panic!("kaboom {}", 1)
+            }
+        }
+
+        let yaml_str = r#"
+        stages:
+        - name: lifecycle
+          args:
+            id: a
+        - name: explode
+          args: {}
+        - name: add
+          args:
+            x: 1
+        "#;
+
+        let file: StageFile<Value> = serde_yaml::from_str(yaml_str).unwrap();
+        let mut m = StageManager::from_file(file);
+        m.register::<Lifecycle>()
+            .unwrap()
+            .register_named::<Explode>("explode")
+            .unwrap()
+            .register::<Add>()
+            .unwrap()
+            .catch_panics(true);
+
+        let mut c = CalcContext { x: 1 };
+        match m.run_stages(&mut c) {
+            Err(StageError::Panicked { name, payload }) => {
+                assert_eq!(name, "explode");
+                assert_eq!(payload, "kaboom 1");
+            }
+            other => panic!("expected Panicked, got {:?}", other),
+        }
+        assert_eq!(c.x, 1);
+        assert_eq!(take_log(), vec!["setup a", "run a", "teardown a"]);
+    }
+
+    #[test]
+    fn error_policies() {
+        let yaml_str = r#"
+        stages:
+        - name: fail
+          args:
+            msg: first
+        - name: add
+          args:
+            x: 2
+        - name: fail
+          args:
+            msg: second
+        - name: add
+          args:
+            x: 3
+        "#;
+
+        let file: StageFile<Value> = serde_yaml::from_str(yaml_str).unwrap();
+        let mut m = StageManager::from_file(file);
+        m.register::<Fail>().unwrap().register::<Add>().unwrap();
+
+        let mut c = CalcContext { x: 1 };
+        let err = m.run_stages(&mut c).unwrap_err();
+        assert_eq!(err.to_string(), "stage failed: first");
+        assert_eq!(c.x, 1);
+
+        m.with_error_policy(ErrorPolicy::ContinueCollect);
+        match m.run_stages(&mut c) {
+            Err(StageError::Collected { failures }) => {
+                let messages: Vec<_> = failures
+                    .iter()
+                    .map(|(name, e)| format!("{}: {}", name, e))
+                    .collect();
+                assert_eq!(
+                    messages,
+                    vec!["fail: stage failed: first", "fail: stage failed: second"]
+                );
+            }
+            other => panic!("expected Collected, got {:?}", other),
+        }
+        assert_eq!(c.x, 6);
+    }
+
+    #[test]
+    fn stages_see_their_position() {
+        #[derive(Deserialize)]
+        struct Progress {}
+
+        impl Stage for Progress {
+            type C = CalcContext;
+
+            fn run(&self, _c: &mut Self::C) -> Result<(), StageError> {
+                Ok(())
+            }
+
+            fn run_with_meta(
+                &self,
+                c: &mut Self::C,
+                shared: &dyn Any,
+                meta: &StageMeta<'_>,
+            ) -> Result<StageFlow, StageError> {
+                log(format!(
+                    "{} {} of {}",
+                    meta.name,
+                    meta.index + 1,
+                    meta.total
+                ));
+                self.run_shared(c, shared)
+            }
+        }
+
+        let yaml_str = r#"
+        stages:
+        - name: progress
+          args: {}
+        - name: add
+          args:
+            x: 1
+        - name: progress
+          repeat: 2
+          args: {}
+        "#;
+
+        let file: StageFile<Value> = serde_yaml::from_str(yaml_str).unwrap();
+        let mut m = StageManager::from_file(file);
+        m.register_named::<Progress>("progress")
+            .unwrap()
+            .register::<Add>()
+            .unwrap();
+
+        let mut c = CalcContext { x: 1 };
+        m.run_stages(&mut c).unwrap();
+        assert_eq!(
+            take_log(),
+            vec!["progress 1 of 3", "progress 3 of 3", "progress 3 of 3"]
+        );
+
+        let pipeline = m.build().unwrap();
+        pipeline.run(&mut c).unwrap();
+        assert_eq!(
+            take_log(),
+            vec!["progress 1 of 3", "progress 3 of 3", "progress 3 of 3"]
+        );
+    }
+
+    #[test]
+    fn args_can_be_omitted() {
+        #[derive(Deserialize)]
+        struct Noop {}
+
+        impl Stage for Noop {
+            type C = CalcContext;
+
+            fn run(&self, _c: &mut Self::C) -> Result<(), StageError> {
+                Ok(())
+            }
+        }
+
+        #[derive(Deserialize)]
+        struct Bump {
+            #[serde(default)]
+            by: Option<i64>,
+        }
+
+        impl Stage for Bump {
+            type C = CalcContext;
+
+            fn run(&self, c: &mut Self::C) -> Result<(), StageError> {
+                c.x += self.by.unwrap_or(1);
+                Ok(())
+            }
+        }
+
+        let yaml_str = r#"
+        stages:
+        - name: noop
+        - name: bump
+        - name: bump
+          args:
+            by: 10
+        "#;
+
+        let file: StageFile<Value> = serde_yaml::from_str(yaml_str).unwrap();
+        let mut m = StageManager::from_file(file);
+        m.register_named::<Noop>("noop")
+            .unwrap()
+            .register_named::<Bump>("bump")
+            .unwrap();
+
+        let mut c = CalcContext { x: 1 };
+        m.run_stages(&mut c).unwrap();
+        assert_eq!(c.x, 12);
+    }
+
+    #[test]
+    fn omitted_args_error_when_args_type_has_no_empty_map() {
+        let s: StageArgs<u32> = serde_yaml::from_str("name: noop").unwrap();
+        assert!(s.args().is_none());
+        assert!(s.args_or_empty().is_err());
+    }
+
+    #[test]
+    fn check_reports_every_bad_stage() {
+        let yaml_str = r#"
+        stages:
+        - name: add
+          args:
+            x: one
+        - name: mul
+          args:
+            x: 2
+        - name: sub
+          args:
+            x: 1
+        - name: mul
+          enabled: false
+          args: {}
+        "#;
+
+        let file: StageFile<Value> = serde_yaml::from_str(yaml_str).unwrap();
+        let mut m = StageManager::from_file(file);
+        m.register::<Mul>().unwrap().register::<Add>().unwrap();
+
+        let errors = m.check().unwrap_err();
+        assert_eq!(errors.len(), 3);
+        match &errors[0] {
+            StageError::UnknownStage { names } => assert_eq!(names, &["sub"]),
+            other => panic!("expected UnknownStage, got {:?}", other),
+        }
+        let bad: Vec<_> = errors[1..]
+            .iter()
+            .map(|e| match e {
+                StageError::Deserialize {
+                    stage_name, index, ..
+                } => (stage_name.as_str(), *index),
+                other => panic!("expected Deserialize, got {:?}", other),
+            })
+            .collect();
+        assert_eq!(bad, vec![("add", 0), ("mul", 3)]);
+    }
+
+    #[test]
+    fn stage_meta_is_kept_out_of_args() {
+        let yaml_str = r#"
+        stages:
+        - name: add
+          meta:
+            owner: data-team
+            ticket: 42
+          args:
+            x: 2
+        - name: mul
+          args:
+            x: 3
+        "#;
+
+        let file: StageFile<Value> = serde_yaml::from_str(yaml_str).unwrap();
+        let mut m = StageManager::from_file(file);
+        m.register::<Mul>().unwrap().register::<Add>().unwrap();
+        m.strict(true);
+
+        let meta = m.stage_meta(0).unwrap();
+        assert_eq!(meta["owner"], Value::from("data-team"));
+        assert_eq!(meta["ticket"], Value::from(42));
+        assert!(m.stage_meta(1).unwrap().is_empty());
+        assert!(m.stage_meta(2).is_none());
+
+        let mut c = CalcContext { x: 1 };
+        m.run_stages(&mut c).unwrap();
+        assert_eq!(c.x, 9);
+    }
+
+    #[test]
+    fn cloned_manager_is_registered_again() {
+        let yaml_str = r#"
+        stages:
+        - name: add
+          args:
+            x: 2
+        - name: mul
+          args:
+            x: 3
+        "#;
+
+        let file: StageFile<Value> = serde_yaml::from_str(yaml_str).unwrap();
+        let mut m = StageManager::from_file(file);
+        m.register::<Mul>().unwrap().register::<Add>().unwrap();
+
+        let bare = m.clone();
+        assert_eq!(bare.registered_names().count(), 0);
+        assert_eq!(
+            bare.validate(),
+            Err(vec!["add".to_string(), "mul".to_string()])
+        );
+
+        let clone = m
+            .clone_with_registry(|m| {
+                m.register::<Mul>()?.register::<Add>()?;
+                Ok(())
+            })
+            .unwrap();
+
+        let (mut a, mut b) = (CalcContext { x: 1 }, CalcContext { x: 1 });
+        m.run_stages(&mut a).unwrap();
+        clone.run_stages(&mut b).unwrap();
+        assert_eq!(a.x, 9);
+        assert_eq!(b.x, a.x);
+    }
+
+    #[test]
+    fn transformed_file_runs_inserted_stages() {
+        use crate::stages::stage_fn;
+
+        let yaml_str = r#"
+        stages:
+        - name: add
+          args:
+            x: 1
+        - name: mul
+          args:
+            x: 3
+        - name: add
+          args:
+            x: 2
+        "#;
+
+        let file: StageFile<Value> = serde_yaml::from_str(yaml_str).unwrap();
+        let mut m = StageManager::from_file(file);
+        m.register::<Add>()
+            .unwrap()
+            .register::<Mul>()
+            .unwrap()
+            .register_fn("noop", |_| Ok(stage_fn(|_: &mut CalcContext| {})))
+            .unwrap();
+
+        m.transform_file(|stages| {
+            let mut with_noops = Vec::new();
+            for (i, s) in stages.drain(..).enumerate() {
+                if i > 0 {
+                    with_noops.push(StageArgs::new("noop", Value::Null));
+                }
+                with_noops.push(s);
+            }
+            *stages = with_noops;
+        });
+
+        let names: Vec<_> = m.stages().map(|s| s.name()).collect();
+        assert_eq!(names, vec!["add", "noop", "mul", "noop", "add"]);
+
+        let mut c = CalcContext { x: 1 };
+        m.run_stages(&mut c).unwrap();
+        assert_eq!(c.x, 8);
+    }
+
+    #[cfg(feature = "yaml")]
+    #[test]
+    fn to_yaml_string_round_trips() {
+        let yaml_str = r#"
+        version: 1
+        stages:
+        - name: add
+          tags: [fast]
+          args:
+            x: 1
+        - name: mul
+          enabled: false
+          args:
+            x: 3
+        "#;
+
+        let mut m = StageManager::<CalcContext, Value>::from_yaml_str(yaml_str).unwrap();
+        m.transform_file(|stages| {
+            stages[1].set_enabled(true);
+            let args = serde_yaml::from_str("x: 2").unwrap();
+            stages.push(StageArgs::new("add", args));
+        });
+
+        let written = m.to_yaml_string().unwrap();
+        let mut reread = StageManager::from_yaml_str(&written).unwrap();
+        assert_eq!(reread.to_yaml_string().unwrap(), written);
+        assert!(!written.contains("enabled"));
+
+        reread.register::<Add>().unwrap().register::<Mul>().unwrap();
+        let mut c = CalcContext { x: 1 };
+        reread.run_stages(&mut c).unwrap();
+        assert_eq!(c.x, 8);
+        assert_eq!(reread.stages().next().unwrap().args().unwrap()["x"], 1);
+    }
+
+    #[test]
+    fn namespaced_stages_share_a_name() {
+        #[derive(Debug, Deserialize)]
+        struct AddTwice {
+            x: i64,
+        }
+
+        impl Stage for AddTwice {
+            type C = CalcContext;
+
+            fn run(&self, c: &mut Self::C) -> Result<(), StageError> {
+                c.x += 2 * self.x;
+                Ok(())
+            }
+        }
+
+        impl StageName for AddTwice {
+            fn stage_name() -> &'static str {
+                "add"
+            }
+        }
+
+        let yaml_str = r#"
+        stages:
+        - name: math.add
+          args:
+            x: 1
+        - name: double.add
+          args:
+            x: 3
+        "#;
+
+        let file: StageFile<Value> = serde_yaml::from_str(yaml_str).unwrap();
+        let mut m = StageManager::from_file(file);
+        m.register_namespaced::<Add>("math")
+            .unwrap()
+            .register_namespaced::<AddTwice>("double")
+            .unwrap();
+        assert!(!m.is_registered("add"));
+
+        let mut c = CalcContext { x: 0 };
+        m.run_stages(&mut c).unwrap();
+        assert_eq!(c.x, 7);
+    }
+
+    #[test]
+    fn cancelled_run_stops_between_stages() {
+        static CANCEL: AtomicBool = AtomicBool::new(false);
+
+        let yaml_str = r#"
+        stages:
+        - name: add
+          args:
+            x: 2
+        - name: mul
+          args:
+            x: 3
+        - name: add
+          args:
+            x: 1
+        "#;
+
+        let file: StageFile<Value> = serde_yaml::from_str(yaml_str).unwrap();
+        let mut m = StageManager::from_file(file);
+        m.register::<Add>().unwrap().register::<Mul>().unwrap();
+        m.after_each(Box::new(|_, _, _| CANCEL.store(true, Ordering::SeqCst)));
+
+        let mut c = CalcContext { x: 1 };
+        match m.run_stages_cancellable(&mut c, &CANCEL) {
+            Err(StageError::Cancelled { next_index }) => assert_eq!(next_index, 1),
+            other => panic!("unexpected result: {:?}", other),
+        }
+        assert_eq!(c.x, 3);
+    }
+
+    #[test]
+    fn args_default_from_context() {
+        #[derive(Debug, Deserialize)]
+        struct AddOrDouble {
+            x: Option<i64>,
+        }
+
+        impl Stage for AddOrDouble {
+            type C = CalcContext;
+
+            fn setup_with_context(&mut self, c: &Self::C) {
+                self.x.get_or_insert(c.x);
+            }
+
+            fn run(&self, c: &mut Self::C) -> Result<(), StageError> {
+                c.x += self.x.unwrap_or_default();
+                Ok(())
+            }
+        }
+
+        let yaml_str = r#"
+        stages:
+        - name: add
+          args:
+            x: 2
+        - name: add_or_double
+        - name: add_or_double
+          args:
+            x: 1
+        "#;
+
+        let file: StageFile<Value> = serde_yaml::from_str(yaml_str).unwrap();
+        let mut m = StageManager::from_file(file);
+        m.register::<Add>()
+            .unwrap()
+            .register_named::<AddOrDouble>("add_or_double")
+            .unwrap();
+
+        let mut c = CalcContext { x: 1 };
+        m.run_stages(&mut c).unwrap();
+        assert_eq!(c.x, 7);
+    }
+
+    #[test]
+    fn boxed_stages_run_by_hand() {
+        let stages: Vec<BoxedStage<CalcContext>> = vec![
+            box_stage(Add { x: 2 }),
+            box_stage(Mul { x: 3 }),
+            box_stage(Add { x: 1 }),
+        ];
+
+        let mut c = CalcContext { x: 1 };
+        for stage in &stages {
+            stage.run(&mut c).unwrap();
+        }
+        assert_eq!(c.x, 10);
+    }
+
+    #[test]
+    fn aliased_stage_runs_under_both_names() {
+        let yaml_str = r#"
+        stages:
+        - name: add
+          args:
+            x: 1
+        - name: plus
+          args:
+            x: 2
+        "#;
+
+        let file: StageFile<Value> = serde_yaml::from_str(yaml_str).unwrap();
+        let mut m = StageManager::from_file(file);
+        m.register::<Add>().unwrap().alias("add", "plus").unwrap();
+        assert_eq!(
+            m.alias("sub", "minus").unwrap_err(),
+            RegisterError::NotRegistered("sub".to_string())
+        );
+
+        let mut c = CalcContext { x: 1 };
+        m.run_stages(&mut c).unwrap();
+        assert_eq!(c.x, 4);
+    }
+
+    #[test]
+    fn dirty_stages_are_reported() {
+        let yaml_str = r#"
+        stages:
+        - name: mul
+          args:
+            x: 1
+        - name: add
+          args:
+            x: 5
+        - name: add
+          args:
+            x: 0
+        "#;
+
+        let file: StageFile<Value> = serde_yaml::from_str(yaml_str).unwrap();
+        let mut m = StageManager::from_file(file);
+        m.register::<Add>().unwrap().register::<Mul>().unwrap();
+
+        let mut c = CalcContext { x: 2 };
+        assert_eq!(m.run_stages_dirty(&mut c).unwrap(), vec![1]);
+        assert_eq!(c.x, 7);
+    }
+
+    #[test]
+    fn indexed_stages_know_their_index() {
+        #[derive(Debug, Deserialize)]
+        struct AddIndex {
+            #[serde(skip)]
+            index: usize,
+        }
+
+        impl WithIndex for AddIndex {
+            fn with_index(self, index: usize) -> Self {
+                Self { index }
+            }
+        }
+
+        impl Stage for AddIndex {
+            type C = CalcContext;
+
+            fn run(&self, c: &mut Self::C) -> Result<(), StageError> {
+                c.x = c.x * 10 + self.index as i64;
+                Ok(())
+            }
+        }
+
+        impl StageName for AddIndex {
+            fn stage_name() -> &'static str {
+                "add_index"
+            }
+        }
+
+        let yaml_str = r#"
+        stages:
+        - name: add_index
+        - name: add
+          args:
+            x: 0
+        - name: add_index
+        - name: add_index
+        "#;
+
+        let file: StageFile<Value> = serde_yaml::from_str(yaml_str).unwrap();
+        let mut m = StageManager::from_file(file);
+        m.register::<Add>()
+            .unwrap()
+            .register_indexed::<AddIndex>()
+            .unwrap();
+
+        let mut c = CalcContext { x: 1 };
+        m.run_stages(&mut c).unwrap();
+        assert_eq!(c.x, 1023);
+    }
+
+    #[test]
+    fn compiled_stage_runs_alone() {
+        let yaml_str = r#"
+        stages:
+        - name: add
+          args:
+            x: 2
+        - name: mul
+          args:
+            x: 3
+        - name: add
+          args:
+            x: 1
+          enabled: false
+        "#;
+
+        let file: StageFile<Value> = serde_yaml::from_str(yaml_str).unwrap();
+        let mut m = StageManager::from_file(file);
+        m.register::<Add>().unwrap().register::<Mul>().unwrap();
+        let pipeline = m.build().unwrap();
+
+        let mut c = CalcContext { x: 5 };
+        pipeline.run_one(1, &mut c).unwrap();
+        assert_eq!(c.x, 15);
+
+        assert!(pipeline.stage(0).is_some());
+        assert!(pipeline.stage(2).is_none());
+        assert!(matches!(
+            pipeline.run_one(2, &mut c),
+            Err(StageError::NoStageAt { index: 2 })
+        ));
+    }
+
+    #[test]
+    fn pipeline_from_stages() {
+        let pipeline = CompiledPipeline::from_stages(vec![
+            Box::new(Add { x: 2 }),
+            Box::new(Mul { x: 3 }),
+            Box::new(Add { x: 1 }),
+        ]);
+
+        let mut c = CalcContext { x: 1 };
+        pipeline.run(&mut c).unwrap();
+        assert_eq!(c.x, 10);
+    }
+
+    #[test]
+    fn stage_results_are_collected() {
+        use std::cell::Cell;
 
-#[derive(Debug, Deserialize)]
-pub struct StageFile<V> {
-    stages: Vec<StageArgs<V>>,
-}
+        #[derive(Debug, Deserialize)]
+        struct Report {
+            #[serde(skip)]
+            seen: Cell<i64>,
+        }
 
-#[derive(Debug, Deserialize)]
-struct StageArgs<V> {
-    name: String,
-    args: V,
-}
+        impl Stage for Report {
+            type C = CalcContext;
 
-type FnDeserializeStage<C, V> = Box<dyn Fn(V) -> Box<dyn Stage<C = C>>>;
+            fn run(&self, c: &mut Self::C) -> Result<(), StageError> {
+                self.seen.set(c.x);
+                Ok(())
+            }
 
-#[derive(Deserialize)]
-pub struct StageManager<C, V> {
-    #[serde(flatten)]
-    file: StageFile<V>,
+            fn result(&self) -> Option<Box<dyn Any>> {
+                Some(Box::new(self.seen.get()))
+            }
+        }
 
-    #[serde(skip)]
-    deserialize_map: HashMap<String, FnDeserializeStage<C, V>>,
-}
+        let yaml_str = r#"
+        stages:
+        - name: report
+        - name: add
+          args:
+            x: 2
+        - name: report
+        "#;
 
-impl<C, V> Debug for StageManager<C, V> where V: Debug {
-    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
-        write!(f, "StateManager{{file: {:?}, deserialize_map: {:?}}}", self.file, self.deserialize_map.keys())
-    }
-}
+        let file: StageFile<Value> = serde_yaml::from_str(yaml_str).unwrap();
+        let mut m = StageManager::from_file(file);
+        m.register::<Add>()
+            .unwrap()
+            .register_named::<Report>("report")
+            .unwrap();
 
-impl<'de, C, V> StageManager<C, V>
-where
-    V: Deserialize<'de> + Deserializer<'de> + Clone,
-{
-    pub fn from_file(stage_file: StageFile<V>) -> Self {
-        Self {
-            file: stage_file,
-            deserialize_map: HashMap::new(),
-        }
+        let mut c = CalcContext { x: 1 };
+        let results = m.run_stages_collect(&mut c).unwrap();
+        assert_eq!(results.len(), 2);
+        assert_eq!(results[&0].downcast_ref::<i64>(), Some(&1));
+        assert_eq!(results[&2].downcast_ref::<i64>(), Some(&3));
     }
 
-    pub fn run_stages(&self, context: &mut C) {
-        self.file
-            .stages
-            .iter()
-            .map(|s| {
-                let f = &self.deserialize_map[&s.name];
-                let mut s = f(s.args.clone());
-                s.setup();
-                s
-            })
-            .for_each(|s| {
-                s.run(context);
-            });
-    }
+    #[test]
+    fn fallback_builds_unknown_stages() {
+        use crate::stages::stage_fn;
+        use std::{cell::RefCell, rc::Rc};
 
-    pub fn register_named<'a, S>(&mut self, name: &str) -> &mut Self
-    where
-        S: 'static + Stage<C = C> + Deserialize<'de>,
-    {
-        self.deserialize_map.insert(
-            name.to_string(),
-            Box::new(|v| Box::new(S::deserialize(v).unwrap())),
-        );
-        self
-    }
+        let yaml_str = r#"
+        stages:
+        - name: foo
+        - name: add
+          args:
+            x: 2
+        "#;
 
-    pub fn register<'a, S>(&mut self) -> &mut Self
-    where
-        S: 'static + Stage<C = C> + StageName + Deserialize<'de>,
-    {
-        self.register_named::<S>(S::stage_name())
-    }
-}
+        let file: StageFile<Value> = serde_yaml::from_str(yaml_str).unwrap();
+        let mut m = StageManager::from_file(file);
+        m.register::<Add>().unwrap();
+        assert!(m.validate().is_err());
 
-impl<'de, C, V> Stage for StageManager<C, V>
-where
-    V: Deserialize<'de> + Deserializer<'de> + Clone
-{
-    type C = C;
+        let seen = Rc::new(RefCell::new(Vec::new()));
+        let names = Rc::clone(&seen);
+        m.register_default(move |name, _| {
+            names.borrow_mut().push(name.to_string());
+            Ok(stage_fn(|c: &mut CalcContext| c.x *= 10))
+        });
+        assert_eq!(m.validate(), Ok(()));
 
-    fn run(&self, c: &mut Self::C) {
-        self.run_stages(c);
+        let mut c = CalcContext { x: 1 };
+        m.run_stages(&mut c).unwrap();
+        assert_eq!(c.x, 12);
+        assert_eq!(*seen.borrow(), vec!["foo".to_string()]);
     }
-}
 
-#[cfg(test)]
-mod test {
-    use serde_yaml::Value;
+    #[test]
+    fn undo_reverses_run() {
+        let yaml_str = r#"
+        stages:
+        - name: add
+          args:
+            x: 3
+        - name: mul
+          args:
+            x: 4
+          repeat: 2
+        "#;
 
-    use super::*;
+        let file: StageFile<Value> = serde_yaml::from_str(yaml_str).unwrap();
+        let mut m = StageManager::from_file(file);
+        m.register::<Add>().unwrap().register::<Mul>().unwrap();
 
-    #[derive(Debug, Default, Clone)]
-    struct CalcContext {
-        x: i64,
+        let mut c = CalcContext { x: 2 };
+        m.run_stages(&mut c).unwrap();
+        assert_eq!(c.x, 80);
+
+        m.undo_stages(&mut c).unwrap();
+        assert_eq!(c.x, 2);
     }
 
-    #[derive(Debug, Deserialize)]
-    struct Add {
-        x: i64,
+    #[test]
+    fn stages_can_be_inspected() {
+        let yaml_str = r#"
+        stages:
+        - name: top
+          args:
+            stages:
+            - name: add
+              args:
+                x: 1
+            - name: add
+              args:
+                x: 4
+        - name: mul
+          args:
+            x: 2
+        "#;
+
+        let file: StageFile<Value> = serde_yaml::from_str(yaml_str).unwrap();
+        let m = StageManager::<CalcContext, _>::from_file(file);
+
+        let stages: Vec<_> = m.stages().map(|s| (s.index(), s.name())).collect();
+        assert_eq!(stages, vec![(0, "top"), (1, "mul")]);
+
+        let top = m.stages().next().unwrap();
+        assert_eq!(
+            top.args().unwrap()["stages"].as_sequence().unwrap().len(),
+            2
+        );
     }
 
-    impl Stage for Add {
-        type C = CalcContext;
+    #[test]
+    fn versioned_stage_names() {
+        #[derive(Deserialize)]
+        struct AddV1 {
+            x: i64,
+        }
 
-        fn run(&self, c: &mut Self::C) {
-            c.x += self.x;
+        #[derive(Deserialize)]
+        struct AddV2 {
+            amount: i64,
         }
-    }
 
-    impl StageName for Add {
-        fn stage_name() -> &'static str {
-            "add"
+        impl StageName for AddV1 {
+            fn stage_name() -> &'static str {
+                "add"
+            }
+
+            fn version() -> u32 {
+                1
+            }
         }
-    }
 
-    #[derive(Debug, Deserialize)]
-    struct Mul {
-        x: i64,
-    }
+        impl StageName for AddV2 {
+            fn stage_name() -> &'static str {
+                "add"
+            }
 
-    impl StageName for Mul {
-        fn stage_name() -> &'static str {
-            "mul"
+            fn version() -> u32 {
+                2
+            }
         }
-    }
 
-    impl Stage for Mul {
-        type C = CalcContext;
+        impl Stage for AddV1 {
+            type C = CalcContext;
 
-        fn run(&self, c: &mut Self::C) {
-            c.x *= self.x;
+            fn run(&self, c: &mut Self::C) -> Result<(), StageError> {
+                c.x += self.x;
+                Ok(())
+            }
+        }
+
+        impl Stage for AddV2 {
+            type C = CalcContext;
+
+            fn run(&self, c: &mut Self::C) -> Result<(), StageError> {
+                c.x += self.amount * 10;
+                Ok(())
+            }
         }
-    }
 
-    #[test]
-    fn calc_add_pipeline() {
         let yaml_str = r#"
         stages:
-        - name: add
+        - name: add@1
           args:
             x: 1
         - name: add
+          args:
+            amount: 2
+        - name: add@2
+          args:
+            amount: 3
+        "#;
+
+        let file: StageFile<Value> = serde_yaml::from_str(yaml_str).unwrap();
+        let mut m = StageManager::from_file(file);
+        m.register::<AddV2>().unwrap().register::<AddV1>().unwrap();
+
+        assert!(m.is_registered("add"));
+        assert!(m.is_registered("add@1"));
+        assert!(!m.is_registered("add@3"));
+
+        let mut c = CalcContext { x: 0 };
+        m.run_stages(&mut c).unwrap();
+        assert_eq!(c.x, 51);
+    }
+
+    #[test]
+    fn version_zero_resolves_to_the_plain_name() {
+        let file: StageFile<Value> = serde_yaml::from_str("[{name: add@0, args: {x: 2}}]").unwrap();
+        let mut m = StageManager::from_file(file);
+        m.register::<Add>().unwrap();
+
+        assert!(m.is_registered("add@0"));
+        let mut c = CalcContext { x: 1 };
+        m.run_stages(&mut c).unwrap();
+        assert_eq!(c.x, 3);
+    }
+
+    #[test]
+    fn tagged_run_selects_stages() {
+        let yaml_str = r#"
+        stages:
+        - name: add
+          tags: [fast]
           args:
             x: 2
+        - name: mul
+          tags: [slow]
+          args:
+            x: 3
         - name: add
+          tags: [fast, prod]
           args:
             x: 5
+        - name: add
+          args:
+            x: 100
+        "#;
+
+        let file: StageFile<Value> = serde_yaml::from_str(yaml_str).unwrap();
+        let mut m = StageManager::from_file(file);
+        m.register::<Mul>().unwrap().register::<Add>().unwrap();
+
+        let run = |filter: TagFilter| {
+            let mut c = CalcContext { x: 1 };
+            m.run_stages_with_tags(&mut c, &filter).unwrap();
+            c.x
+        };
+
+        assert_eq!(run(TagFilter::include("fast")), 8);
+        assert_eq!(run(TagFilter::include("fast").untagged(true)), 108);
+        assert_eq!(run(TagFilter::include("fast").and_exclude("prod")), 3);
+        assert_eq!(run(TagFilter::exclude("slow")), 108);
+    }
+
+    #[test]
+    fn shared_config_is_visible_to_stages() {
+        struct Settings {
+            multiplier: i64,
+        }
+
+        #[derive(Deserialize)]
+        struct Scale {}
+
+        impl Stage for Scale {
+            type C = CalcContext;
+
+            fn run(&self, _c: &mut Self::C) -> Result<(), StageError> {
+                Err(StageError::custom("scale needs shared settings"))
+            }
+
+            fn run_shared(
+                &self,
+                c: &mut Self::C,
+                shared: &dyn Any,
+            ) -> Result<StageFlow, StageError> {
+                match shared.downcast_ref::<Settings>() {
+                    Some(settings) => {
+                        c.x *= settings.multiplier;
+                        Ok(StageFlow::Continue)
+                    }
+                    None => self.run_flow(c),
+                }
+            }
+        }
+
+        let yaml_str = r#"
+        stages:
+        - name: add
+          args:
+            x: 1
+        - name: scale
+          args: {}
         "#;
 
         let file: StageFile<Value> = serde_yaml::from_str(yaml_str).unwrap();
         let mut m = StageManager::from_file(file);
-        m.register::<Add>();
+        m.register::<Add>()
+            .unwrap()
+            .register_named::<Scale>("scale")
+            .unwrap();
 
+        let settings = Settings { multiplier: 3 };
         let mut c = CalcContext { x: 1 };
-        m.run(&mut c);
+        m.run_with_shared(&mut c, &settings).unwrap();
+        assert_eq!(c.x, 6);
 
-        assert_eq!(c.x, 9);
+        let mut c = CalcContext { x: 1 };
+        assert!(m.run_stages(&mut c).is_err());
     }
 
     #[test]
-    fn calc_mul_pipeline() {
+    fn observer_sees_lifecycle_events() {
+        use std::{cell::RefCell, rc::Rc};
+
+        struct Recorder(Rc<RefCell<Vec<String>>>);
+
+        impl Observer for Recorder {
+            fn on_stage_start(&self, name: &str, index: usize) {
+                self.0
+                    .borrow_mut()
+                    .push(format!("start {} {}", name, index));
+            }
+
+            fn on_stage_end(
+                &self,
+                name: &str,
+                index: usize,
+                result: &Result<StageFlow, StageError>,
+            ) {
+                self.0
+                    .borrow_mut()
+                    .push(format!("end {} {} {}", name, index, result.is_ok()));
+            }
+
+            fn on_stage_skipped(&self, name: &str, index: usize) {
+                self.0
+                    .borrow_mut()
+                    .push(format!("skipped {} {}", name, index));
+            }
+
+            fn on_pipeline_end(&self, summary: &RunSummary) {
+                self.0.borrow_mut().push(format!(
+                    "done {} {} {}",
+                    summary.executed,
+                    summary.skipped,
+                    summary.failed.is_some()
+                ));
+            }
+        }
+
         let yaml_str = r#"
         stages:
         - name: mul
           args:
             x: 1
-        - name: mul
+        - name: add
+          enabled: false
           args:
             x: 2
         - name: mul
@@ -185,19 +4321,34 @@ mod test {
             x: 5
         "#;
 
+        let events = Rc::new(RefCell::new(Vec::new()));
         let file: StageFile<Value> = serde_yaml::from_str(yaml_str).unwrap();
         let mut m = StageManager::from_file(file);
-        m.register::<Mul>();
+        m.register::<Mul>()
+            .unwrap()
+            .register::<Add>()
+            .unwrap()
+            .with_observer(Box::new(Recorder(events.clone())));
 
         let mut c = CalcContext { x: 1 };
-        m.run(&mut c);
+        m.run(&mut c).unwrap();
 
-        assert_eq!(c.x, 10);
+        assert_eq!(
+            *events.borrow(),
+            vec![
+                "start mul 0",
+                "end mul 0 true",
+                "skipped add 1",
+                "start mul 2",
+                "end mul 2 true",
+                "done 2 1 false",
+            ]
+        );
     }
 
     #[test]
-    fn calc_add_mul_pipeline() {
-        let yaml_str = r#"
+    fn tagged_file_matches_nested_file() {
+        let nested = r#"
         stages:
         - name: mul
           args:
@@ -206,69 +4357,89 @@ mod test {
           args:
             x: 2
         - name: mul
+          repeat: 2
           args:
             x: 5
         "#;
+        let tagged = r#"
+        stages:
+        - type: mul
+          x: 1
+        - type: add
+          x: 2
+        - type: mul
+          repeat: 2
+          x: 5
+        "#;
 
-        let file: StageFile<Value> = serde_yaml::from_str(yaml_str).unwrap();
-        let mut m = StageManager::from_file(file);
-        m.register::<Mul>().register::<Add>();
+        let run = |file: StageFile<Value>| {
+            let mut m = StageManager::from_file(file);
+            m.register::<Mul>().unwrap().register::<Add>().unwrap();
 
-        let mut c = CalcContext { x: 1 };
-        m.run(&mut c);
+            let mut c = CalcContext { x: 1 };
+            m.run(&mut c).unwrap();
+            c.x
+        };
 
-        assert_eq!(c.x, 15);
+        let tagged: TaggedStageFile<Value> = serde_yaml::from_str(tagged).unwrap();
+        assert_eq!(run(tagged.into()), 75);
+        assert_eq!(run(serde_yaml::from_str(nested).unwrap()), 75);
     }
 
     #[test]
-    fn stage_recursive_pipeline() {
+    fn setup_receives_position() {
         #[derive(Deserialize)]
-        struct Top {
-            #[serde(flatten)]
-            stages: StageManager<CalcContext, Value>,
-        }
+        struct Label {}
 
-        impl Stage for Top {
+        impl Stage for Label {
             type C = CalcContext;
 
-            fn setup(&mut self) {
-                self.stages.register::<Add>().register::<Mul>();
-            }
-
-            fn run(&self, c: &mut Self::C) {
-                self.stages.run(c);
+            fn setup_with(&mut self, ctx: StageSetupCtx<'_>) {
+                log(format!(
+                    "{} is step {} of {}",
+                    ctx.name,
+                    ctx.index + 1,
+                    ctx.total
+                ));
             }
-        }
 
-        impl StageName for Top {
-            fn stage_name() -> &'static str {
-                "top"
+            fn run(&self, _c: &mut Self::C) -> Result<(), StageError> {
+                Ok(())
             }
         }
 
         let yaml_str = r#"
         stages:
-        - name: top 
-          args:
-            stages:
-            - name: add
-              args:
-                x: 1
-            - name: add
-              args:
-                x: 4
-        - name: mul
+        - name: label
+          args: {}
+        - name: lifecycle
           args:
-            x: 2
+            id: a
+        - name: step
+          args: {}
         "#;
 
         let file: StageFile<Value> = serde_yaml::from_str(yaml_str).unwrap();
         let mut m = StageManager::from_file(file);
-        m.register::<Mul>().register::<Add>().register::<Top>();
+        m.register_named::<Label>("label")
+            .unwrap()
+            .register_named::<Label>("step")
+            .unwrap()
+            .register::<Lifecycle>()
+            .unwrap();
 
         let mut c = CalcContext { x: 1 };
-        m.run(&mut c);
+        m.run(&mut c).unwrap();
 
-        assert_eq!(c.x, 12);
+        assert_eq!(
+            take_log(),
+            vec![
+                "label is step 1 of 3",
+                "setup a",
+                "run a",
+                "step is step 3 of 3",
+                "teardown a"
+            ]
+        );
     }
 }
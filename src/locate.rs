@@ -0,0 +1,186 @@
+use std::fmt;
+
+// Where a stage's args start in the text its file was parsed from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Position {
+    pub line: usize,
+    pub column: usize,
+}
+
+impl fmt::Display for Position {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}, column {}", self.line, self.column)
+    }
+}
+
+// Parsed values keep no positions, so the source is parsed again and the
+// parse deliberately fails at the args of the stage at `index`; serde_yaml
+// attaches the position of the failing node to the error.
+#[cfg(feature = "yaml")]
+pub(crate) fn stage_position(source: &str, index: usize) -> Option<Position> {
+    let e = serde_yaml::seed::from_str_seed(source, yaml::Root(index)).err()?;
+    let location = e.location()?;
+    Some(Position {
+        line: location.line(),
+        column: location.column(),
+    })
+}
+
+#[cfg(not(feature = "yaml"))]
+pub(crate) fn stage_position(_source: &str, _index: usize) -> Option<Position> {
+    None
+}
+
+#[cfg(feature = "yaml")]
+mod yaml {
+    use serde::de::{DeserializeSeed, Deserializer, IgnoredAny, MapAccess, SeqAccess, Visitor};
+    use std::fmt;
+
+    // The whole file, either `stages: [...]` or a bare sequence.
+    pub(super) struct Root(pub(super) usize);
+
+    impl<'de> DeserializeSeed<'de> for Root {
+        type Value = ();
+
+        fn deserialize<D: Deserializer<'de>>(self, d: D) -> Result<(), D::Error> {
+            d.deserialize_any(self)
+        }
+    }
+
+    impl<'de> Visitor<'de> for Root {
+        type Value = ();
+
+        fn expecting(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            f.write_str("a stage file")
+        }
+
+        fn visit_seq<A: SeqAccess<'de>>(self, seq: A) -> Result<(), A::Error> {
+            Stages(self.0).visit_seq(seq)
+        }
+
+        fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<(), A::Error> {
+            while let Some(key) = map.next_key::<String>()? {
+                if key == "stages" {
+                    map.next_value_seed(Stages(self.0))?;
+                } else {
+                    map.next_value::<IgnoredAny>()?;
+                }
+            }
+            Ok(())
+        }
+    }
+
+    struct Stages(usize);
+
+    impl<'de> DeserializeSeed<'de> for Stages {
+        type Value = ();
+
+        fn deserialize<D: Deserializer<'de>>(self, d: D) -> Result<(), D::Error> {
+            d.deserialize_seq(self)
+        }
+    }
+
+    impl<'de> Visitor<'de> for Stages {
+        type Value = ();
+
+        fn expecting(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            f.write_str("a sequence of stages")
+        }
+
+        fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<(), A::Error> {
+            for _ in 0..self.0 {
+                if seq.next_element::<IgnoredAny>()?.is_none() {
+                    return Ok(());
+                }
+            }
+            seq.next_element_seed(Stage)?;
+            while seq.next_element::<IgnoredAny>()?.is_some() {}
+            Ok(())
+        }
+    }
+
+    struct Stage;
+
+    impl<'de> DeserializeSeed<'de> for Stage {
+        type Value = ();
+
+        fn deserialize<D: Deserializer<'de>>(self, d: D) -> Result<(), D::Error> {
+            d.deserialize_map(self)
+        }
+    }
+
+    impl<'de> Visitor<'de> for Stage {
+        type Value = ();
+
+        fn expecting(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            f.write_str("a stage")
+        }
+
+        fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<(), A::Error> {
+            while let Some(key) = map.next_key::<String>()? {
+                if key == "args" {
+                    map.next_value_seed(Args)?;
+                } else {
+                    map.next_value::<IgnoredAny>()?;
+                }
+            }
+            Ok(())
+        }
+    }
+
+    // Rejects whatever it is given, failing at the args node.
+    struct Args;
+
+    impl<'de> DeserializeSeed<'de> for Args {
+        type Value = ();
+
+        fn deserialize<D: Deserializer<'de>>(self, d: D) -> Result<(), D::Error> {
+            d.deserialize_any(self)
+        }
+    }
+
+    impl<'de> Visitor<'de> for Args {
+        type Value = ();
+
+        fn expecting(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            f.write_str("nothing")
+        }
+    }
+}
+
+#[cfg(all(test, feature = "yaml"))]
+mod test {
+    use crate::{
+        test::{Add, CalcContext, Mul},
+        StageError, StageManager,
+    };
+
+    #[test]
+    fn args_errors_report_their_position() {
+        let yaml_str = "stages:
+- name: add
+  args:
+    x: 1
+- name: mul
+  args:
+    x: two
+- name: add
+  args:
+    x: 3
+";
+
+        let mut m = StageManager::from_yaml_str(yaml_str).unwrap();
+        m.register::<Add>().unwrap().register::<Mul>().unwrap();
+
+        let mut c = CalcContext { x: 1 };
+        match m.run_stages(&mut c).unwrap_err() {
+            StageError::Deserialize {
+                index, position, ..
+            } => {
+                assert_eq!(index, 1);
+                assert_eq!(position.map(|p| p.line), Some(7));
+            }
+            e => panic!("unexpected error: {}", e),
+        }
+    }
+}
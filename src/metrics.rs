@@ -0,0 +1,111 @@
+use std::{cell::Cell, time::Instant};
+
+use crate::{Observer, StageError, StageFlow};
+
+// The counter and histogram calls MetricsObserver makes. Implementing it with
+// the `metrics` crate's `counter!`/`histogram!` macros, labelled with
+// `"name" => stage`, feeds any exporter that crate supports.
+pub trait MetricsRecorder {
+    fn increment_counter(&self, key: &'static str, stage: &str);
+    fn record_histogram(&self, key: &'static str, stage: &str, value: f64);
+}
+
+// Counts every stage run under `stage_fright.stage.runs` and records how
+// long it took, in seconds, under `stage_fright.stage.duration`.
+pub struct MetricsObserver<R> {
+    recorder: R,
+    started: Cell<Option<Instant>>,
+}
+
+impl<R: MetricsRecorder> MetricsObserver<R> {
+    pub const RUNS: &'static str = "stage_fright.stage.runs";
+    pub const DURATION: &'static str = "stage_fright.stage.duration";
+
+    pub fn new(recorder: R) -> Self {
+        Self {
+            recorder,
+            started: Cell::new(None),
+        }
+    }
+}
+
+impl<R: MetricsRecorder> Observer for MetricsObserver<R> {
+    fn on_stage_start(&self, _name: &str, _index: usize) {
+        self.started.set(Some(Instant::now()));
+    }
+
+    fn on_stage_end(&self, name: &str, _index: usize, _result: &Result<StageFlow, StageError>) {
+        self.recorder.increment_counter(Self::RUNS, name);
+        if let Some(started) = self.started.take() {
+            let elapsed = started.elapsed().as_secs_f64();
+            self.recorder
+                .record_histogram(Self::DURATION, name, elapsed);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use serde_yaml::Value;
+    use std::{cell::RefCell, collections::HashMap, rc::Rc};
+
+    use super::*;
+    use crate::{
+        test::{Add, CalcContext, Mul},
+        StageFile, StageManager,
+    };
+
+    #[derive(Default)]
+    struct TestRecorder {
+        counters: RefCell<HashMap<(&'static str, String), u64>>,
+        histograms: RefCell<Vec<(&'static str, String)>>,
+    }
+
+    impl MetricsRecorder for Rc<TestRecorder> {
+        fn increment_counter(&self, key: &'static str, stage: &str) {
+            *self
+                .counters
+                .borrow_mut()
+                .entry((key, stage.to_string()))
+                .or_default() += 1;
+        }
+
+        fn record_histogram(&self, key: &'static str, stage: &str, _value: f64) {
+            self.histograms.borrow_mut().push((key, stage.to_string()));
+        }
+    }
+
+    #[test]
+    fn stage_runs_are_counted() {
+        let yaml_str = r#"
+        stages:
+        - name: add
+          args:
+            x: 1
+        - name: mul
+          args:
+            x: 2
+        - name: add
+          args:
+            x: 3
+        "#;
+
+        let file: StageFile<Value> = serde_yaml::from_str(yaml_str).unwrap();
+        let recorder = Rc::new(TestRecorder::default());
+        let mut m = StageManager::from_file(file);
+        m.register::<Add>()
+            .unwrap()
+            .register::<Mul>()
+            .unwrap()
+            .with_observer(Box::new(MetricsObserver::new(Rc::clone(&recorder))));
+
+        let mut c = CalcContext { x: 1 };
+        m.run_stages(&mut c).unwrap();
+
+        let counters = recorder.counters.borrow();
+        let runs = MetricsObserver::<Rc<TestRecorder>>::RUNS;
+        assert_eq!(counters[&(runs, "add".to_string())], 2);
+        assert_eq!(counters[&(runs, "mul".to_string())], 1);
+        assert_eq!(recorder.histograms.borrow().len(), 3);
+    }
+}
@@ -0,0 +1,202 @@
+use serde::{
+    de::{DeserializeSeed, IgnoredAny, MapAccess, SeqAccess, Visitor},
+    Deserialize, Deserializer,
+};
+use std::{fmt, marker::PhantomData, rc::Rc};
+
+use crate::{StageError, StageFile, StageManager};
+
+impl<'de, C, V> StageManager<C, V>
+where
+    V: Deserialize<'de> + Deserializer<'de> + Clone,
+{
+    // Runs only the stages found at a slash separated `path` into the file,
+    // such as `stages/0/args/stages`, with this manager's factories, run
+    // settings, args preprocessing and observer. The value there may be a
+    // map with a `stages` key or a bare sequence of stages.
+    pub fn run_at(&self, path: &str, context: &mut C) -> Result<(), StageError> {
+        let file = self.file_at(path)?;
+        let mut m = self.clone();
+        m.file = file;
+        m.deserialize_map = self.deserialize_map.clone();
+        m.fallback = self.fallback.as_ref().map(Rc::clone);
+        m.preprocess = self.preprocess.as_ref().map(Rc::clone);
+        m.observer = self.observer.as_ref().map(Rc::clone);
+        m.schemas = self.schemas.clone();
+        m.source = None;
+        m.run_stages(context)
+    }
+
+    fn file_at(&self, path: &str) -> Result<StageFile<V>, StageError> {
+        let invalid = |source: &str| StageError::InvalidPath {
+            path: path.to_string(),
+            source: source.into(),
+        };
+
+        let segments: Vec<&str> = path.trim_matches('/').split('/').collect();
+        let s = match segments.as_slice() {
+            ["stages", index, "args", ..] => index
+                .parse()
+                .ok()
+                .and_then(|index: usize| self.file.stages.get(index)),
+            _ => return Err(invalid("path must start with `stages/<index>/args`")),
+        }
+        .ok_or_else(|| invalid("no stage at that index"))?;
+
+        let args = s
+            .args_or_empty()
+            .map_err(|source| StageError::InvalidPath {
+                path: path.to_string(),
+                source,
+            })?;
+        let value = args
+            .deserialize_any(PathVisitor::<V>::new(&segments[3..]))
+            .map_err(|e| StageError::InvalidPath {
+                path: path.to_string(),
+                source: e.to_string().into(),
+            })?
+            .ok_or_else(|| invalid("no value at that path"))?;
+        StageFile::deserialize(value).map_err(|e| StageError::InvalidPath {
+            path: path.to_string(),
+            source: e.to_string().into(),
+        })
+    }
+}
+
+// Finds the value at `segments` below the one it visits, keeping it as a `V`.
+struct PathVisitor<'p, V> {
+    segments: &'p [&'p str],
+    _value: PhantomData<V>,
+}
+
+impl<'p, V> PathVisitor<'p, V> {
+    fn new(segments: &'p [&'p str]) -> Self {
+        Self {
+            segments,
+            _value: PhantomData,
+        }
+    }
+}
+
+impl<'de, V: Deserialize<'de>> DeserializeSeed<'de> for PathVisitor<'_, V> {
+    type Value = Option<V>;
+
+    fn deserialize<D: Deserializer<'de>>(self, d: D) -> Result<Option<V>, D::Error> {
+        if self.segments.is_empty() {
+            V::deserialize(d).map(Some)
+        } else {
+            d.deserialize_any(self)
+        }
+    }
+}
+
+impl<'de, V: Deserialize<'de>> Visitor<'de> for PathVisitor<'_, V> {
+    type Value = Option<V>;
+
+    fn expecting(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("a map or sequence")
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Option<V>, A::Error> {
+        if self.segments.is_empty() {
+            return V::deserialize(serde::de::value::MapAccessDeserializer::new(map)).map(Some);
+        }
+        let mut found = None;
+        while let Some(key) = map.next_key::<String>()? {
+            if found.is_none() && key == self.segments[0] {
+                found = map.next_value_seed(PathVisitor::new(&self.segments[1..]))?;
+            } else {
+                map.next_value::<IgnoredAny>()?;
+            }
+        }
+        Ok(found)
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Option<V>, A::Error> {
+        if self.segments.is_empty() {
+            return V::deserialize(serde::de::value::SeqAccessDeserializer::new(seq)).map(Some);
+        }
+        let index: usize = match self.segments[0].parse() {
+            Ok(index) => index,
+            Err(_) => return Ok(None),
+        };
+        for _ in 0..index {
+            if seq.next_element::<IgnoredAny>()?.is_none() {
+                return Ok(None);
+            }
+        }
+        let found = seq
+            .next_element_seed(PathVisitor::new(&self.segments[1..]))?
+            .flatten();
+        while seq.next_element::<IgnoredAny>()?.is_some() {}
+        Ok(found)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use serde_yaml::Value;
+
+    use crate::{
+        test::{Add, CalcContext, Mul},
+        StageError, StageFile, StageManager,
+    };
+
+    #[test]
+    fn runs_nested_pipeline_by_path() {
+        let yaml_str = r#"
+        stages:
+        - name: top
+          args:
+            stages:
+            - name: add
+              args:
+                x: 1
+            - name: mul
+              args:
+                x: 4
+        - name: mul
+          args:
+            x: 2
+        "#;
+
+        let file: StageFile<Value> = serde_yaml::from_str(yaml_str).unwrap();
+        let mut m = StageManager::from_file(file);
+        m.register::<Add>().unwrap().register::<Mul>().unwrap();
+
+        let mut c = CalcContext { x: 1 };
+        m.run_at("stages/0/args/stages", &mut c).unwrap();
+        assert_eq!(c.x, 8);
+
+        m.run_at("/stages/0/args", &mut c).unwrap();
+        assert_eq!(c.x, 36);
+
+        match m.run_at("stages/0/args/steps", &mut c) {
+            Err(StageError::InvalidPath { path, .. }) => assert_eq!(path, "stages/0/args/steps"),
+            other => panic!("unexpected result: {:?}", other),
+        }
+    }
+
+    #[cfg(feature = "yaml")]
+    #[test]
+    fn nested_pipeline_keeps_env_interpolation() {
+        let yaml_str = r#"
+        stages:
+        - name: top
+          args:
+            stages:
+            - name: add
+              args:
+                x: ${STAGE_FRIGHT_TEST_RUN_AT_UNSET:-3}
+        "#;
+
+        let file: StageFile<Value> = serde_yaml::from_str(yaml_str).unwrap();
+        let mut m = StageManager::from_file(file);
+        m.register::<Add>().unwrap();
+        m.with_env_interpolation(true);
+
+        let mut c = CalcContext { x: 1 };
+        m.run_at("stages/0/args", &mut c).unwrap();
+        assert_eq!(c.x, 4);
+    }
+}
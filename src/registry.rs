@@ -0,0 +1,103 @@
+use serde::{Deserialize, Deserializer};
+use std::{collections::BTreeMap, rc::Rc};
+
+use crate::{schema::SchemaProbes, FnDeserializeStage, RegisterError, StageFile, StageManager};
+
+// A catalog of stage factories built once and attached to any number of
+// managers. Clones share the same maps; a manager that registers more stages
+// on top of an attached registry gets its own copy and leaves the others be.
+pub struct StageRegistry<C, V> {
+    factories: Rc<BTreeMap<String, FnDeserializeStage<C, V>>>,
+    schemas: Rc<SchemaProbes>,
+}
+
+impl<C, V> Clone for StageRegistry<C, V> {
+    fn clone(&self) -> Self {
+        Self {
+            factories: Rc::clone(&self.factories),
+            schemas: Rc::clone(&self.schemas),
+        }
+    }
+}
+
+impl<'de, C, V> StageRegistry<C, V>
+where
+    V: Deserialize<'de> + Deserializer<'de> + Clone,
+{
+    // Collects whatever `register` registers on an empty manager.
+    pub fn build<R>(register: R) -> Result<Self, RegisterError>
+    where
+        R: FnOnce(&mut StageManager<C, V>) -> Result<(), RegisterError>,
+    {
+        let mut m = StageManager::from_file(StageFile {
+            version: 0,
+            stages: Vec::new(),
+        });
+        register(&mut m)?;
+        Ok(m.registry())
+    }
+
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.factories.keys().map(String::as_str)
+    }
+}
+
+impl<'de, C, V> StageManager<C, V>
+where
+    V: Deserialize<'de> + Deserializer<'de> + Clone,
+{
+    // The manager's factories, to attach to other managers.
+    pub fn registry(&self) -> StageRegistry<C, V> {
+        StageRegistry {
+            factories: Rc::clone(&self.deserialize_map),
+            schemas: Rc::clone(&self.schemas),
+        }
+    }
+
+    // Replaces every registered factory with those in `registry`.
+    pub fn with_registry(&mut self, registry: &StageRegistry<C, V>) -> &mut Self {
+        self.deserialize_map = Rc::clone(&registry.factories);
+        self.schemas = Rc::clone(&registry.schemas);
+        self.clear_cached();
+        self.prepared = None;
+        self
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use serde_yaml::Value;
+    use std::rc::Rc;
+
+    use super::StageRegistry;
+    use crate::{
+        test::{Add, CalcContext, Mul},
+        StageFile, StageManager,
+    };
+
+    #[test]
+    fn one_registry_many_managers() {
+        let registry = StageRegistry::<CalcContext, Value>::build(|m| {
+            m.register::<Add>()?.register::<Mul>()?;
+            Ok(())
+        })
+        .unwrap();
+
+        let run = |yaml_str: &str| {
+            let file: StageFile<Value> = serde_yaml::from_str(yaml_str).unwrap();
+            let mut m = StageManager::from_file(file);
+            m.with_registry(&registry);
+            assert!(Rc::ptr_eq(&m.deserialize_map, &registry.factories));
+
+            let mut c = CalcContext { x: 1 };
+            m.run_stages(&mut c).unwrap();
+            c.x
+        };
+
+        assert_eq!(run("stages: [{name: add, args: {x: 2}}]"), 3);
+        assert_eq!(
+            run("[{name: mul, args: {x: 4}}, {name: add, args: {x: 1}}]"),
+            5
+        );
+    }
+}
@@ -0,0 +1,148 @@
+use serde::{Deserialize, Deserializer};
+
+use crate::{StageArgs, StageFile, StageManager};
+
+// Stage keys (`id`, or the name) that changed in a reload, in file order.
+// The n-th entry with a given key in the old file is compared with the n-th
+// entry with that key in the new one.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ReloadDiff {
+    pub added: Vec<String>,
+    pub removed: Vec<String>,
+    pub modified: Vec<String>,
+}
+
+impl ReloadDiff {
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.modified.is_empty()
+    }
+}
+
+impl<'de, C, V> StageManager<C, V>
+where
+    V: Deserialize<'de> + Deserializer<'de> + Clone + PartialEq,
+{
+    // Swaps in a new stage file, keeping every registration.
+    pub fn reload(&mut self, new_file: StageFile<V>) -> ReloadDiff {
+        let old = std::mem::replace(&mut self.file, new_file);
+        self.source = None;
+        self.prepared = None;
+
+        let mut diff = ReloadDiff::default();
+        let mut matched = vec![false; self.file.stages.len()];
+        for (i, s) in old.stages.iter().enumerate() {
+            let occurrence = nth_with_key(&old.stages[..i], s.key());
+            let found = self
+                .file
+                .stages
+                .iter()
+                .enumerate()
+                .filter(|(_, n)| n.key() == s.key())
+                .nth(occurrence);
+            match found {
+                Some((j, n)) => {
+                    matched[j] = true;
+                    if n != s {
+                        diff.modified.push(s.key().to_string());
+                    }
+                }
+                None => diff.removed.push(s.key().to_string()),
+            }
+        }
+        for (s, matched) in self.file.stages.iter().zip(matched) {
+            if !matched {
+                diff.added.push(s.key().to_string());
+            }
+        }
+        diff
+    }
+}
+
+fn nth_with_key<V>(stages: &[StageArgs<V>], key: &str) -> usize {
+    stages.iter().filter(|s| s.key() == key).count()
+}
+
+#[cfg(test)]
+mod test {
+    use serde_yaml::Value;
+
+    use super::*;
+    use crate::test::{Add, CalcContext, Mul};
+
+    fn file(yaml_str: &str) -> StageFile<Value> {
+        serde_yaml::from_str(yaml_str).unwrap()
+    }
+
+    #[test]
+    fn reload_reports_changes() {
+        let mut m = StageManager::from_file(file(
+            r#"
+        stages:
+        - name: add
+          args:
+            x: 2
+        - name: mul
+          args:
+            x: 3
+        - name: add
+          args:
+            x: 5
+        "#,
+        ));
+        m.register::<Mul>().unwrap().register::<Add>().unwrap();
+
+        let diff = m.reload(file(
+            r#"
+        stages:
+        - name: add
+          args:
+            x: 2
+        - name: mul
+          args:
+            x: 3
+        "#,
+        ));
+        assert_eq!(
+            diff,
+            ReloadDiff {
+                removed: vec!["add".to_string()],
+                ..ReloadDiff::default()
+            }
+        );
+
+        let mut c = CalcContext { x: 1 };
+        m.run_stages(&mut c).unwrap();
+        assert_eq!(c.x, 9);
+
+        let diff = m.reload(file(
+            r#"
+        stages:
+        - name: add
+          args:
+            x: 4
+        - name: mul
+          id: triple
+          args:
+            x: 3
+        "#,
+        ));
+        assert_eq!(diff.added, vec!["triple"]);
+        assert_eq!(diff.removed, vec!["mul"]);
+        assert_eq!(diff.modified, vec!["add"]);
+
+        let diff = m.reload(file(
+            r#"
+        stages:
+        - name: add
+          tags: [slow]
+          args:
+            x: 4
+        - name: add
+          id: triple
+          args:
+            x: 3
+        "#,
+        ));
+        assert_eq!(diff.modified, vec!["add", "triple"]);
+    }
+}
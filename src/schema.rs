@@ -0,0 +1,542 @@
+use serde::{
+    de::{
+        self, value, DeserializeSeed, EnumAccess, IntoDeserializer, MapAccess, SeqAccess,
+        VariantAccess, Visitor,
+    },
+    Deserialize, Deserializer,
+};
+use std::{cell::OnceCell, collections::HashMap, fmt};
+
+use crate::StageManager;
+
+// The shape of a value a stage's args deserialize from, as far as it can be
+// told from the calls its Deserialize impl makes.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ArgType {
+    Any,
+    Null,
+    Bool,
+    Integer,
+    Number,
+    String,
+    Array(Box<ArgType>),
+    Tuple(Vec<ArgType>),
+    Map,
+    Object(Vec<(String, ArgType)>),
+    Optional(Box<ArgType>),
+    Enum(Vec<&'static str>),
+}
+
+impl fmt::Display for ArgType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ArgType::Any => f.write_str("any"),
+            ArgType::Null => f.write_str("null"),
+            ArgType::Bool => f.write_str("boolean"),
+            ArgType::Integer => f.write_str("integer"),
+            ArgType::Number => f.write_str("number"),
+            ArgType::String => f.write_str("string"),
+            ArgType::Array(item) => write!(f, "array of {}", item),
+            ArgType::Tuple(items) => {
+                let items: Vec<_> = items.iter().map(ArgType::to_string).collect();
+                write!(f, "[{}]", items.join(", "))
+            }
+            ArgType::Map | ArgType::Object(_) => f.write_str("object"),
+            ArgType::Optional(inner) => write!(f, "optional {}", inner),
+            ArgType::Enum(variants) => write!(f, "one of {}", variants.join(", ")),
+        }
+    }
+}
+
+// The top level fields of a struct stage's args, for every stage that
+// deserializes as a struct.
+#[derive(Debug, Clone, PartialEq)]
+pub struct StageSchema {
+    pub fields: Vec<(String, ArgType)>,
+}
+
+impl StageSchema {
+    pub fn field(&self, name: &str) -> Option<&ArgType> {
+        self.fields
+            .iter()
+            .find(|(n, _)| n == name)
+            .map(|(_, ty)| ty)
+    }
+}
+
+impl<'de, C, V> StageManager<C, V>
+where
+    V: Deserialize<'de> + Deserializer<'de> + Clone,
+{
+    pub fn schema(&self, name: &str) -> Option<&StageSchema> {
+        self.schemas.get(self.resolve(name)?)?.get().as_ref()
+    }
+}
+
+// A probe of a stage's args type, run the first time its result is asked
+// for rather than on every register.
+#[derive(Clone)]
+pub(crate) struct LazyProbe<T> {
+    probe: fn() -> T,
+    result: OnceCell<T>,
+}
+
+impl<T> LazyProbe<T> {
+    pub(crate) fn new(probe: fn() -> T) -> Self {
+        Self {
+            probe,
+            result: OnceCell::new(),
+        }
+    }
+
+    pub(crate) fn get(&self) -> &T {
+        self.result.get_or_init(self.probe)
+    }
+}
+
+pub(crate) type SchemaProbes = HashMap<String, LazyProbe<Option<StageSchema>>>;
+
+pub(crate) fn stage_schema<'de, S: Deserialize<'de>>() -> Option<StageSchema> {
+    let mut ty = ArgType::Any;
+    let _ = S::deserialize(Probe {
+        slot: &mut ty,
+        depth: 0,
+    });
+    match ty {
+        ArgType::Object(fields) => Some(StageSchema { fields }),
+        _ => None,
+    }
+}
+
+// Deep enough for any sensible args while stopping recursive types.
+const MAX_DEPTH: usize = 16;
+
+// A deserializer that hands every visitor the simplest value it asks for and
+// records what was asked for in `slot`.
+struct Probe<'a> {
+    slot: &'a mut ArgType,
+    depth: usize,
+}
+
+fn probe(slot: &mut ArgType, depth: usize) -> Result<Probe<'_>, value::Error> {
+    if depth >= MAX_DEPTH {
+        return Err(de::Error::custom("args nest too deeply to describe"));
+    }
+    Ok(Probe { slot, depth })
+}
+
+// A visitor's result alongside the shapes recorded while producing it.
+type Probed<T, S> = (Result<T, value::Error>, S);
+
+fn visit_tuple<'de, W: Visitor<'de>>(
+    len: usize,
+    depth: usize,
+    visitor: W,
+) -> Probed<W::Value, Vec<ArgType>> {
+    let mut items = vec![ArgType::Any; len];
+    let result = items
+        .iter_mut()
+        .map(|slot| probe(slot, depth + 1))
+        .collect::<Result<Vec<_>, _>>()
+        .and_then(|probes| visitor.visit_seq(SeqProbe(probes.into_iter())));
+    (result, items)
+}
+
+fn visit_struct<'de, W: Visitor<'de>>(
+    fields: &'static [&'static str],
+    depth: usize,
+    visitor: W,
+) -> Probed<W::Value, Vec<(String, ArgType)>> {
+    let mut slots = vec![ArgType::Any; fields.len()];
+    let result = slots
+        .iter_mut()
+        .map(|slot| probe(slot, depth + 1))
+        .collect::<Result<Vec<_>, _>>()
+        .and_then(|probes| {
+            visitor.visit_map(StructProbe {
+                fields: fields.iter().zip(probes).collect::<Vec<_>>().into_iter(),
+                value: None,
+            })
+        });
+    let fields = fields.iter().map(|f| f.to_string()).zip(slots).collect();
+    (result, fields)
+}
+
+impl<'de, 'a> Deserializer<'de> for Probe<'a> {
+    type Error = value::Error;
+
+    fn deserialize_any<W: Visitor<'de>>(self, visitor: W) -> Result<W::Value, Self::Error> {
+        *self.slot = ArgType::Any;
+        visitor.visit_unit()
+    }
+
+    fn deserialize_bool<W: Visitor<'de>>(self, visitor: W) -> Result<W::Value, Self::Error> {
+        *self.slot = ArgType::Bool;
+        visitor.visit_bool(false)
+    }
+
+    fn deserialize_i8<W: Visitor<'de>>(self, visitor: W) -> Result<W::Value, Self::Error> {
+        self.deserialize_u64(visitor)
+    }
+
+    fn deserialize_i16<W: Visitor<'de>>(self, visitor: W) -> Result<W::Value, Self::Error> {
+        self.deserialize_u64(visitor)
+    }
+
+    fn deserialize_i32<W: Visitor<'de>>(self, visitor: W) -> Result<W::Value, Self::Error> {
+        self.deserialize_u64(visitor)
+    }
+
+    fn deserialize_i64<W: Visitor<'de>>(self, visitor: W) -> Result<W::Value, Self::Error> {
+        self.deserialize_u64(visitor)
+    }
+
+    fn deserialize_u8<W: Visitor<'de>>(self, visitor: W) -> Result<W::Value, Self::Error> {
+        self.deserialize_u64(visitor)
+    }
+
+    fn deserialize_u16<W: Visitor<'de>>(self, visitor: W) -> Result<W::Value, Self::Error> {
+        self.deserialize_u64(visitor)
+    }
+
+    fn deserialize_u32<W: Visitor<'de>>(self, visitor: W) -> Result<W::Value, Self::Error> {
+        self.deserialize_u64(visitor)
+    }
+
+    fn deserialize_u64<W: Visitor<'de>>(self, visitor: W) -> Result<W::Value, Self::Error> {
+        *self.slot = ArgType::Integer;
+        visitor.visit_u64(0)
+    }
+
+    fn deserialize_f32<W: Visitor<'de>>(self, visitor: W) -> Result<W::Value, Self::Error> {
+        self.deserialize_f64(visitor)
+    }
+
+    fn deserialize_f64<W: Visitor<'de>>(self, visitor: W) -> Result<W::Value, Self::Error> {
+        *self.slot = ArgType::Number;
+        visitor.visit_f64(0.0)
+    }
+
+    fn deserialize_char<W: Visitor<'de>>(self, visitor: W) -> Result<W::Value, Self::Error> {
+        *self.slot = ArgType::String;
+        visitor.visit_char('a')
+    }
+
+    fn deserialize_str<W: Visitor<'de>>(self, visitor: W) -> Result<W::Value, Self::Error> {
+        *self.slot = ArgType::String;
+        visitor.visit_str("")
+    }
+
+    fn deserialize_string<W: Visitor<'de>>(self, visitor: W) -> Result<W::Value, Self::Error> {
+        self.deserialize_str(visitor)
+    }
+
+    fn deserialize_bytes<W: Visitor<'de>>(self, visitor: W) -> Result<W::Value, Self::Error> {
+        *self.slot = ArgType::Array(Box::new(ArgType::Integer));
+        visitor.visit_bytes(&[])
+    }
+
+    fn deserialize_byte_buf<W: Visitor<'de>>(self, visitor: W) -> Result<W::Value, Self::Error> {
+        self.deserialize_bytes(visitor)
+    }
+
+    fn deserialize_option<W: Visitor<'de>>(self, visitor: W) -> Result<W::Value, Self::Error> {
+        let mut inner = ArgType::Any;
+        let result = probe(&mut inner, self.depth + 1).and_then(|p| visitor.visit_some(p));
+        *self.slot = ArgType::Optional(Box::new(inner));
+        result
+    }
+
+    fn deserialize_unit<W: Visitor<'de>>(self, visitor: W) -> Result<W::Value, Self::Error> {
+        *self.slot = ArgType::Null;
+        visitor.visit_unit()
+    }
+
+    fn deserialize_unit_struct<W: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        visitor: W,
+    ) -> Result<W::Value, Self::Error> {
+        self.deserialize_unit(visitor)
+    }
+
+    fn deserialize_newtype_struct<W: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        visitor: W,
+    ) -> Result<W::Value, Self::Error> {
+        visitor.visit_newtype_struct(self)
+    }
+
+    fn deserialize_seq<W: Visitor<'de>>(self, visitor: W) -> Result<W::Value, Self::Error> {
+        let (result, mut items) = visit_tuple(1, self.depth, visitor);
+        *self.slot = ArgType::Array(Box::new(items.remove(0)));
+        result
+    }
+
+    fn deserialize_tuple<W: Visitor<'de>>(
+        self,
+        len: usize,
+        visitor: W,
+    ) -> Result<W::Value, Self::Error> {
+        let (result, items) = visit_tuple(len, self.depth, visitor);
+        *self.slot = ArgType::Tuple(items);
+        result
+    }
+
+    fn deserialize_tuple_struct<W: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        len: usize,
+        visitor: W,
+    ) -> Result<W::Value, Self::Error> {
+        self.deserialize_tuple(len, visitor)
+    }
+
+    fn deserialize_map<W: Visitor<'de>>(self, visitor: W) -> Result<W::Value, Self::Error> {
+        *self.slot = ArgType::Map;
+        visitor.visit_map(StructProbe {
+            fields: Vec::new().into_iter(),
+            value: None,
+        })
+    }
+
+    fn deserialize_struct<W: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        fields: &'static [&'static str],
+        visitor: W,
+    ) -> Result<W::Value, Self::Error> {
+        let (result, fields) = visit_struct(fields, self.depth, visitor);
+        *self.slot = ArgType::Object(fields);
+        result
+    }
+
+    fn deserialize_enum<W: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        variants: &'static [&'static str],
+        visitor: W,
+    ) -> Result<W::Value, Self::Error> {
+        *self.slot = ArgType::Enum(variants.to_vec());
+        match variants.first() {
+            Some(&variant) => visitor.visit_enum(EnumProbe {
+                variant,
+                depth: self.depth,
+            }),
+            None => Err(de::Error::custom("enum has no variants")),
+        }
+    }
+
+    fn deserialize_identifier<W: Visitor<'de>>(self, visitor: W) -> Result<W::Value, Self::Error> {
+        self.deserialize_str(visitor)
+    }
+
+    fn deserialize_ignored_any<W: Visitor<'de>>(self, visitor: W) -> Result<W::Value, Self::Error> {
+        self.deserialize_any(visitor)
+    }
+}
+
+struct SeqProbe<'a>(std::vec::IntoIter<Probe<'a>>);
+
+impl<'de, 'a> SeqAccess<'de> for SeqProbe<'a> {
+    type Error = value::Error;
+
+    fn next_element_seed<T: DeserializeSeed<'de>>(
+        &mut self,
+        seed: T,
+    ) -> Result<Option<T::Value>, Self::Error> {
+        self.0.next().map(|p| seed.deserialize(p)).transpose()
+    }
+}
+
+struct StructProbe<'a> {
+    fields: std::vec::IntoIter<(&'static &'static str, Probe<'a>)>,
+    value: Option<Probe<'a>>,
+}
+
+impl<'de, 'a> MapAccess<'de> for StructProbe<'a> {
+    type Error = value::Error;
+
+    fn next_key_seed<K: DeserializeSeed<'de>>(
+        &mut self,
+        seed: K,
+    ) -> Result<Option<K::Value>, Self::Error> {
+        match self.fields.next() {
+            Some((&field, p)) => {
+                self.value = Some(p);
+                seed.deserialize(field.into_deserializer()).map(Some)
+            }
+            None => Ok(None),
+        }
+    }
+
+    fn next_value_seed<T: DeserializeSeed<'de>>(
+        &mut self,
+        seed: T,
+    ) -> Result<T::Value, Self::Error> {
+        match self.value.take() {
+            Some(p) => seed.deserialize(p),
+            None => Err(de::Error::custom("value requested before key")),
+        }
+    }
+}
+
+struct EnumProbe {
+    variant: &'static str,
+    depth: usize,
+}
+
+impl<'de> EnumAccess<'de> for EnumProbe {
+    type Error = value::Error;
+    type Variant = Self;
+
+    fn variant_seed<T: DeserializeSeed<'de>>(
+        self,
+        seed: T,
+    ) -> Result<(T::Value, Self), Self::Error> {
+        let variant = seed.deserialize(self.variant.into_deserializer())?;
+        Ok((variant, self))
+    }
+}
+
+impl<'de> VariantAccess<'de> for EnumProbe {
+    type Error = value::Error;
+
+    fn unit_variant(self) -> Result<(), Self::Error> {
+        Ok(())
+    }
+
+    fn newtype_variant_seed<T: DeserializeSeed<'de>>(
+        self,
+        seed: T,
+    ) -> Result<T::Value, Self::Error> {
+        let mut inner = ArgType::Any;
+        seed.deserialize(probe(&mut inner, self.depth + 1)?)
+    }
+
+    fn tuple_variant<W: Visitor<'de>>(
+        self,
+        len: usize,
+        visitor: W,
+    ) -> Result<W::Value, Self::Error> {
+        visit_tuple(len, self.depth, visitor).0
+    }
+
+    fn struct_variant<W: Visitor<'de>>(
+        self,
+        fields: &'static [&'static str],
+        visitor: W,
+    ) -> Result<W::Value, Self::Error> {
+        visit_struct(fields, self.depth, visitor).0
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use serde_yaml::Value;
+    use std::cell::Cell;
+
+    use super::*;
+    use crate::{
+        stages::Retry,
+        test::{Add, CalcContext},
+        Stage, StageError, StageFile,
+    };
+
+    #[derive(Deserialize)]
+    #[allow(dead_code)]
+    enum Mode {
+        Fast,
+        Careful { passes: u8 },
+    }
+
+    #[derive(Deserialize)]
+    #[allow(dead_code)]
+    struct Everything {
+        name: String,
+        ratio: f64,
+        #[serde(default)]
+        limit: Option<u32>,
+        tags: Vec<String>,
+        mode: Mode,
+        point: (i32, i32),
+        #[serde(rename = "on")]
+        enabled: bool,
+    }
+
+    #[test]
+    fn schema_describes_args() {
+        let file: StageFile<Value> = serde_yaml::from_str("stages: []").unwrap();
+        let mut m = StageManager::<CalcContext, _>::from_file(file);
+        m.register::<Add>()
+            .unwrap()
+            .register_named::<Retry<Add>>("retry")
+            .unwrap();
+
+        let add = m.schema("add").unwrap();
+        assert_eq!(add.field("x"), Some(&ArgType::Integer));
+        assert_eq!(add.field("x").unwrap().to_string(), "integer");
+
+        let retry = m.schema("retry").unwrap();
+        assert_eq!(
+            retry.field("inner"),
+            Some(&ArgType::Object(vec![("x".to_string(), ArgType::Integer)]))
+        );
+        assert!(m.schema("mul").is_none());
+
+        let schema = stage_schema::<Everything>().unwrap();
+        let fields: Vec<_> = schema
+            .fields
+            .iter()
+            .map(|(name, ty)| format!("{}: {}", name, ty))
+            .collect();
+        assert_eq!(
+            fields,
+            vec![
+                "name: string",
+                "ratio: number",
+                "limit: optional integer",
+                "tags: array of string",
+                "mode: one of Fast, Careful",
+                "point: [integer, integer]",
+                "on: boolean",
+            ]
+        );
+    }
+
+    thread_local! {
+        static PROBES: Cell<usize> = const { Cell::new(0) };
+    }
+
+    // Counts how often its Deserialize impl is called.
+    struct Counted;
+
+    impl<'de> Deserialize<'de> for Counted {
+        fn deserialize<D: Deserializer<'de>>(d: D) -> Result<Self, D::Error> {
+            PROBES.with(|n| n.set(n.get() + 1));
+            de::IgnoredAny::deserialize(d).map(|_| Counted)
+        }
+    }
+
+    impl Stage for Counted {
+        type C = CalcContext;
+
+        fn run(&self, _c: &mut Self::C) -> Result<(), StageError> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn schema_is_probed_on_first_use() {
+        let file: StageFile<Value> = serde_yaml::from_str("stages: []").unwrap();
+        let mut m = StageManager::<CalcContext, _>::from_file(file);
+        PROBES.with(|n| n.set(0));
+        m.register_named::<Counted>("counted").unwrap();
+        assert_eq!(PROBES.with(Cell::get), 0);
+
+        assert!(m.schema("counted").is_none());
+        assert!(m.schema("counted").is_none());
+        assert_eq!(PROBES.with(Cell::get), 1);
+    }
+}
@@ -0,0 +1,73 @@
+use serde::{Deserialize, Deserializer};
+use std::{fmt, ops::Deref};
+
+use crate::StageManager;
+
+/// A manager that can run but no longer be registered into, so a stage's
+/// `setup` can't register factories at the wrong time. Derefs to the
+/// manager's `&self` API.
+///
+/// ```compile_fail
+/// use stage_fright::{StageFile, StageManager};
+///
+/// let file: StageFile<serde_yaml::Value> = serde_yaml::from_str("stages: []").unwrap();
+/// let mut sealed = StageManager::<(), _>::from_file(file).seal();
+/// sealed.register_named::<StageManager<(), serde_yaml::Value>>("nested");
+/// ```
+pub struct SealedStageManager<C, V>(StageManager<C, V>);
+
+impl<'de, C, V> StageManager<C, V>
+where
+    V: Deserialize<'de> + Deserializer<'de> + Clone,
+{
+    pub fn seal(self) -> SealedStageManager<C, V> {
+        SealedStageManager(self)
+    }
+}
+
+impl<C, V> Deref for SealedStageManager<C, V> {
+    type Target = StageManager<C, V>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+impl<C, V: fmt::Debug> fmt::Debug for SealedStageManager<C, V> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "SealedStageManager({:?})", self.0)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use serde_yaml::Value;
+
+    use crate::{
+        test::{Add, CalcContext, Mul},
+        StageFile, StageManager,
+    };
+
+    #[test]
+    fn sealed_manager_runs() {
+        let yaml_str = r#"
+        stages:
+        - name: add
+          args:
+            x: 2
+        - name: mul
+          args:
+            x: 3
+        "#;
+
+        let file: StageFile<Value> = serde_yaml::from_str(yaml_str).unwrap();
+        let mut m = StageManager::from_file(file);
+        m.register::<Add>().unwrap().register::<Mul>().unwrap();
+        let sealed = m.seal();
+
+        let mut c = CalcContext { x: 1 };
+        sealed.run_stages(&mut c).unwrap();
+        assert_eq!(c.x, 9);
+        assert!(sealed.is_registered("add"));
+    }
+}
@@ -0,0 +1,186 @@
+use serde::Deserialize;
+use std::{cmp::Ordering, fmt, marker::PhantomData};
+
+use crate::{Stage, StageError, StageName};
+
+// A context that can look up its fields by name, for Assert.
+pub trait Queryable {
+    fn query(&self, field: &str) -> Option<Scalar>;
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(untagged)]
+pub enum Scalar {
+    Bool(bool),
+    Int(i64),
+    Float(f64),
+    Str(String),
+}
+
+impl Scalar {
+    // Ints and floats compare with each other; other mixed kinds don't.
+    fn compare(&self, other: &Scalar) -> Option<Ordering> {
+        match (self, other) {
+            (Scalar::Bool(a), Scalar::Bool(b)) => a.partial_cmp(b),
+            (Scalar::Int(a), Scalar::Int(b)) => a.partial_cmp(b),
+            (Scalar::Int(a), Scalar::Float(b)) => (*a as f64).partial_cmp(b),
+            (Scalar::Float(a), Scalar::Int(b)) => a.partial_cmp(&(*b as f64)),
+            (Scalar::Float(a), Scalar::Float(b)) => a.partial_cmp(b),
+            (Scalar::Str(a), Scalar::Str(b)) => a.partial_cmp(b),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for Scalar {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Scalar::Bool(b) => write!(f, "{}", b),
+            Scalar::Int(i) => write!(f, "{}", i),
+            Scalar::Float(x) => write!(f, "{}", x),
+            Scalar::Str(s) => write!(f, "{:?}", s),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+pub enum Op {
+    #[serde(rename = "==")]
+    Eq,
+    #[serde(rename = "!=")]
+    Ne,
+    #[serde(rename = "<")]
+    Lt,
+    #[serde(rename = "<=")]
+    Le,
+    #[serde(rename = ">")]
+    Gt,
+    #[serde(rename = ">=")]
+    Ge,
+}
+
+impl Op {
+    fn holds(self, ordering: Option<Ordering>) -> bool {
+        match (self, ordering) {
+            (Op::Ne, None) => true,
+            (_, None) => false,
+            (Op::Eq, Some(o)) => o == Ordering::Equal,
+            (Op::Ne, Some(o)) => o != Ordering::Equal,
+            (Op::Lt, Some(o)) => o == Ordering::Less,
+            (Op::Le, Some(o)) => o != Ordering::Greater,
+            (Op::Gt, Some(o)) => o == Ordering::Greater,
+            (Op::Ge, Some(o)) => o != Ordering::Less,
+        }
+    }
+}
+
+impl fmt::Display for Op {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Op::Eq => "==",
+            Op::Ne => "!=",
+            Op::Lt => "<",
+            Op::Le => "<=",
+            Op::Gt => ">",
+            Op::Ge => ">=",
+        })
+    }
+}
+
+// Checks `field op value` against the context and fails the run with
+// StageError::AssertionFailed when it doesn't hold. The context is never
+// changed.
+#[derive(Debug, Deserialize)]
+pub struct Assert<C> {
+    field: String,
+    op: Op,
+    value: Scalar,
+    #[serde(skip)]
+    _context: PhantomData<fn(&C)>,
+}
+
+impl<C> StageName for Assert<C> {
+    fn stage_name() -> &'static str {
+        "assert"
+    }
+}
+
+impl<C: Queryable> Stage for Assert<C> {
+    type C = C;
+
+    fn run(&self, c: &mut Self::C) -> Result<(), StageError> {
+        let actual = c.query(&self.field);
+        let holds = actual
+            .as_ref()
+            .is_some_and(|a| self.op.holds(a.compare(&self.value)));
+        if holds {
+            return Ok(());
+        }
+        Err(StageError::AssertionFailed {
+            assertion: format!("{} {} {}", self.field, self.op, self.value),
+            actual: actual.map(|a| a.to_string()),
+        })
+    }
+
+    fn is_mutating(&self) -> bool {
+        false
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use serde_yaml::Value;
+
+    use super::{Assert, Queryable, Scalar};
+    use crate::{
+        test::{Add, CalcContext},
+        StageError, StageFile, StageManager,
+    };
+
+    impl Queryable for CalcContext {
+        fn query(&self, field: &str) -> Option<Scalar> {
+            match field {
+                "x" => Some(Scalar::Int(self.x)),
+                _ => None,
+            }
+        }
+    }
+
+    fn run(expected: i64) -> Result<(), StageError> {
+        let yaml_str = format!(
+            r#"
+        stages:
+        - name: add
+          args:
+            x: 1
+        - name: add
+          args:
+            x: 1
+        - name: assert
+          args:
+            field: x
+            op: "=="
+            value: {}
+        "#,
+            expected
+        );
+
+        let file: StageFile<Value> = serde_yaml::from_str(&yaml_str).unwrap();
+        let mut m = StageManager::from_file(file);
+        m.register::<Add>()
+            .unwrap()
+            .register::<Assert<CalcContext>>()
+            .unwrap();
+
+        let mut c = CalcContext { x: 1 };
+        m.run_stages(&mut c)
+    }
+
+    #[test]
+    fn assert_checks_the_context() {
+        run(3).unwrap();
+
+        let err = run(4).unwrap_err();
+        assert_eq!(err.to_string(), "assertion `x == 4` failed, found 3");
+    }
+}
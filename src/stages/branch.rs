@@ -0,0 +1,163 @@
+use serde::{Deserialize, Deserializer};
+use std::{any::Any, fmt, rc::Rc};
+
+use crate::{RegisterError, Stage, StageArgs, StageError, StageFile, StageFlow, StageManager};
+
+// Runs the `then` stages when the condition holds for the context and the
+// `else` stages otherwise. Both branches are nested managers; register one
+// with StageManager::register_if.
+pub struct If<C, V> {
+    cond: Rc<dyn Fn(&C) -> bool>,
+    then: StageManager<C, V>,
+    otherwise: StageManager<C, V>,
+}
+
+#[derive(Deserialize)]
+struct IfArgs<V> {
+    then: Vec<StageArgs<V>>,
+    #[serde(rename = "else", default = "Vec::new")]
+    otherwise: Vec<StageArgs<V>>,
+}
+
+impl<'de, C, V> StageManager<C, V>
+where
+    C: 'static,
+    V: 'static + Deserialize<'de> + Deserializer<'de> + Clone,
+{
+    // Registers an If stage under `name` that branches on `cond`. `register`
+    // adds the stages available to both branches, which are validated as the
+    // If stage is deserialized.
+    pub fn register_if<P, R>(
+        &mut self,
+        name: &str,
+        cond: P,
+        register: R,
+    ) -> Result<&mut Self, RegisterError>
+    where
+        P: 'static + Fn(&C) -> bool,
+        R: 'static + Fn(&mut StageManager<C, V>) -> Result<(), RegisterError>,
+    {
+        let cond: Rc<dyn Fn(&C) -> bool> = Rc::new(cond);
+        self.register_fn(name, move |v| {
+            let args = IfArgs::<V>::deserialize(v).map_err(|e| e.to_string())?;
+            let branch = |stages| -> Result<_, StageError> {
+                let mut m = StageManager::from_file(StageFile { version: 0, stages });
+                register(&mut m).map_err(StageError::Register)?;
+                m.validate()
+                    .map_err(|names| StageError::UnknownStage { names })?;
+                Ok(m)
+            };
+            Ok(If {
+                cond: cond.clone(),
+                then: branch(args.then)?,
+                otherwise: branch(args.otherwise)?,
+            })
+        })
+    }
+}
+
+impl<'de, C, V> Stage for If<C, V>
+where
+    V: Deserialize<'de> + Deserializer<'de> + Clone,
+{
+    type C = C;
+
+    fn run(&self, c: &mut Self::C) -> Result<(), StageError> {
+        self.run_shared(c, &()).map(|_| ())
+    }
+
+    fn run_shared(&self, c: &mut Self::C, shared: &dyn Any) -> Result<StageFlow, StageError> {
+        if (self.cond)(c) {
+            self.then.run_shared(c, shared)
+        } else {
+            self.otherwise.run_shared(c, shared)
+        }
+    }
+}
+
+impl<C, V> fmt::Debug for If<C, V>
+where
+    V: fmt::Debug,
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("If")
+            .field("then", &self.then)
+            .field("else", &self.otherwise)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use serde_yaml::Value;
+
+    use crate::{
+        test::{Add, CalcContext, Mul},
+        StageError, StageFile, StageManager,
+    };
+
+    fn manager(yaml_str: &str) -> StageManager<CalcContext, Value> {
+        let file: StageFile<Value> = serde_yaml::from_str(yaml_str).unwrap();
+        let mut m = StageManager::from_file(file);
+        m.register_if(
+            "if_positive",
+            |c: &CalcContext| c.x > 0,
+            |m| {
+                m.register::<Add>()?.register::<Mul>()?;
+                Ok(())
+            },
+        )
+        .unwrap();
+        m
+    }
+
+    #[test]
+    fn branch_on_context() {
+        let m = manager(
+            r#"
+        stages:
+        - name: if_positive
+          args:
+            then:
+            - name: mul
+              args:
+                x: 3
+            else:
+            - name: add
+              args:
+                x: 10
+        "#,
+        );
+
+        let mut c = CalcContext { x: 2 };
+        m.run_stages(&mut c).unwrap();
+        assert_eq!(c.x, 6);
+
+        let mut c = CalcContext { x: -2 };
+        m.run_stages(&mut c).unwrap();
+        assert_eq!(c.x, 8);
+    }
+
+    #[test]
+    fn branch_stages_are_validated() {
+        let m = manager(
+            r#"
+        stages:
+        - name: if_positive
+          args:
+            then:
+            - name: sub
+              args:
+                x: 3
+        "#,
+        );
+
+        let mut c = CalcContext { x: 2 };
+        match m.run_stages(&mut c) {
+            Err(StageError::Deserialize { stage_name, .. }) => {
+                assert_eq!(stage_name, "if_positive")
+            }
+            other => panic!("expected Deserialize, got {:?}", other),
+        }
+    }
+}
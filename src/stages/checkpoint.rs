@@ -0,0 +1,201 @@
+use serde::{Deserialize, Deserializer};
+use std::{any::Any, fmt, rc::Rc};
+
+use crate::{RegisterError, Stage, StageError, StageFile, StageFlow, StageManager};
+
+type FnRegister<C, V> = dyn Fn(&mut StageManager<C, V>) -> Result<(), RegisterError>;
+
+// Runs nested stages against the context and restores the context as it was
+// before them if any fails, like a savepoint. Register one with
+// StageManager::register_checkpoint.
+pub struct Checkpoint<C, V> {
+    stages: StageManager<C, V>,
+}
+
+impl<'de, C, V> StageManager<C, V>
+where
+    C: 'static + Clone,
+    V: 'static + Deserialize<'de> + Deserializer<'de> + Clone,
+{
+    // Registers a Checkpoint stage under `name`, whose args are a stage file.
+    // `register` adds the stages available inside it; the checkpoint itself
+    // is always available so checkpoints can nest.
+    pub fn register_checkpoint<R>(
+        &mut self,
+        name: &str,
+        register: R,
+    ) -> Result<&mut Self, RegisterError>
+    where
+        R: 'static + Fn(&mut StageManager<C, V>) -> Result<(), RegisterError>,
+    {
+        self.register_checkpoint_with(name, Rc::new(register))
+    }
+
+    fn register_checkpoint_with(
+        &mut self,
+        name: &str,
+        register: Rc<FnRegister<C, V>>,
+    ) -> Result<&mut Self, RegisterError> {
+        let own_name = name.to_string();
+        self.register_fn(name, move |v| {
+            let file = StageFile::deserialize(v).map_err(|e| e.to_string())?;
+            let mut stages = StageManager::from_file(file);
+            stages
+                .register_checkpoint_with(&own_name, register.clone())
+                .and_then(|m| register(m))
+                .map_err(StageError::Register)?;
+            stages
+                .validate()
+                .map_err(|names| StageError::UnknownStage { names })?;
+            Ok(Checkpoint { stages })
+        })
+    }
+}
+
+impl<'de, C, V> Stage for Checkpoint<C, V>
+where
+    C: Clone,
+    V: Deserialize<'de> + Deserializer<'de> + Clone,
+{
+    type C = C;
+
+    fn run(&self, c: &mut Self::C) -> Result<(), StageError> {
+        self.run_shared(c, &()).map(|_| ())
+    }
+
+    fn run_shared(&self, c: &mut Self::C, shared: &dyn Any) -> Result<StageFlow, StageError> {
+        let saved = c.clone();
+        let result = self.stages.run_shared(c, shared);
+        if result.is_err() {
+            *c = saved;
+        }
+        result
+    }
+}
+
+impl<C, V> fmt::Debug for Checkpoint<C, V>
+where
+    V: fmt::Debug,
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Checkpoint")
+            .field("stages", &self.stages)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use serde::Deserialize;
+    use serde_yaml::Value;
+    use std::cell::RefCell;
+
+    use crate::{
+        test::{Add, CalcContext},
+        ErrorPolicy, Stage, StageError, StageFile, StageManager, StageName,
+    };
+
+    thread_local! {
+        static SEEN: RefCell<Vec<i64>> = const { RefCell::new(Vec::new()) };
+    }
+
+    #[derive(Deserialize)]
+    struct Peek {}
+
+    impl StageName for Peek {
+        fn stage_name() -> &'static str {
+            "peek"
+        }
+    }
+
+    impl Stage for Peek {
+        type C = CalcContext;
+
+        fn run(&self, c: &mut Self::C) -> Result<(), StageError> {
+            SEEN.with(|seen| seen.borrow_mut().push(c.x));
+            Ok(())
+        }
+    }
+
+    #[derive(Deserialize)]
+    struct Fail {}
+
+    impl Stage for Fail {
+        type C = CalcContext;
+
+        fn run(&self, _c: &mut Self::C) -> Result<(), StageError> {
+            Err(StageError::custom("nope"))
+        }
+    }
+
+    fn manager(yaml_str: &str) -> StageManager<CalcContext, Value> {
+        let register = |m: &mut StageManager<CalcContext, Value>| {
+            m.register::<Add>()?
+                .register::<Peek>()?
+                .register_named::<Fail>("fail")?
+                .with_error_policy(ErrorPolicy::ContinueCollect);
+            Ok(())
+        };
+        let file: StageFile<Value> = serde_yaml::from_str(yaml_str).unwrap();
+        let mut m = StageManager::from_file(file);
+        register(&mut m).unwrap();
+        m.with_error_policy(ErrorPolicy::Abort)
+            .register_checkpoint("checkpoint", register)
+            .unwrap();
+        m
+    }
+
+    #[test]
+    fn checkpoint_rolls_back_on_failure() {
+        let m = manager(
+            r#"
+        stages:
+        - name: add
+          args:
+            x: 1
+        - name: checkpoint
+          args:
+            stages:
+            - name: add
+              args:
+                x: 5
+            - name: fail
+        "#,
+        );
+
+        let mut c = CalcContext { x: 0 };
+        m.run_stages(&mut c).unwrap_err();
+        assert_eq!(c.x, 1);
+    }
+
+    #[test]
+    fn nested_checkpoints_keep_their_own_savepoint() {
+        let m = manager(
+            r#"
+        stages:
+        - name: checkpoint
+          args:
+            stages:
+            - name: add
+              args:
+                x: 1
+            - name: checkpoint
+              args:
+                stages:
+                - name: add
+                  args:
+                    x: 10
+                - name: fail
+            - name: peek
+        - name: add
+          args:
+            x: 100
+        "#,
+        );
+
+        let mut c = CalcContext { x: 0 };
+        m.run_stages(&mut c).unwrap_err();
+        assert_eq!(c.x, 0);
+        SEEN.with(|seen| assert_eq!(*seen.borrow(), vec![1]));
+    }
+}
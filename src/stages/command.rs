@@ -0,0 +1,122 @@
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    io::Write,
+    marker::PhantomData,
+    process::{self, Stdio},
+    thread,
+};
+
+use crate::{Stage, StageError};
+
+// Runs an external program with the context written to its stdin, then
+// replaces the context with what the program prints to stdout. Both sides
+// are YAML, so a program reading and writing JSON works too.
+#[derive(Debug, Deserialize)]
+pub struct Command<C> {
+    program: String,
+    #[serde(default)]
+    args: Vec<String>,
+    #[serde(default)]
+    env: HashMap<String, String>,
+    #[serde(skip)]
+    _context: PhantomData<fn(&mut C)>,
+}
+
+impl<C> Stage for Command<C>
+where
+    C: Serialize + DeserializeOwned,
+{
+    type C = C;
+
+    fn run(&self, c: &mut Self::C) -> Result<(), StageError> {
+        let input = serde_yaml::to_vec(c).map_err(StageError::custom)?;
+        let mut child = process::Command::new(&self.program)
+            .args(&self.args)
+            .envs(&self.env)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()
+            .map_err(StageError::custom)?;
+
+        // Written from another thread so a program that fills its stdout
+        // before reading all of stdin can't deadlock.
+        let mut stdin = child.stdin.take().expect("stdin is piped");
+        let writer = thread::spawn(move || stdin.write_all(&input));
+        let output = child.wait_with_output().map_err(StageError::custom)?;
+        if let Ok(Err(e)) = writer.join() {
+            if e.kind() != std::io::ErrorKind::BrokenPipe {
+                return Err(StageError::custom(e));
+            }
+        }
+
+        if !output.status.success() {
+            return Err(StageError::CommandFailed {
+                code: output.status.code(),
+            });
+        }
+        *c = serde_yaml::from_slice(&output.stdout).map_err(StageError::custom)?;
+        Ok(())
+    }
+}
+
+#[cfg(all(test, unix))]
+mod test {
+    use serde_yaml::Value;
+
+    use super::*;
+    use crate::{
+        test::{Add, CalcContext},
+        StageFile, StageManager,
+    };
+
+    fn manager(yaml_str: &str) -> StageManager<CalcContext, Value> {
+        let file: StageFile<Value> = serde_yaml::from_str(yaml_str).unwrap();
+        let mut m = StageManager::from_file(file);
+        m.register_named::<Command<CalcContext>>("command")
+            .unwrap()
+            .register::<Add>()
+            .unwrap();
+        m
+    }
+
+    #[test]
+    fn cat_round_trips_the_context() {
+        let m = manager(
+            r#"
+            stages:
+            - name: add
+              args:
+                x: 4
+            - name: command
+              args:
+                program: cat
+            "#,
+        );
+
+        let mut c = CalcContext { x: 1 };
+        m.run_stages(&mut c).unwrap();
+        assert_eq!(c.x, 5);
+    }
+
+    #[test]
+    fn nonzero_exit_fails() {
+        let m = manager(
+            r#"
+            stages:
+            - name: command
+              args:
+                program: sh
+                args: ["-c", "exit $CODE"]
+                env:
+                  CODE: "3"
+            "#,
+        );
+
+        let mut c = CalcContext { x: 1 };
+        match m.run_stages(&mut c) {
+            Err(StageError::CommandFailed { code }) => assert_eq!(code, Some(3)),
+            other => panic!("unexpected result: {:?}", other),
+        }
+    }
+}
@@ -0,0 +1,102 @@
+use serde::Deserialize;
+use std::{collections::BTreeMap, marker::PhantomData};
+
+use crate::{Stage, StageError};
+
+// A context that can have fields set by name from raw args values.
+pub trait ApplyFields<V> {
+    fn set_field(&mut self, key: &str, val: &V) -> Result<(), StageError>;
+}
+
+// Sets each key of its args map as a field on the context, in key order.
+#[derive(Debug, Deserialize)]
+#[serde(transparent, bound(deserialize = "V: Deserialize<'de>"))]
+pub struct SetFields<C, V> {
+    fields: BTreeMap<String, V>,
+    #[serde(skip)]
+    _context: PhantomData<fn(&mut C)>,
+}
+
+impl<C, V> Stage for SetFields<C, V>
+where
+    C: ApplyFields<V>,
+{
+    type C = C;
+
+    fn run(&self, c: &mut Self::C) -> Result<(), StageError> {
+        for (key, val) in &self.fields {
+            c.set_field(key, val)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use serde_yaml::Value;
+
+    use super::*;
+    use crate::{
+        test::{Add, CalcContext},
+        StageFile, StageManager,
+    };
+
+    impl ApplyFields<Value> for CalcContext {
+        fn set_field(&mut self, key: &str, val: &Value) -> Result<(), StageError> {
+            match key {
+                "x" => {
+                    self.x = val
+                        .as_i64()
+                        .ok_or_else(|| StageError::custom("`x` must be an integer"))?;
+                    Ok(())
+                }
+                _ => Err(StageError::custom(format!("no field `{}`", key))),
+            }
+        }
+    }
+
+    fn manager(yaml_str: &str) -> StageManager<CalcContext, Value> {
+        let file: StageFile<Value> = serde_yaml::from_str(yaml_str).unwrap();
+        let mut m = StageManager::from_file(file);
+        m.register_named::<SetFields<CalcContext, Value>>("set_fields")
+            .unwrap()
+            .register::<Add>()
+            .unwrap();
+        m
+    }
+
+    #[test]
+    fn fields_are_set_from_args() {
+        let m = manager(
+            r#"
+            stages:
+            - name: set_fields
+              args:
+                x: 10
+            - name: add
+              args:
+                x: 2
+            "#,
+        );
+
+        let mut c = CalcContext { x: 1 };
+        m.run_stages(&mut c).unwrap();
+        assert_eq!(c.x, 12);
+    }
+
+    #[test]
+    fn unknown_field_fails() {
+        let m = manager(
+            r#"
+            stages:
+            - name: set_fields
+              args:
+                y: 10
+            "#,
+        );
+
+        let mut c = CalcContext { x: 1 };
+        let err = m.run_stages(&mut c).unwrap_err();
+        assert_eq!(err.to_string(), "stage failed: no field `y`");
+    }
+}
@@ -0,0 +1,78 @@
+use std::{fmt, marker::PhantomData};
+
+use crate::{Stage, StageError};
+
+// Wraps a closure as an infallible stage. Build one with `stage_fn`.
+pub struct FnStage<C, F> {
+    f: F,
+    _context: PhantomData<fn(&mut C)>,
+}
+
+pub fn stage_fn<C, F>(f: F) -> FnStage<C, F>
+where
+    F: Fn(&mut C),
+{
+    FnStage {
+        f,
+        _context: PhantomData,
+    }
+}
+
+impl<C, F> Stage for FnStage<C, F>
+where
+    F: Fn(&mut C),
+{
+    type C = C;
+
+    fn run(&self, c: &mut Self::C) -> Result<(), StageError> {
+        (self.f)(c);
+        Ok(())
+    }
+}
+
+impl<C, F> fmt::Debug for FnStage<C, F> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("FnStage")
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use serde::Deserialize;
+    use serde_yaml::Value;
+
+    use super::*;
+    use crate::{test::CalcContext, StageFile, StageManager};
+
+    #[test]
+    fn closure_stages() {
+        let yaml_str = r#"
+        stages:
+        - name: negate
+          args: ~
+        - name: offset
+          args:
+            by: 3
+        "#;
+
+        #[derive(Deserialize)]
+        struct Offset {
+            by: i64,
+        }
+
+        let file: StageFile<Value> = serde_yaml::from_str(yaml_str).unwrap();
+        let mut m = StageManager::from_file(file);
+        m.register_fn("negate", |_| Ok(stage_fn(|c: &mut CalcContext| c.x = -c.x)))
+            .unwrap()
+            .register_fn("offset", |v| {
+                let Offset { by } = Offset::deserialize(v)?;
+                Ok(stage_fn(move |c: &mut CalcContext| c.x += by))
+            })
+            .unwrap();
+
+        let mut c = CalcContext { x: 5 };
+        m.run_stages(&mut c).unwrap();
+
+        assert_eq!(c.x, -2);
+    }
+}
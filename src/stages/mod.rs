@@ -0,0 +1,27 @@
+mod assert;
+mod branch;
+mod checkpoint;
+#[cfg(feature = "yaml")]
+mod command;
+mod fields;
+mod func;
+mod parallel;
+mod project;
+mod retry;
+mod scatter;
+mod sleep;
+mod timeout;
+
+pub use assert::{Assert, Op, Queryable, Scalar};
+pub use branch::If;
+pub use checkpoint::Checkpoint;
+#[cfg(feature = "yaml")]
+pub use command::Command;
+pub use fields::{ApplyFields, SetFields};
+pub use func::{stage_fn, FnStage};
+pub use parallel::{ParallelGroup, Reducer};
+pub use project::{Lens, Projected};
+pub use retry::{Retry, RetryBudget};
+pub use scatter::{Merge, Scatter};
+pub use sleep::Sleep;
+pub use timeout::Timeout;
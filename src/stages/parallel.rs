@@ -0,0 +1,115 @@
+use serde::Deserialize;
+use std::thread;
+
+use crate::{Stage, StageError, StageSetupCtx};
+
+pub trait Reducer<C> {
+    // Folds one branch's finished context back into `base`. `original` is
+    // the context every branch started from.
+    fn reduce(&self, base: &mut C, original: &C, branch: C);
+}
+
+// Runs each branch on its own thread against a clone of the context, then
+// folds the results back in branch order with the reducer. Branches share a
+// single stage type since boxed stages are not Send.
+#[derive(Debug, Deserialize)]
+#[serde(bound(deserialize = "S: Deserialize<'de>, R: Default"))]
+pub struct ParallelGroup<S, R> {
+    branches: Vec<S>,
+    #[serde(skip)]
+    reducer: R,
+}
+
+impl<S, R> Stage for ParallelGroup<S, R>
+where
+    S: Stage + Sync,
+    S::C: Clone + Send,
+    R: Reducer<S::C>,
+{
+    type C = S::C;
+
+    fn setup_with(&mut self, ctx: StageSetupCtx<'_>) {
+        self.branches.iter_mut().for_each(|b| b.setup_with(ctx));
+    }
+
+    fn setup_with_context(&mut self, c: &Self::C) {
+        self.branches
+            .iter_mut()
+            .for_each(|b| b.setup_with_context(c));
+    }
+
+    fn run(&self, c: &mut Self::C) -> Result<(), StageError> {
+        let original = c.clone();
+        let results: Vec<Result<S::C, StageError>> = thread::scope(|scope| {
+            let handles: Vec<_> = self
+                .branches
+                .iter()
+                .map(|b| {
+                    let mut branch = original.clone();
+                    scope.spawn(move || b.run(&mut branch).map(|()| branch))
+                })
+                .collect();
+
+            handles
+                .into_iter()
+                .map(|h| h.join().unwrap_or_else(|p| std::panic::resume_unwind(p)))
+                .collect()
+        });
+
+        for branch in results {
+            self.reducer.reduce(c, &original, branch?);
+        }
+        Ok(())
+    }
+
+    fn teardown(&mut self) {
+        self.branches.iter_mut().rev().for_each(|b| b.teardown());
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use serde_yaml::Value;
+
+    use super::*;
+    use crate::{
+        test::{Add, CalcContext, Mul},
+        StageFile, StageManager,
+    };
+
+    #[derive(Default)]
+    struct SumDeltas;
+
+    impl Reducer<CalcContext> for SumDeltas {
+        fn reduce(&self, base: &mut CalcContext, original: &CalcContext, branch: CalcContext) {
+            base.x += branch.x - original.x;
+        }
+    }
+
+    #[test]
+    fn parallel_branches_are_reduced() {
+        let yaml_str = r#"
+        stages:
+        - name: parallel_add
+          args:
+            branches:
+            - x: 2
+            - x: 5
+        - name: mul
+          args:
+            x: 2
+        "#;
+
+        let file: StageFile<Value> = serde_yaml::from_str(yaml_str).unwrap();
+        let mut m = StageManager::from_file(file);
+        m.register_named::<ParallelGroup<Add, SumDeltas>>("parallel_add")
+            .unwrap()
+            .register::<Mul>()
+            .unwrap();
+
+        let mut c = CalcContext { x: 1 };
+        m.run(&mut c).unwrap();
+
+        assert_eq!(c.x, 16);
+    }
+}
@@ -0,0 +1,176 @@
+use serde::{Deserialize, Deserializer};
+use std::{any::Any, marker::PhantomData};
+
+use crate::{
+    RegisterError, Stage, StageError, StageFlow, StageManager, StageMeta, StageName, StageSetupCtx,
+};
+
+// Maps a whole context to the part of it a stage works on, so a stage written
+// against the smaller view can run in any context that contains one.
+pub trait Lens {
+    type Whole;
+    type View;
+
+    fn view(whole: &Self::Whole) -> &Self::View;
+    fn view_mut(whole: &mut Self::Whole) -> &mut Self::View;
+}
+
+// Runs the inner stage against the view `L` picks out of the context. It
+// deserializes from the inner stage's args and takes its name; register one
+// with StageManager::register_projected.
+#[derive(Debug, Deserialize)]
+#[serde(transparent, bound(deserialize = "S: Deserialize<'de>"))]
+pub struct Projected<S, L> {
+    inner: S,
+    #[serde(skip)]
+    _lens: PhantomData<fn() -> L>,
+}
+
+impl<S: StageName, L> StageName for Projected<S, L> {
+    fn stage_name() -> &'static str {
+        S::stage_name()
+    }
+
+    fn version() -> u32 {
+        S::version()
+    }
+}
+
+impl<S, L> Stage for Projected<S, L>
+where
+    S: Stage,
+    L: Lens<View = S::C>,
+{
+    type C = L::Whole;
+
+    fn run(&self, c: &mut Self::C) -> Result<(), StageError> {
+        self.inner.run(L::view_mut(c))
+    }
+
+    fn run_with_meta(
+        &self,
+        c: &mut Self::C,
+        shared: &dyn Any,
+        meta: &StageMeta<'_>,
+    ) -> Result<StageFlow, StageError> {
+        self.inner.run_with_meta(L::view_mut(c), shared, meta)
+    }
+
+    fn setup_with(&mut self, ctx: StageSetupCtx<'_>) {
+        self.inner.setup_with(ctx);
+    }
+
+    fn setup_with_context(&mut self, c: &Self::C) {
+        self.inner.setup_with_context(L::view(c));
+    }
+
+    fn result(&self) -> Option<Box<dyn Any>> {
+        self.inner.result()
+    }
+
+    fn is_mutating(&self) -> bool {
+        self.inner.is_mutating()
+    }
+
+    fn undo(&self, c: &mut Self::C) -> Result<(), StageError> {
+        self.inner.undo(L::view_mut(c))
+    }
+
+    fn teardown(&mut self) {
+        self.inner.teardown();
+    }
+
+    fn describe(&self) -> Option<String> {
+        self.inner.describe()
+    }
+}
+
+impl<'de, C, V> StageManager<C, V>
+where
+    V: Deserialize<'de> + Deserializer<'de> + Clone,
+{
+    // Registers `S` under its own name, running it on the view `L` takes of
+    // this manager's context.
+    pub fn register_projected<S, L>(&mut self) -> Result<&mut Self, RegisterError>
+    where
+        S: 'static + Stage + StageName + Deserialize<'de>,
+        L: 'static + Lens<Whole = C, View = S::C>,
+    {
+        self.register::<Projected<S, L>>()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use serde_yaml::Value;
+
+    use super::Lens;
+    use crate::{
+        test::{Add, CalcContext},
+        StageFile, StageManager,
+    };
+
+    #[derive(Debug, Default)]
+    struct Scored {
+        label: String,
+        calc: CalcContext,
+    }
+
+    #[derive(Debug, Default)]
+    struct Pair {
+        left: CalcContext,
+        right: CalcContext,
+    }
+
+    struct ScoredCalc;
+
+    impl Lens for ScoredCalc {
+        type Whole = Scored;
+        type View = CalcContext;
+
+        fn view(whole: &Scored) -> &CalcContext {
+            &whole.calc
+        }
+
+        fn view_mut(whole: &mut Scored) -> &mut CalcContext {
+            &mut whole.calc
+        }
+    }
+
+    struct RightCalc;
+
+    impl Lens for RightCalc {
+        type Whole = Pair;
+        type View = CalcContext;
+
+        fn view(whole: &Pair) -> &CalcContext {
+            &whole.right
+        }
+
+        fn view_mut(whole: &mut Pair) -> &mut CalcContext {
+            &mut whole.right
+        }
+    }
+
+    fn file() -> StageFile<Value> {
+        serde_yaml::from_str("[{name: add, args: {x: 2}}, {name: add, args: {x: 3}}]").unwrap()
+    }
+
+    #[test]
+    fn one_stage_many_contexts() {
+        let mut scored = StageManager::from_file(file());
+        scored.register_projected::<Add, ScoredCalc>().unwrap();
+        let mut c = Scored {
+            label: "a".to_string(),
+            ..Scored::default()
+        };
+        scored.run_stages(&mut c).unwrap();
+        assert_eq!((c.label.as_str(), c.calc.x), ("a", 5));
+
+        let mut pair = StageManager::from_file(file());
+        pair.register_projected::<Add, RightCalc>().unwrap();
+        let mut c = Pair::default();
+        pair.run_stages(&mut c).unwrap();
+        assert_eq!((c.left.x, c.right.x), (0, 5));
+    }
+}
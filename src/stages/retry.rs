@@ -0,0 +1,245 @@
+use serde::Deserialize;
+use std::{any::Any, cell::Cell, rc::Rc, thread, time::Duration};
+
+use super::sleep::sleep_unless_cancelled;
+use crate::{Stage, StageError, StageFlow, StageMeta, StageSetupCtx};
+
+// Runs the inner stage until it succeeds or `max_attempts` runs have failed,
+// sleeping `backoff_ms` between attempts. The last error is returned.
+#[derive(Debug, Deserialize)]
+pub struct Retry<S> {
+    max_attempts: u32,
+    #[serde(default)]
+    backoff_ms: u64,
+    inner: S,
+    #[serde(skip)]
+    budget: Option<RetryBudget>,
+}
+
+// Retries left for every Retry stage in a run. Clones share the count.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RetryBudget {
+    limit: u32,
+    remaining: Rc<Cell<u32>>,
+}
+
+impl RetryBudget {
+    pub fn new(limit: u32) -> Self {
+        Self {
+            limit,
+            remaining: Rc::new(Cell::new(limit)),
+        }
+    }
+
+    // A full budget with the same limit that shares nothing with this one.
+    pub fn fresh(&self) -> Self {
+        Self::new(self.limit)
+    }
+
+    pub fn remaining(&self) -> u32 {
+        self.remaining.get()
+    }
+
+    pub fn reset(&self) {
+        self.remaining.set(self.limit);
+    }
+
+    // Uses up one retry, or returns false if none are left.
+    pub fn take(&self) -> bool {
+        match self.remaining.get() {
+            0 => false,
+            n => {
+                self.remaining.set(n - 1);
+                true
+            }
+        }
+    }
+}
+
+impl<S> Retry<S> {
+    // Calls `run` until it succeeds or the attempts run out. Given the run's
+    // meta, a cancel cuts the backoff short and is never retried.
+    fn attempt<T>(
+        &self,
+        meta: Option<&StageMeta<'_>>,
+        mut run: impl FnMut() -> Result<T, StageError>,
+    ) -> Result<T, StageError> {
+        let mut attempt = 1;
+        loop {
+            match run() {
+                Ok(value) => return Ok(value),
+                Err(e @ StageError::Cancelled { .. }) => return Err(e),
+                Err(e) if attempt >= self.max_attempts => return Err(e),
+                Err(e) if !self.budget.as_ref().is_none_or(RetryBudget::take) => return Err(e),
+                Err(_) => {
+                    attempt += 1;
+                    let backoff = Duration::from_millis(self.backoff_ms);
+                    match meta {
+                        Some(meta) => sleep_unless_cancelled(backoff, meta)?,
+                        None => thread::sleep(backoff),
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl<S: Stage> Stage for Retry<S> {
+    type C = S::C;
+
+    fn setup_with(&mut self, ctx: StageSetupCtx<'_>) {
+        self.budget = ctx.retry_budget.cloned();
+        self.inner.setup_with(ctx);
+    }
+
+    fn setup_with_context(&mut self, c: &Self::C) {
+        self.inner.setup_with_context(c);
+    }
+
+    fn run(&self, c: &mut Self::C) -> Result<(), StageError> {
+        self.attempt(None, || self.inner.run(c))
+    }
+
+    fn run_with_meta(
+        &self,
+        c: &mut Self::C,
+        shared: &dyn Any,
+        meta: &StageMeta<'_>,
+    ) -> Result<StageFlow, StageError> {
+        self.attempt(Some(meta), || self.inner.run_with_meta(c, shared, meta))
+    }
+
+    fn undo(&self, c: &mut Self::C) -> Result<(), StageError> {
+        self.inner.undo(c)
+    }
+
+    fn teardown(&mut self) {
+        self.inner.teardown();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use serde_yaml::Value;
+    use std::cell::Cell;
+
+    use super::*;
+    use crate::{test::CalcContext, StageFile, StageManager};
+
+    #[derive(Debug, Deserialize)]
+    struct Flaky {
+        failures: u32,
+        x: i64,
+        #[serde(skip)]
+        attempts: Cell<u32>,
+    }
+
+    impl Stage for Flaky {
+        type C = CalcContext;
+
+        fn run(&self, c: &mut Self::C) -> Result<(), StageError> {
+            let attempt = self.attempts.get() + 1;
+            self.attempts.set(attempt);
+            if attempt <= self.failures {
+                return Err(StageError::custom(format!("attempt {} failed", attempt)));
+            }
+            c.x += self.x;
+            Ok(())
+        }
+    }
+
+    fn run(yaml_str: &str) -> (Result<(), StageError>, CalcContext) {
+        let file: StageFile<Value> = serde_yaml::from_str(yaml_str).unwrap();
+        let mut m = StageManager::from_file(file);
+        m.register_named::<Retry<Flaky>>("retry").unwrap();
+
+        let mut c = CalcContext { x: 1 };
+        (m.run_stages(&mut c), c)
+    }
+
+    #[test]
+    fn retries_until_success() {
+        let (result, c) = run(r#"
+        stages:
+        - name: retry
+          args:
+            max_attempts: 3
+            backoff_ms: 1
+            inner:
+              failures: 2
+              x: 5
+        "#);
+
+        result.unwrap();
+        assert_eq!(c.x, 6);
+    }
+
+    #[test]
+    fn exhausted_retries_return_last_error() {
+        let (result, c) = run(r#"
+        stages:
+        - name: retry
+          args:
+            max_attempts: 2
+            inner:
+              failures: 5
+              x: 5
+        "#);
+
+        assert_eq!(
+            result.unwrap_err().to_string(),
+            "stage failed: attempt 2 failed"
+        );
+        assert_eq!(c.x, 1);
+    }
+
+    #[test]
+    fn retry_budget_is_shared_across_stages() {
+        let file: StageFile<Value> = serde_yaml::from_str(
+            r#"
+        stages:
+        - name: retry
+          args: &flaky
+            max_attempts: 3
+            inner:
+              failures: 1
+              x: 1
+        - name: retry
+          args: *flaky
+        - name: retry
+          args: *flaky
+        "#,
+        )
+        .unwrap();
+        let mut m = StageManager::from_file(file);
+        m.register_named::<Retry<Flaky>>("retry")
+            .unwrap()
+            .with_retry_budget(2);
+
+        let mut c = CalcContext { x: 1 };
+        assert_eq!(
+            m.run_stages(&mut c).unwrap_err().to_string(),
+            "stage failed: attempt 1 failed"
+        );
+        assert_eq!(c.x, 3);
+    }
+
+    #[test]
+    fn single_attempt_runs_once() {
+        let (result, _) = run(r#"
+        stages:
+        - name: retry
+          args:
+            max_attempts: 1
+            backoff_ms: 10000
+            inner:
+              failures: 1
+              x: 5
+        "#);
+
+        assert_eq!(
+            result.unwrap_err().to_string(),
+            "stage failed: attempt 1 failed"
+        );
+    }
+}
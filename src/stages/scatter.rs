@@ -0,0 +1,100 @@
+use serde::Deserialize;
+
+use crate::{Stage, StageError, StageSetupCtx};
+
+pub trait Merge<C> {
+    // Combines every branch's finished context, in branch order, into `base`.
+    fn merge(&self, base: &mut C, results: Vec<C>);
+}
+
+// Runs each branch in turn against its own clone of the context, so no branch
+// sees another's changes, then hands all of them to the merger. Unlike
+// ParallelGroup nothing runs on another thread.
+#[derive(Debug, Deserialize)]
+#[serde(bound(deserialize = "S: Deserialize<'de>, M: Default"))]
+pub struct Scatter<S, M> {
+    branches: Vec<S>,
+    #[serde(skip)]
+    merger: M,
+}
+
+impl<S, M> Stage for Scatter<S, M>
+where
+    S: Stage,
+    S::C: Clone,
+    M: Merge<S::C>,
+{
+    type C = S::C;
+
+    fn setup_with(&mut self, ctx: StageSetupCtx<'_>) {
+        self.branches.iter_mut().for_each(|b| b.setup_with(ctx));
+    }
+
+    fn setup_with_context(&mut self, c: &Self::C) {
+        self.branches
+            .iter_mut()
+            .for_each(|b| b.setup_with_context(c));
+    }
+
+    fn run(&self, c: &mut Self::C) -> Result<(), StageError> {
+        let mut results = Vec::with_capacity(self.branches.len());
+        for b in &self.branches {
+            let mut branch = c.clone();
+            b.run(&mut branch)?;
+            results.push(branch);
+        }
+        self.merger.merge(c, results);
+        Ok(())
+    }
+
+    fn teardown(&mut self) {
+        self.branches.iter_mut().rev().for_each(|b| b.teardown());
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use serde_yaml::Value;
+
+    use super::*;
+    use crate::{
+        test::{Add, CalcContext, Mul},
+        StageFile, StageManager,
+    };
+
+    #[derive(Default)]
+    struct Sum;
+
+    impl Merge<CalcContext> for Sum {
+        fn merge(&self, base: &mut CalcContext, results: Vec<CalcContext>) {
+            base.x = results.iter().map(|c| c.x).sum();
+        }
+    }
+
+    #[test]
+    fn branches_see_the_original_context() {
+        let yaml_str = r#"
+        stages:
+        - name: scatter_add
+          args:
+            branches:
+            - x: 2
+            - x: 5
+        - name: mul
+          args:
+            x: 2
+        "#;
+
+        let file: StageFile<Value> = serde_yaml::from_str(yaml_str).unwrap();
+        let mut m = StageManager::from_file(file);
+        m.register_named::<Scatter<Add, Sum>>("scatter_add")
+            .unwrap()
+            .register::<Mul>()
+            .unwrap();
+
+        let mut c = CalcContext { x: 1 };
+        m.run(&mut c).unwrap();
+
+        assert_eq!(c.x, 18);
+    }
+}
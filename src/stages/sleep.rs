@@ -0,0 +1,314 @@
+use serde::Deserialize;
+use std::{
+    any::Any,
+    marker::PhantomData,
+    thread,
+    time::{Duration, Instant},
+};
+
+use crate::{Stage, StageError, StageFlow, StageMeta, StageName};
+
+// How often a cancellable run checks its flag while sleeping.
+pub(crate) const CANCEL_POLL: Duration = Duration::from_millis(5);
+
+// Sleeps for `duration`, or until the run `meta` belongs to is cancelled.
+pub(crate) fn sleep_unless_cancelled(
+    duration: Duration,
+    meta: &StageMeta<'_>,
+) -> Result<(), StageError> {
+    if meta.cancel.is_none() {
+        thread::sleep(duration);
+        return Ok(());
+    }
+
+    let deadline = Instant::now() + duration;
+    loop {
+        if meta.is_cancelled() {
+            return Err(StageError::Cancelled {
+                next_index: meta.index,
+            });
+        }
+        let left = deadline.saturating_duration_since(Instant::now());
+        if left == Duration::ZERO {
+            return Ok(());
+        }
+        thread::sleep(left.min(CANCEL_POLL));
+    }
+}
+
+// Blocks for `ms` milliseconds and leaves the context alone, for pacing
+// calls to other systems. Under run_stages_cancellable the sleep ends early
+// with StageError::Cancelled once the flag is set.
+#[derive(Debug, Deserialize)]
+pub struct Sleep<C> {
+    ms: u64,
+    #[serde(skip)]
+    _context: PhantomData<fn(&mut C)>,
+}
+
+impl<C> StageName for Sleep<C> {
+    fn stage_name() -> &'static str {
+        "sleep"
+    }
+}
+
+impl<C> Stage for Sleep<C> {
+    type C = C;
+
+    fn run(&self, _c: &mut Self::C) -> Result<(), StageError> {
+        thread::sleep(Duration::from_millis(self.ms));
+        Ok(())
+    }
+
+    fn run_with_meta(
+        &self,
+        _c: &mut Self::C,
+        _shared: &dyn Any,
+        meta: &StageMeta<'_>,
+    ) -> Result<StageFlow, StageError> {
+        sleep_unless_cancelled(Duration::from_millis(self.ms), meta)?;
+        Ok(StageFlow::Continue)
+    }
+
+    fn is_mutating(&self) -> bool {
+        false
+    }
+}
+
+#[cfg(feature = "async")]
+mod delay {
+    use std::{
+        future::Future,
+        pin::Pin,
+        sync::{Arc, Mutex},
+        task::{Context, Poll, Waker},
+        thread,
+        time::{Duration, Instant},
+    };
+
+    use super::{Sleep, CANCEL_POLL};
+    use crate::{
+        async_stage::{AsyncStage, BoxFuture},
+        StageError, StageMeta,
+    };
+
+    // Resolves once `deadline` passes, woken by a thread that sleeps until
+    // then so no runtime timer is needed. Given a cancellable run's meta,
+    // the thread wakes it every CANCEL_POLL to check the flag instead, and
+    // exits early once the Delay is dropped.
+    struct Delay<'a> {
+        deadline: Instant,
+        meta: Option<StageMeta<'a>>,
+        waker: Option<Arc<Mutex<Waker>>>,
+    }
+
+    impl<'a> Delay<'a> {
+        fn new(ms: u64, meta: Option<StageMeta<'a>>) -> Self {
+            Self {
+                deadline: Instant::now() + Duration::from_millis(ms),
+                meta: meta.filter(|m| m.cancel.is_some()),
+                waker: None,
+            }
+        }
+    }
+
+    impl Future for Delay<'_> {
+        type Output = Result<(), StageError>;
+
+        fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+            if let Some(meta) = self.meta.filter(|m| m.is_cancelled()) {
+                return Poll::Ready(Err(StageError::Cancelled {
+                    next_index: meta.index,
+                }));
+            }
+            let deadline = self.deadline;
+            let left = deadline.saturating_duration_since(Instant::now());
+            if left == Duration::ZERO {
+                return Poll::Ready(Ok(()));
+            }
+            match &self.waker {
+                Some(waker) => *waker.lock().unwrap() = cx.waker().clone(),
+                None => {
+                    let step = if self.meta.is_some() {
+                        CANCEL_POLL
+                    } else {
+                        left
+                    };
+                    let waker = Arc::new(Mutex::new(cx.waker().clone()));
+                    let wake = Arc::clone(&waker);
+                    thread::spawn(move || loop {
+                        let left = deadline.saturating_duration_since(Instant::now());
+                        thread::sleep(left.min(step));
+                        wake.lock().unwrap().wake_by_ref();
+                        if left <= step || Arc::strong_count(&wake) == 1 {
+                            break;
+                        }
+                    });
+                    self.waker = Some(waker);
+                }
+            }
+            Poll::Pending
+        }
+    }
+
+    impl<C> AsyncStage for Sleep<C> {
+        type C = C;
+
+        fn run<'a>(&'a self, _c: &'a mut Self::C) -> BoxFuture<'a, Result<(), StageError>> {
+            Box::pin(Delay::new(self.ms, None))
+        }
+
+        fn run_with_meta<'a>(
+            &'a self,
+            _c: &'a mut Self::C,
+            meta: StageMeta<'a>,
+        ) -> BoxFuture<'a, Result<(), StageError>> {
+            Box::pin(Delay::new(self.ms, Some(meta)))
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use serde_yaml::Value;
+    use std::{
+        sync::atomic::{AtomicBool, Ordering},
+        thread,
+        time::{Duration, Instant},
+    };
+
+    use super::Sleep;
+    use crate::{
+        stages::{Retry, Timeout},
+        test::{Add, CalcContext},
+        StageError, StageFile, StageManager,
+    };
+
+    fn manager(yaml_str: &str) -> StageManager<CalcContext, Value> {
+        let file: StageFile<Value> = serde_yaml::from_str(yaml_str).unwrap();
+        let mut m = StageManager::from_file(file);
+        m.register::<Sleep<CalcContext>>()
+            .unwrap()
+            .register::<Add>()
+            .unwrap()
+            .register_named::<Retry<Sleep<CalcContext>>>("retry")
+            .unwrap()
+            .register_named::<Timeout<Sleep<CalcContext>>>("timeout")
+            .unwrap();
+        m
+    }
+
+    fn run_cancelled_after(
+        m: &StageManager<CalcContext, Value>,
+        after: Duration,
+    ) -> Result<(), StageError> {
+        let cancel = AtomicBool::new(false);
+        let mut c = CalcContext { x: 1 };
+        thread::scope(|s| {
+            s.spawn(|| {
+                thread::sleep(after);
+                cancel.store(true, Ordering::SeqCst);
+            });
+            m.run_stages_cancellable(&mut c, &cancel)
+        })
+    }
+
+    #[test]
+    fn sleep_delays_the_pipeline() {
+        let m = manager("[{name: sleep, args: {ms: 10}}, {name: add, args: {x: 2}}]");
+
+        let mut c = CalcContext { x: 1 };
+        let started = Instant::now();
+        m.run_stages(&mut c).unwrap();
+
+        assert!(started.elapsed() >= Duration::from_millis(10));
+        assert_eq!(c.x, 3);
+    }
+
+    #[test]
+    fn cancel_interrupts_sleep() {
+        let m = manager("[{name: sleep, args: {ms: 60000}}, {name: add, args: {x: 2}}]");
+        let cancel = AtomicBool::new(false);
+
+        let mut c = CalcContext { x: 1 };
+        let started = Instant::now();
+        let result = thread::scope(|s| {
+            s.spawn(|| {
+                thread::sleep(Duration::from_millis(10));
+                cancel.store(true, Ordering::SeqCst);
+            });
+            m.run_stages_cancellable(&mut c, &cancel)
+        });
+
+        assert!(matches!(
+            result,
+            Err(StageError::Cancelled { next_index: 0 })
+        ));
+        assert!(started.elapsed() < Duration::from_secs(10));
+        assert_eq!(c.x, 1);
+    }
+
+    #[test]
+    fn cancel_reaches_sleep_through_wrappers() {
+        let wrapped = [
+            "[{name: retry, args: {max_attempts: 3, inner: {ms: 60000}}}]",
+            "[{name: timeout, args: {duration_ms: 60000, inner: {ms: 60000}}}]",
+        ];
+        for yaml_str in wrapped {
+            let m = manager(yaml_str);
+            let started = Instant::now();
+            let result = run_cancelled_after(&m, Duration::from_millis(10));
+            assert!(matches!(
+                result,
+                Err(StageError::Cancelled { next_index: 0 })
+            ));
+            assert!(started.elapsed() < Duration::from_secs(10));
+        }
+    }
+
+    #[cfg(feature = "async")]
+    #[test]
+    fn async_sleep_waits() {
+        use crate::async_stage::{test::block_on, AsyncStageManager};
+
+        let file: StageFile<Value> =
+            serde_yaml::from_str("[{name: sleep, args: {ms: 10}}]").unwrap();
+        let mut m = AsyncStageManager::from_file(file);
+        m.register::<Sleep<CalcContext>>().unwrap();
+
+        let mut c = CalcContext { x: 1 };
+        let started = Instant::now();
+        block_on(m.run_stages(&mut c)).unwrap();
+        assert!(started.elapsed() >= Duration::from_millis(10));
+    }
+
+    #[cfg(feature = "async")]
+    #[test]
+    fn cancel_interrupts_async_sleep() {
+        use crate::async_stage::{test::block_on, AsyncStageManager};
+
+        let yaml_str = "[{name: sleep, args: {ms: 60000}}, {name: add, args: {x: 2}}]";
+        let file: StageFile<Value> = serde_yaml::from_str(yaml_str).unwrap();
+        let mut m = AsyncStageManager::from_file(file);
+        m.register::<Sleep<CalcContext>>().unwrap();
+        m.register::<Add>().unwrap();
+        let cancel = AtomicBool::new(false);
+
+        let mut c = CalcContext { x: 1 };
+        let started = Instant::now();
+        let result = thread::scope(|s| {
+            s.spawn(|| {
+                thread::sleep(Duration::from_millis(10));
+                cancel.store(true, Ordering::SeqCst);
+            });
+            block_on(m.run_stages_cancellable(&mut c, &cancel))
+        });
+
+        assert!(matches!(
+            result,
+            Err(StageError::Cancelled { next_index: 0 })
+        ));
+        assert!(started.elapsed() < Duration::from_secs(10));
+        assert_eq!(c.x, 1);
+    }
+}
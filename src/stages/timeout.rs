@@ -0,0 +1,258 @@
+use serde::{Deserialize, Deserializer};
+use std::{
+    any::Any,
+    sync::{
+        mpsc::{self, RecvTimeoutError},
+        Arc,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+use super::sleep::CANCEL_POLL;
+use crate::{panic_message, Stage, StageError, StageFlow, StageMeta, StageName, StageSetupCtx};
+
+// Runs the inner stage on a worker thread against a clone of the context and
+// gives up after `duration_ms`. The clone is written back only if the inner
+// stage finishes in time; a timed out worker is left to finish on its own and
+// its result is discarded.
+#[derive(Debug, Deserialize)]
+#[serde(bound(deserialize = "S: Deserialize<'de>"))]
+pub struct Timeout<S> {
+    duration_ms: u64,
+    #[serde(deserialize_with = "deserialize_arc")]
+    inner: Arc<S>,
+}
+
+fn deserialize_arc<'de, D, S>(d: D) -> Result<Arc<S>, D::Error>
+where
+    D: Deserializer<'de>,
+    S: Deserialize<'de>,
+{
+    S::deserialize(d).map(Arc::new)
+}
+
+impl<S> Timeout<S>
+where
+    S: Stage + StageName + Send + Sync + 'static,
+    S::C: Clone + Send + 'static,
+{
+    // Given the run's meta, the inner stage is told its position and the
+    // wait ends early with StageError::Cancelled once the run is cancelled.
+    fn run_worker(
+        &self,
+        c: &mut S::C,
+        meta: Option<&StageMeta<'_>>,
+    ) -> Result<StageFlow, StageError> {
+        let (tx, rx) = mpsc::channel();
+        let inner = Arc::clone(&self.inner);
+        let mut branch = c.clone();
+        let position = meta.map(|m| (m.index, m.total, m.name.to_string()));
+        let worker = thread::spawn(move || {
+            let result = match &position {
+                Some((index, total, name)) => {
+                    let meta = StageMeta {
+                        index: *index,
+                        total: *total,
+                        name,
+                        cancel: None,
+                    };
+                    inner.run_with_meta(&mut branch, &(), &meta)
+                }
+                None => inner.run(&mut branch).map(|()| StageFlow::Continue),
+            };
+            let _ = tx.send(result.map(|flow| (flow, branch)));
+        });
+
+        let deadline = Instant::now() + Duration::from_millis(self.duration_ms);
+        let cancellable = meta.is_some_and(|m| m.cancel.is_some());
+        loop {
+            if let Some(meta) = meta.filter(|m| m.is_cancelled()) {
+                return Err(StageError::Cancelled {
+                    next_index: meta.index,
+                });
+            }
+            let left = deadline.saturating_duration_since(Instant::now());
+            let wait = if cancellable {
+                left.min(CANCEL_POLL)
+            } else {
+                left
+            };
+            match rx.recv_timeout(wait) {
+                Ok(result) => {
+                    let (flow, branch) = result?;
+                    *c = branch;
+                    return Ok(flow);
+                }
+                Err(RecvTimeoutError::Timeout) if wait < left => {}
+                Err(RecvTimeoutError::Timeout) => {
+                    return Err(StageError::Timeout {
+                        name: S::stage_name().to_string(),
+                    })
+                }
+                // The worker dropped its sender while unwinding, so it has
+                // finished and joining only collects the payload.
+                Err(RecvTimeoutError::Disconnected) => {
+                    return Err(StageError::Panicked {
+                        name: S::stage_name().to_string(),
+                        payload: worker.join().err().map(panic_message).unwrap_or_default(),
+                    })
+                }
+            }
+        }
+    }
+}
+
+impl<S> Stage for Timeout<S>
+where
+    S: Stage + StageName + Send + Sync + 'static,
+    S::C: Clone + Send + 'static,
+{
+    type C = S::C;
+
+    fn setup_with(&mut self, ctx: StageSetupCtx<'_>) {
+        if let Some(inner) = Arc::get_mut(&mut self.inner) {
+            inner.setup_with(ctx);
+        }
+    }
+
+    fn setup_with_context(&mut self, c: &Self::C) {
+        if let Some(inner) = Arc::get_mut(&mut self.inner) {
+            inner.setup_with_context(c);
+        }
+    }
+
+    fn run(&self, c: &mut Self::C) -> Result<(), StageError> {
+        self.run_worker(c, None).map(|_| ())
+    }
+
+    // The worker can't borrow the shared value, so the inner stage sees `&()`.
+    fn run_with_meta(
+        &self,
+        c: &mut Self::C,
+        _shared: &dyn Any,
+        meta: &StageMeta<'_>,
+    ) -> Result<StageFlow, StageError> {
+        self.run_worker(c, Some(meta))
+    }
+
+    // Skipped if a timed out worker still holds the inner stage.
+    fn teardown(&mut self) {
+        if let Some(inner) = Arc::get_mut(&mut self.inner) {
+            inner.teardown();
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use serde_yaml::Value;
+
+    use super::*;
+    use crate::{test::CalcContext, StageFile, StageManager};
+
+    #[derive(Debug, Deserialize)]
+    struct Slow {
+        ms: u64,
+        x: i64,
+    }
+
+    impl StageName for Slow {
+        fn stage_name() -> &'static str {
+            "slow"
+        }
+    }
+
+    impl Stage for Slow {
+        type C = CalcContext;
+
+        fn run(&self, c: &mut Self::C) -> Result<(), StageError> {
+            thread::sleep(Duration::from_millis(self.ms));
+            c.x += self.x;
+            Ok(())
+        }
+    }
+
+    fn manager(yaml_str: &str) -> StageManager<CalcContext, Value> {
+        let file: StageFile<Value> = serde_yaml::from_str(yaml_str).unwrap();
+        let mut m = StageManager::from_file(file);
+        m.register_named::<Timeout<Slow>>("timeout").unwrap();
+        m
+    }
+
+    #[test]
+    fn slow_stage_times_out() {
+        let m = manager(
+            r#"
+        stages:
+        - name: timeout
+          args:
+            duration_ms: 10
+            inner:
+              ms: 500
+              x: 1
+        "#,
+        );
+
+        let mut c = CalcContext { x: 1 };
+        match m.run_stages(&mut c) {
+            Err(StageError::Timeout { name }) => assert_eq!(name, "slow"),
+            r => panic!("unexpected result: {:?}", r),
+        }
+        assert_eq!(c.x, 1);
+    }
+
+    #[test]
+    fn stage_within_limit_mutates_context() {
+        let m = manager(
+            r#"
+        stages:
+        - name: timeout
+          args:
+            duration_ms: 2000
+            inner:
+              ms: 1
+              x: 4
+        "#,
+        );
+
+        let mut c = CalcContext { x: 1 };
+        m.run_stages(&mut c).unwrap();
+        assert_eq!(c.x, 5);
+    }
+
+    #[derive(Debug, Deserialize)]
+    struct Explode {}
+
+    impl StageName for Explode {
+        fn stage_name() -> &'static str {
+            "explode"
+        }
+    }
+
+    impl Stage for Explode {
+        type C = CalcContext;
+
+        fn run(&self, _c: &mut Self::C) -> Result<(), StageError> {
+            panic!("kaboom")
+        }
+    }
+
+    #[test]
+    fn worker_panic_is_reported_as_panicked() {
+        let file: StageFile<Value> =
+            serde_yaml::from_str("[{name: timeout, args: {duration_ms: 2000, inner: {}}}]")
+                .unwrap();
+        let mut m = StageManager::from_file(file);
+        m.register_named::<Timeout<Explode>>("timeout").unwrap();
+
+        let mut c = CalcContext { x: 1 };
+        match m.run_stages(&mut c) {
+            Err(StageError::Panicked { name, payload }) => {
+                assert_eq!(name, "explode");
+                assert_eq!(payload, "kaboom");
+            }
+            r => panic!("unexpected result: {:?}", r),
+        }
+    }
+}
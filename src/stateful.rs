@@ -0,0 +1,115 @@
+use serde::{Deserialize, Deserializer};
+use std::cell::RefCell;
+
+use crate::{RegisterError, Stage, StageError, StageManager, StageName, StageSetupCtx};
+
+// A stage that changes itself as it runs, such as an accumulator. Register
+// one with StageManager::register_stateful. Its state lasts as long as the
+// built stage, which is one run unless the instance cache keeps it.
+pub trait StatefulStage {
+    type C;
+
+    fn run_mut(&mut self, c: &mut Self::C) -> Result<(), StageError>;
+
+    fn setup(&mut self) {}
+
+    fn teardown(&mut self) {}
+}
+
+// Adapts a StatefulStage to Stage by borrowing it mutably for each run.
+#[derive(Debug, Deserialize)]
+#[serde(transparent)]
+pub struct Stateful<S>(RefCell<S>);
+
+impl<S: StageName> StageName for Stateful<S> {
+    fn stage_name() -> &'static str {
+        S::stage_name()
+    }
+
+    fn version() -> u32 {
+        S::version()
+    }
+}
+
+impl<S: StatefulStage> Stage for Stateful<S> {
+    type C = S::C;
+
+    fn run(&self, c: &mut Self::C) -> Result<(), StageError> {
+        let mut stage = self
+            .0
+            .try_borrow_mut()
+            .map_err(|_| StageError::custom("stateful stage is already running"))?;
+        stage.run_mut(c)
+    }
+
+    fn setup_with(&mut self, _ctx: StageSetupCtx<'_>) {
+        self.0.get_mut().setup();
+    }
+
+    fn teardown(&mut self) {
+        self.0.get_mut().teardown();
+    }
+}
+
+impl<'de, C, V> StageManager<C, V>
+where
+    V: Deserialize<'de> + Deserializer<'de> + Clone,
+{
+    pub fn register_stateful<S>(&mut self) -> Result<&mut Self, RegisterError>
+    where
+        S: 'static + StatefulStage<C = C> + StageName + Deserialize<'de>,
+    {
+        self.register::<Stateful<S>>()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use serde::Deserialize;
+    use serde_yaml::Value;
+
+    use super::StatefulStage;
+    use crate::{test::CalcContext, StageError, StageFile, StageManager, StageName};
+
+    #[derive(Debug, Deserialize)]
+    struct Counter {
+        step: i64,
+        #[serde(skip)]
+        count: i64,
+    }
+
+    impl StageName for Counter {
+        fn stage_name() -> &'static str {
+            "counter"
+        }
+    }
+
+    impl StatefulStage for Counter {
+        type C = CalcContext;
+
+        fn run_mut(&mut self, c: &mut Self::C) -> Result<(), StageError> {
+            self.count += self.step;
+            c.x += self.count;
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn stateful_stage_keeps_a_running_total() {
+        let yaml_str = r#"
+        stages:
+        - name: counter
+          repeat: 3
+          args:
+            step: 1
+        "#;
+
+        let file: StageFile<Value> = serde_yaml::from_str(yaml_str).unwrap();
+        let mut m = StageManager::from_file(file);
+        m.register_stateful::<Counter>().unwrap();
+
+        let mut c = CalcContext { x: 0 };
+        m.run_stages(&mut c).unwrap();
+        assert_eq!(c.x, 1 + 2 + 3);
+    }
+}
@@ -0,0 +1,240 @@
+use serde::{Deserialize, Deserializer};
+use std::vec;
+
+use crate::{
+    chaos::Chaos, ActiveStage, ErrorPolicy, RunEnv, StageArgs, StageError, StageFlow, StageManager,
+};
+
+// The stage StageIter just ran.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StageStep {
+    pub name: String,
+    pub index: usize,
+}
+
+// Runs one stage of a pipeline per call to next, the same way run_stages
+// runs each one. The stages run so far are torn down when the iterator is
+// dropped. See StageManager::run_iter.
+pub struct StageIter<'a, C, V> {
+    manager: &'a StageManager<C, V>,
+    context: &'a mut C,
+    order: vec::IntoIter<(usize, &'a StageArgs<V>)>,
+    active: Vec<ActiveStage<C>>,
+    skip: usize,
+    chaos: Option<Chaos>,
+}
+
+impl<'de, C, V> StageManager<C, V>
+where
+    V: Deserialize<'de> + Deserializer<'de> + Clone,
+{
+    // Steps through the pipeline one stage at a time, leaving `context` as
+    // the stages so far have left it. Iteration ends early on StageFlow::Stop
+    // or, under ErrorPolicy::Abort, a stage error.
+    pub fn run_iter<'a>(&'a self, context: &'a mut C) -> Result<StageIter<'a, C, V>, StageError> {
+        self.validate()
+            .map_err(|names| StageError::UnknownStage { names })?;
+        self.file.check_version()?;
+        self.file.check_exclusive()?;
+        if let Some(budget) = &self.retry_budget {
+            budget.reset();
+        }
+
+        Ok(StageIter {
+            manager: self,
+            context,
+            order: self.file.ordered_stages()?.into_iter(),
+            active: Vec::new(),
+            skip: 0,
+            chaos: self.chaos_seed.map(Chaos::new),
+        })
+    }
+}
+
+impl<C, V> StageIter<'_, C, V> {
+    pub fn context(&self) -> &C {
+        self.context
+    }
+
+    fn fail(&mut self, e: StageError) -> Option<Result<StageStep, StageError>> {
+        if self.manager.error_policy == ErrorPolicy::Abort {
+            self.order = Vec::new().into_iter();
+        }
+        Some(Err(e))
+    }
+}
+
+impl<'de, C, V> Iterator for StageIter<'_, C, V>
+where
+    V: Deserialize<'de> + Deserializer<'de> + Clone,
+{
+    type Item = Result<StageStep, StageError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let manager = self.manager;
+        let _hooks = manager.hooks.enter();
+        let (index, s) = loop {
+            let (index, s) = self.order.next()?;
+            let runnable = s.enabled
+                && s.repeat > 0
+                && self.chaos.as_mut().is_none_or(|c| c.keep(s.probability));
+            if runnable && self.skip == 0 {
+                break (index, s);
+            }
+            if runnable {
+                self.skip -= 1;
+            }
+            if let Some(observer) = &manager.observer {
+                observer.on_stage_skipped(&s.name, index);
+            }
+        };
+
+        let entry = match manager.start_stage(index, s, self.context) {
+            Ok(entry) => entry,
+            Err(e) => return self.fail(e),
+        };
+        self.active.push(entry);
+
+        let stage = self.active[self.active.len() - 1].stage.as_ref();
+        let result = manager.run_started(index, s, stage, self.context, RunEnv::new(&()), &mut ());
+        match result {
+            Ok(StageFlow::Continue) => {}
+            Ok(StageFlow::Stop) => self.order = Vec::new().into_iter(),
+            Ok(StageFlow::Skip(n)) => self.skip = n,
+            Err(e) => return self.fail(e),
+        }
+        Some(Ok(StageStep {
+            name: s.name.clone(),
+            index,
+        }))
+    }
+}
+
+impl<C, V> Drop for StageIter<'_, C, V> {
+    fn drop(&mut self) {
+        self.active
+            .iter_mut()
+            .rev()
+            .for_each(|s| s.stage.teardown());
+        self.manager
+            .return_to_cache(std::mem::take(&mut self.active));
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use serde_yaml::Value;
+    use std::{cell::RefCell, rc::Rc};
+
+    use super::*;
+    use crate::{
+        test::{Add, CalcContext, Mul},
+        Observer, Stage, StageFile,
+    };
+
+    #[test]
+    fn steps_through_each_stage() {
+        let yaml_str = r#"
+        stages:
+        - name: add
+          args:
+            x: 2
+        - name: mul
+          args:
+            x: 3
+        - name: add
+          args:
+            x: 1
+          enabled: false
+        - name: add
+          args:
+            x: 4
+        "#;
+
+        let file: StageFile<Value> = serde_yaml::from_str(yaml_str).unwrap();
+        let mut m = StageManager::from_file(file);
+        m.register::<Add>().unwrap().register::<Mul>().unwrap();
+
+        let mut c = CalcContext { x: 1 };
+        let mut steps = m.run_iter(&mut c).unwrap();
+        let mut seen = Vec::new();
+        while let Some(step) = steps.next() {
+            let step = step.unwrap();
+            seen.push((step.name, step.index, steps.context().x));
+        }
+        drop(steps);
+
+        assert_eq!(
+            seen,
+            vec![
+                ("add".to_string(), 0, 3),
+                ("mul".to_string(), 1, 9),
+                ("add".to_string(), 3, 13),
+            ]
+        );
+        assert_eq!(c.x, 13);
+    }
+
+    #[derive(Deserialize)]
+    struct Explode {}
+
+    impl Stage for Explode {
+        type C = CalcContext;
+
+        fn run(&self, _c: &mut Self::C) -> Result<(), StageError> {
+            panic!("kaboom")
+        }
+    }
+
+    struct Log(Rc<RefCell<Vec<String>>>);
+
+    impl Observer for Log {
+        fn on_stage_start(&self, name: &str, index: usize) {
+            self.0
+                .borrow_mut()
+                .push(format!("start {} {}", name, index));
+        }
+
+        fn on_stage_skipped(&self, name: &str, index: usize) {
+            self.0.borrow_mut().push(format!("skip {} {}", name, index));
+        }
+    }
+
+    #[test]
+    fn steps_run_like_run_stages() {
+        let yaml_str = r#"
+        stages:
+        - name: explode
+          args: {}
+        - name: add
+          args:
+            x: 1
+          enabled: false
+        - name: add
+          args:
+            x: 2
+        "#;
+
+        let file: StageFile<Value> = serde_yaml::from_str(yaml_str).unwrap();
+        let mut m = StageManager::from_file(file);
+        let log = Rc::new(RefCell::new(Vec::new()));
+        m.register::<Add>()
+            .unwrap()
+            .register_named::<Explode>("explode")
+            .unwrap();
+        m.catch_panics(true)
+            .with_error_policy(ErrorPolicy::ContinueCollect)
+            .with_observer(Box::new(Log(log.clone())));
+
+        let mut c = CalcContext { x: 1 };
+        let steps: Vec<_> = m.run_iter(&mut c).unwrap().collect();
+
+        assert!(matches!(steps[0], Err(StageError::Panicked { .. })));
+        assert_eq!(steps[1].as_ref().unwrap().index, 2);
+        assert_eq!(c.x, 3);
+        assert_eq!(
+            *log.borrow(),
+            ["start explode 0", "skip add 1", "start add 2"]
+        );
+    }
+}
@@ -0,0 +1,440 @@
+use serde::{
+    de::{
+        self, value::MapDeserializer, DeserializeOwned, DeserializeSeed, IgnoredAny, MapAccess,
+        SeqAccess, Visitor,
+    },
+    Deserializer,
+};
+use std::{collections::BTreeMap, fmt, marker::PhantomData};
+
+use crate::{
+    box_stage, registry_key, resolve_name_as, BoxedStage, CompiledPipeline, CompiledStage,
+    NameCase, Stage, StageError, StageName, StageSetupCtx, MAX_SUPPORTED_VERSION,
+};
+
+// A fixed set of stage types, written as a tuple such as `(Add, Mul)`, that
+// CompiledPipeline::from_deserializer picks between by registered name.
+pub trait StageSet<C> {
+    // The registry key of every stage in the set.
+    fn keys() -> Vec<String>;
+
+    // Deserializes the args of the stage whose registry key is `name`, or
+    // returns None if no stage in the set has that key.
+    fn deserialize_named<'de, D>(name: &str, d: D) -> Result<Option<BoxedStage<C>>, D::Error>
+    where
+        D: Deserializer<'de>;
+}
+
+macro_rules! stage_set {
+    ($($s:ident),*) => {
+        impl<C, $($s),*> StageSet<C> for ($($s,)*)
+        where
+            $($s: 'static + Stage<C = C> + StageName + DeserializeOwned,)*
+        {
+            fn keys() -> Vec<String> {
+                vec![$(registry_key::<$s>()),*]
+            }
+
+            #[allow(unused_variables)]
+            fn deserialize_named<'de, D>(name: &str, d: D) -> Result<Option<BoxedStage<C>>, D::Error>
+            where
+                D: Deserializer<'de>,
+            {
+                $(
+                    if registry_key::<$s>() == name {
+                        return $s::deserialize(d).map(|s| Some(box_stage(s)));
+                    }
+                )*
+                Ok(None)
+            }
+        }
+    };
+}
+
+stage_set!();
+stage_set!(S1);
+stage_set!(S1, S2);
+stage_set!(S1, S2, S3);
+stage_set!(S1, S2, S3, S4);
+stage_set!(S1, S2, S3, S4, S5);
+stage_set!(S1, S2, S3, S4, S5, S6);
+stage_set!(S1, S2, S3, S4, S5, S6, S7);
+stage_set!(S1, S2, S3, S4, S5, S6, S7, S8);
+
+impl<C> CompiledPipeline<C> {
+    // Builds and sets up a pipeline straight from a stage file in any format,
+    // deserializing each stage's args as its own type instead of going through
+    // an intermediate value. Each entry's `name` must come before its `args`,
+    // since there is no intermediate value to hold args until the type is
+    // known. For the same reason an entry's args are only skipped unread if
+    // `enabled: false` or `repeat: 0` comes before them; otherwise they are
+    // deserialized even though the stage won't run. Names resolve to
+    // versioned stages like StageManager's do. Entry keys the pipeline can't
+    // honour, such as `depends_on` or `tags`, are rejected rather than
+    // ignored.
+    pub fn from_deserializer<'de, R, D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        R: StageSet<C>,
+        D: Deserializer<'de>,
+    {
+        Self::from_deserializer_as::<R, D>(deserializer, None)
+    }
+
+    // from_deserializer, matching names after bringing them to `case` as
+    // StageManager::with_name_normalization does.
+    pub fn from_deserializer_normalized<'de, R, D>(
+        deserializer: D,
+        case: NameCase,
+    ) -> Result<Self, D::Error>
+    where
+        R: StageSet<C>,
+        D: Deserializer<'de>,
+    {
+        Self::from_deserializer_as::<R, D>(deserializer, Some(case))
+    }
+
+    fn from_deserializer_as<'de, R, D>(
+        deserializer: D,
+        case: Option<NameCase>,
+    ) -> Result<Self, D::Error>
+    where
+        R: StageSet<C>,
+        D: Deserializer<'de>,
+    {
+        let stages = deserializer.deserialize_any(FileVisitor::<C, R>::new(case))?;
+        let total = stages.len();
+        let stages = stages
+            .into_iter()
+            .enumerate()
+            .filter_map(|(index, entry)| {
+                let mut stage = entry.stage?;
+                stage.setup_with(StageSetupCtx {
+                    index,
+                    name: &entry.name,
+                    total,
+                    retry_budget: None,
+                });
+                Some(CompiledStage {
+                    stage,
+                    repeat: entry.repeat,
+                    index,
+                    name: entry.name,
+                })
+            })
+            .collect();
+        Ok(Self { stages, total })
+    }
+}
+
+struct Entry<C> {
+    name: String,
+    // None for disabled stages.
+    stage: Option<BoxedStage<C>>,
+    repeat: u32,
+}
+
+type Entries<C> = Vec<Entry<C>>;
+
+// How entry names are matched, carried down to each entry's args.
+struct FileVisitor<C, R> {
+    case: Option<NameCase>,
+    _set: PhantomData<fn() -> (C, R)>,
+}
+
+impl<C, R> FileVisitor<C, R> {
+    fn new(case: Option<NameCase>) -> Self {
+        Self {
+            case,
+            _set: PhantomData,
+        }
+    }
+}
+
+impl<'de, C, R: StageSet<C>> Visitor<'de> for FileVisitor<C, R> {
+    type Value = Entries<C>;
+
+    fn expecting(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("a map with a `stages` key or a sequence of stages")
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, seq: A) -> Result<Entries<C>, A::Error> {
+        StagesVisitor(self).visit_seq(seq)
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Entries<C>, A::Error> {
+        let mut stages = None;
+        while let Some(key) = map.next_key::<String>()? {
+            match key.as_str() {
+                "stages" if stages.is_some() => {
+                    return Err(de::Error::duplicate_field("stages"));
+                }
+                "stages" => {
+                    stages = Some(
+                        map.next_value_seed(StagesVisitor(FileVisitor::<C, R>::new(self.case)))?,
+                    );
+                }
+                "version" => {
+                    let version: u32 = map.next_value()?;
+                    if version > MAX_SUPPORTED_VERSION {
+                        return Err(de::Error::custom(StageError::UnsupportedFileVersion {
+                            version,
+                        }));
+                    }
+                }
+                _ => {
+                    map.next_value::<IgnoredAny>()?;
+                }
+            }
+        }
+        stages.ok_or_else(|| de::Error::missing_field("stages"))
+    }
+}
+
+struct StagesVisitor<C, R>(FileVisitor<C, R>);
+
+impl<'de, C, R: StageSet<C>> DeserializeSeed<'de> for StagesVisitor<C, R> {
+    type Value = Entries<C>;
+
+    fn deserialize<D: Deserializer<'de>>(self, d: D) -> Result<Entries<C>, D::Error> {
+        d.deserialize_seq(self)
+    }
+}
+
+impl<'de, C, R: StageSet<C>> Visitor<'de> for StagesVisitor<C, R> {
+    type Value = Entries<C>;
+
+    fn expecting(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("a sequence of stages")
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Entries<C>, A::Error> {
+        let mut stages = Vec::new();
+        while let Some(entry) =
+            seq.next_element_seed(EntryVisitor(FileVisitor::<C, R>::new(self.0.case)))?
+        {
+            stages.push(entry);
+        }
+        Ok(stages)
+    }
+}
+
+struct EntryVisitor<C, R>(FileVisitor<C, R>);
+
+// `id` and `meta` are accepted since they don't change how a stage runs.
+const ENTRY_FIELDS: &[&str] = &["name", "args", "enabled", "repeat", "id", "meta"];
+
+impl<'de, C, R: StageSet<C>> DeserializeSeed<'de> for EntryVisitor<C, R> {
+    type Value = Entry<C>;
+
+    fn deserialize<D: Deserializer<'de>>(self, d: D) -> Result<Entry<C>, D::Error> {
+        d.deserialize_map(self)
+    }
+}
+
+impl<'de, C, R: StageSet<C>> Visitor<'de> for EntryVisitor<C, R> {
+    type Value = Entry<C>;
+
+    fn expecting(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("a stage")
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Entry<C>, A::Error> {
+        let mut name: Option<String> = None;
+        let mut stage = None;
+        let mut enabled = true;
+        let mut repeat = 1;
+        while let Some(key) = map.next_key::<String>()? {
+            match key.as_str() {
+                "name" => name = Some(map.next_value()?),
+                "args" if !enabled || repeat == 0 => {
+                    map.next_value::<IgnoredAny>()?;
+                }
+                "args" => {
+                    let name = name
+                        .as_deref()
+                        .ok_or_else(|| de::Error::custom("`name` must come before `args`"))?;
+                    let seed = ArgsSeed::<C, R>::new(name, self.0.case);
+                    stage = Some(map.next_value_seed(seed)?);
+                }
+                "enabled" => enabled = map.next_value()?,
+                "repeat" => repeat = map.next_value()?,
+                "id" | "meta" => {
+                    map.next_value::<IgnoredAny>()?;
+                }
+                _ => return Err(de::Error::unknown_field(&key, ENTRY_FIELDS)),
+            }
+        }
+
+        let name = name.ok_or_else(|| de::Error::missing_field("name"))?;
+        if !enabled || repeat == 0 {
+            return Ok(Entry {
+                name,
+                stage: None,
+                repeat,
+            });
+        }
+        let stage = match stage {
+            Some(stage) => stage,
+            None => {
+                let empty =
+                    MapDeserializer::<_, de::value::Error>::new(std::iter::empty::<((), ())>());
+                ArgsSeed::<C, R>::new(&name, self.0.case)
+                    .deserialize(empty)
+                    .map_err(de::Error::custom)?
+            }
+        };
+        Ok(Entry {
+            name,
+            stage: Some(stage),
+            repeat,
+        })
+    }
+}
+
+struct ArgsSeed<'n, C, R> {
+    name: &'n str,
+    case: Option<NameCase>,
+    _set: PhantomData<fn() -> (C, R)>,
+}
+
+impl<'n, C, R> ArgsSeed<'n, C, R> {
+    fn new(name: &'n str, case: Option<NameCase>) -> Self {
+        Self {
+            name,
+            case,
+            _set: PhantomData,
+        }
+    }
+}
+
+impl<'de, C, R: StageSet<C>> DeserializeSeed<'de> for ArgsSeed<'_, C, R> {
+    type Value = BoxedStage<C>;
+
+    fn deserialize<D: Deserializer<'de>>(self, d: D) -> Result<BoxedStage<C>, D::Error> {
+        let keys: BTreeMap<String, ()> = R::keys().into_iter().map(|k| (k, ())).collect();
+        let unknown = || de::Error::custom(format!("no stage registered for: {}", self.name));
+        let key = resolve_name_as(&keys, self.name, self.case).ok_or_else(unknown)?;
+        R::deserialize_named(key, d)?.ok_or_else(unknown)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test::{Add, CalcContext, Mul};
+    use serde::Deserialize;
+
+    #[test]
+    fn pipeline_from_a_yaml_deserializer() {
+        let yaml_str = r#"
+        stages:
+        - name: add
+          args:
+            x: 2
+        - name: mul
+          args:
+            x: 3
+        - name: add
+          args:
+            x: 100
+          enabled: false
+        "#;
+
+        let de = serde_yaml::Deserializer::from_str(yaml_str);
+        let pipeline = CompiledPipeline::from_deserializer::<(Add, Mul), _>(de).unwrap();
+
+        let mut c = CalcContext { x: 1 };
+        pipeline.run(&mut c).unwrap();
+        assert_eq!(c.x, 9);
+
+        let de = serde_yaml::Deserializer::from_str("[{name: sub, args: {x: 1}}]");
+        let err =
+            CompiledPipeline::<CalcContext>::from_deserializer::<(Add, Mul), _>(de).unwrap_err();
+        assert!(err.to_string().contains("no stage registered for: sub"));
+    }
+
+    #[test]
+    fn unsupported_entry_keys_are_rejected() {
+        let de = serde_yaml::Deserializer::from_str("[{name: add, args: {x: 1}, tags: [a]}]");
+        let err =
+            CompiledPipeline::<CalcContext>::from_deserializer::<(Add, Mul), _>(de).unwrap_err();
+        assert!(err.to_string().contains("unknown field `tags`"));
+
+        let de = serde_yaml::Deserializer::from_str("{version: 2, stages: []}");
+        let err =
+            CompiledPipeline::<CalcContext>::from_deserializer::<(Add, Mul), _>(de).unwrap_err();
+        assert!(err.to_string().contains("version 2"));
+    }
+
+    #[test]
+    fn args_before_name_are_rejected() {
+        let de = serde_yaml::Deserializer::from_str("[{args: {x: 1}, name: add}]");
+        let err =
+            CompiledPipeline::<CalcContext>::from_deserializer::<(Add, Mul), _>(de).unwrap_err();
+        assert!(err.to_string().contains("`name` must come before `args`"));
+    }
+
+    #[test]
+    fn names_resolve_like_the_manager() {
+        #[derive(Deserialize)]
+        struct AddV2 {
+            amount: i64,
+        }
+
+        impl StageName for AddV2 {
+            fn stage_name() -> &'static str {
+                "add_twice"
+            }
+
+            fn version() -> u32 {
+                2
+            }
+        }
+
+        impl Stage for AddV2 {
+            type C = CalcContext;
+
+            fn run(&self, c: &mut Self::C) -> Result<(), StageError> {
+                c.x += self.amount * 2;
+                Ok(())
+            }
+        }
+
+        let yaml_str =
+            "[{name: add_twice, args: {amount: 1}}, {name: add_twice@2, args: {amount: 2}}]";
+        let de = serde_yaml::Deserializer::from_str(yaml_str);
+        let pipeline = CompiledPipeline::from_deserializer::<(AddV2,), _>(de).unwrap();
+        let mut c = CalcContext { x: 0 };
+        pipeline.run(&mut c).unwrap();
+        assert_eq!(c.x, 6);
+
+        let de = serde_yaml::Deserializer::from_str("[{name: AddTwice, args: {amount: 1}}]");
+        let pipeline =
+            CompiledPipeline::from_deserializer_normalized::<(AddV2,), _>(de, NameCase::Snake)
+                .unwrap();
+        let mut c = CalcContext { x: 0 };
+        pipeline.run(&mut c).unwrap();
+        assert_eq!(c.x, 2);
+    }
+
+    #[test]
+    fn disabled_entries_skip_their_args() {
+        let yaml_str = r#"
+        - name: add
+          enabled: false
+          args:
+            x: not a number
+        - name: mul
+          repeat: 0
+        - name: add
+          args:
+            x: 2
+        "#;
+
+        let de = serde_yaml::Deserializer::from_str(yaml_str);
+        let pipeline = CompiledPipeline::from_deserializer::<(Add, Mul), _>(de).unwrap();
+        let mut c = CalcContext { x: 1 };
+        pipeline.run(&mut c).unwrap();
+        assert_eq!(c.x, 3);
+    }
+}
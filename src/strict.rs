@@ -0,0 +1,107 @@
+use serde::{de::IgnoredAny, Deserialize, Deserializer};
+use std::collections::BTreeMap;
+
+use crate::{StageArgs, StageError, StageManager};
+
+impl<'de, C, V> StageManager<C, V>
+where
+    V: Deserialize<'de> + Deserializer<'de> + Clone,
+{
+    // Rejects args with keys the stage's struct doesn't declare. Only the top
+    // level of each stage's args is checked, and only for stages registered
+    // by type that deserialize as a plain struct.
+    pub fn strict(&mut self, enabled: bool) -> &mut Self {
+        self.strict = enabled;
+        self
+    }
+
+    pub(crate) fn check_fields(&self, s: &StageArgs<V>, args: &V) -> Result<(), StageError> {
+        let key = self.resolve(&s.name).unwrap_or(&s.name);
+        let schema = match self.schemas.get(key) {
+            Some(schema) if self.strict => schema.get(),
+            _ => return Ok(()),
+        };
+        let schema = match schema {
+            Some(schema) => schema,
+            None => return Ok(()),
+        };
+        let keys = match BTreeMap::<String, IgnoredAny>::deserialize(args.clone()) {
+            Ok(keys) => keys,
+            Err(_) => return Ok(()),
+        };
+        match keys.into_keys().find(|k| schema.field(k).is_none()) {
+            Some(field) => Err(StageError::UnknownField {
+                stage_name: s.name.clone(),
+                field,
+            }),
+            None => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use serde_yaml::Value;
+
+    use crate::{
+        stages::Retry,
+        test::{Add, CalcContext},
+        StageError, StageFile, StageManager,
+    };
+
+    fn manager(yaml_str: &str) -> StageManager<CalcContext, Value> {
+        let file: StageFile<Value> = serde_yaml::from_str(yaml_str).unwrap();
+        let mut m = StageManager::from_file(file);
+        m.register::<Add>()
+            .unwrap()
+            .register_named::<Retry<Add>>("retry")
+            .unwrap();
+        m
+    }
+
+    #[test]
+    fn strict_rejects_unknown_fields() {
+        let yaml_str = r#"
+        stages:
+        - name: add
+          args:
+            x: 1
+            xx: 2
+        "#;
+
+        let mut m = manager(yaml_str);
+        let mut c = CalcContext { x: 1 };
+        m.run_stages(&mut c).unwrap();
+        assert_eq!(c.x, 2);
+
+        m.strict(true);
+        match m.run_stages(&mut c) {
+            Err(StageError::UnknownField { stage_name, field }) => {
+                assert_eq!(stage_name, "add");
+                assert_eq!(field, "xx");
+            }
+            other => panic!("expected UnknownField, got {:?}", other),
+        }
+        assert_eq!(c.x, 2);
+    }
+
+    #[test]
+    fn strict_checks_top_level_only() {
+        let yaml_str = r#"
+        stages:
+        - name: retry
+          args:
+            max_attempts: 2
+            inner:
+              x: 3
+              extra: true
+        "#;
+
+        let mut m = manager(yaml_str);
+        m.strict(true);
+
+        let mut c = CalcContext { x: 1 };
+        m.run_stages(&mut c).unwrap();
+        assert_eq!(c.x, 4);
+    }
+}
@@ -0,0 +1,130 @@
+use serde::{Deserialize, Deserializer};
+use std::{cell::RefCell, marker::PhantomData, rc::Rc};
+
+use crate::{RegisterError, Stage, StageError, StageManager};
+
+// Every recorded run as the stage's registered name and the args it was
+// built from, in run order.
+pub type CallLog<V> = Rc<RefCell<Vec<(String, V)>>>;
+
+// A stage that does nothing but note each run in its log.
+pub struct RecordingStage<C, V> {
+    name: String,
+    args: V,
+    log: CallLog<V>,
+    _context: PhantomData<fn(&mut C)>,
+}
+
+impl<C, V> RecordingStage<C, V> {
+    pub fn new(name: &str, args: V, log: CallLog<V>) -> Self {
+        Self {
+            name: name.to_string(),
+            args,
+            log,
+            _context: PhantomData,
+        }
+    }
+}
+
+impl<C, V: Clone> Stage for RecordingStage<C, V> {
+    type C = C;
+
+    fn run(&self, _c: &mut Self::C) -> Result<(), StageError> {
+        self.log
+            .borrow_mut()
+            .push((self.name.clone(), self.args.clone()));
+        Ok(())
+    }
+}
+
+// Registers recording stages in place of real ones so tests can assert which
+// stages ran, in what order and with which args.
+pub struct MockRegistry<V> {
+    log: CallLog<V>,
+}
+
+impl<V> Default for MockRegistry<V> {
+    fn default() -> Self {
+        Self { log: Rc::default() }
+    }
+}
+
+impl<V: 'static + Clone> MockRegistry<V> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn register<'de, C: 'static>(
+        &self,
+        manager: &mut StageManager<C, V>,
+        names: &[&str],
+    ) -> Result<(), RegisterError>
+    where
+        V: Deserialize<'de> + Deserializer<'de>,
+    {
+        for &name in names {
+            let log = Rc::clone(&self.log);
+            let owned = name.to_string();
+            manager.register_fn(name, move |v| {
+                Ok(RecordingStage::new(&owned, v, Rc::clone(&log)))
+            })?;
+        }
+        Ok(())
+    }
+
+    pub fn calls(&self) -> Vec<(String, V)> {
+        self.log.borrow().clone()
+    }
+
+    pub fn names(&self) -> Vec<String> {
+        self.log
+            .borrow()
+            .iter()
+            .map(|(name, _)| name.clone())
+            .collect()
+    }
+
+    pub fn clear(&self) {
+        self.log.borrow_mut().clear();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use serde_yaml::Value;
+
+    use super::MockRegistry;
+    use crate::{test::CalcContext, StageFile, StageManager};
+
+    #[test]
+    fn records_add_mul_order() {
+        let yaml_str = r#"
+        stages:
+        - name: mul
+          args:
+            x: 1
+        - name: add
+          args:
+            x: 2
+        - name: mul
+          enabled: false
+        - name: mul
+          args:
+            x: 5
+        "#;
+
+        let file: StageFile<Value> = serde_yaml::from_str(yaml_str).unwrap();
+        let mut m = StageManager::<CalcContext, Value>::from_file(file);
+        let mock = MockRegistry::new();
+        mock.register(&mut m, &["add", "mul"]).unwrap();
+
+        let mut c = CalcContext { x: 1 };
+        m.run_stages(&mut c).unwrap();
+
+        assert_eq!(mock.names(), ["mul", "add", "mul"]);
+        let calls = mock.calls();
+        assert_eq!(calls[1].1["x"], Value::from(2));
+        assert_eq!(calls[2].1["x"], Value::from(5));
+        assert_eq!(c.x, 1);
+    }
+}
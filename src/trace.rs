@@ -0,0 +1,121 @@
+use serde::{Deserialize, Deserializer, Serialize};
+
+use crate::{Listener, StageError, StageFile, StageFlow, StageManager};
+
+// What happened to one stage in a traced run.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TraceOutcome {
+    Continue,
+    Stop,
+    Skip(usize),
+    Failed(String),
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct TraceEntry<V> {
+    pub index: usize,
+    pub name: String,
+    // As written in the file, before any preprocessing; None if omitted.
+    pub args: Option<V>,
+    pub outcome: TraceOutcome,
+}
+
+// Every stage that ran in a run_stages_traced call, in run order.
+#[derive(Debug, Clone, Serialize)]
+pub struct RunTrace<V> {
+    pub entries: Vec<TraceEntry<V>>,
+}
+
+struct TraceListener<'a, V> {
+    file: &'a StageFile<V>,
+    entries: Vec<TraceEntry<V>>,
+}
+
+impl<C, V: Clone> Listener<C> for TraceListener<'_, V> {
+    fn after_stage(&mut self, index: usize, name: &str, result: &Result<StageFlow, StageError>) {
+        let outcome = match result {
+            Ok(StageFlow::Continue) => TraceOutcome::Continue,
+            Ok(StageFlow::Stop) => TraceOutcome::Stop,
+            Ok(StageFlow::Skip(n)) => TraceOutcome::Skip(*n),
+            Err(e) => TraceOutcome::Failed(e.to_string()),
+        };
+        self.entries.push(TraceEntry {
+            index,
+            name: name.to_string(),
+            args: self.file.stages[index].args.clone(),
+            outcome,
+        });
+    }
+}
+
+impl<'de, C, V> StageManager<C, V>
+where
+    V: Deserialize<'de> + Deserializer<'de> + Clone,
+{
+    // Runs the pipeline and records each stage that ran with its args and
+    // outcome. Stages whose args fail to deserialize never run and so are
+    // missing from the trace, but still fail the run.
+    pub fn run_stages_traced(&self, context: &mut C) -> (Result<(), StageError>, RunTrace<V>) {
+        let mut listener = TraceListener {
+            file: &self.file,
+            entries: Vec::new(),
+        };
+        let result = self.run_with(context, &(), |_, _| Ok(()), &mut listener);
+        let trace = RunTrace {
+            entries: listener.entries,
+        };
+        (result, trace)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use serde_yaml::Value;
+
+    use super::TraceOutcome;
+    use crate::{
+        test::{Add, CalcContext, Mul},
+        StageFile, StageManager,
+    };
+
+    #[test]
+    fn trace_records_each_stage() {
+        let yaml_str = r#"
+        stages:
+        - name: mul
+          args:
+            x: 1
+        - name: add
+          args:
+            x: 2
+        - name: mul
+          args:
+            x: 5
+        "#;
+
+        let file: StageFile<Value> = serde_yaml::from_str(yaml_str).unwrap();
+        let mut m = StageManager::from_file(file);
+        m.register::<Mul>().unwrap().register::<Add>().unwrap();
+
+        let mut c = CalcContext { x: 1 };
+        let (result, trace) = m.run_stages_traced(&mut c);
+        result.unwrap();
+
+        let names: Vec<_> = trace.entries.iter().map(|e| e.name.as_str()).collect();
+        assert_eq!(names, ["mul", "add", "mul"]);
+        let args: Vec<_> = trace
+            .entries
+            .iter()
+            .map(|e| e.args.as_ref().unwrap()["x"].clone())
+            .collect();
+        assert_eq!(args, [Value::from(1), Value::from(2), Value::from(5)]);
+        assert!(trace
+            .entries
+            .iter()
+            .all(|e| e.outcome == TraceOutcome::Continue));
+
+        let written = serde_yaml::to_string(&trace).unwrap();
+        assert!(written.contains("outcome: continue"));
+    }
+}
@@ -0,0 +1,182 @@
+use serde::{Deserialize, Deserializer};
+use std::{collections::BTreeMap, fmt::Debug};
+
+use crate::{
+    registered, registry_key, resolve_name, BoxError, RegisterError, StageError, StageFile,
+    StageName,
+};
+
+// A stage that consumes its input and returns the next value instead of
+// mutating a context in place.
+pub trait TransformStage {
+    type In;
+    type Out;
+
+    fn run(&self, input: Self::In) -> Result<Self::Out, StageError>;
+}
+
+type FnDeserializeTransform<T, V> =
+    Box<dyn Fn(V) -> Result<Box<dyn TransformStage<In = T, Out = T>>, BoxError>>;
+
+// Threads a value through each stage in the file, passing every stage's
+// output to the next. All stages take and return the same type `T`.
+#[derive(Deserialize)]
+pub struct TransformManager<T, V> {
+    #[serde(flatten)]
+    file: StageFile<V>,
+
+    #[serde(skip)]
+    deserialize_map: BTreeMap<String, FnDeserializeTransform<T, V>>,
+}
+
+impl<T, V> Debug for TransformManager<T, V>
+where
+    V: Debug,
+{
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(
+            f,
+            "TransformManager{{file: {:?}, deserialize_map: {:?}}}",
+            self.file,
+            self.deserialize_map.keys()
+        )
+    }
+}
+
+impl<'de, T, V> TransformManager<T, V>
+where
+    V: Deserialize<'de> + Deserializer<'de> + Clone,
+{
+    pub fn from_file(stage_file: StageFile<V>) -> Self {
+        Self {
+            file: stage_file,
+            deserialize_map: BTreeMap::new(),
+        }
+    }
+
+    pub fn validate(&self) -> Result<(), Vec<String>> {
+        let missing = self
+            .file
+            .unregistered(|name| resolve_name(&self.deserialize_map, name).is_some());
+        if missing.is_empty() {
+            Ok(())
+        } else {
+            Err(missing)
+        }
+    }
+
+    pub fn run(&self, input: T) -> Result<T, StageError> {
+        self.validate()
+            .map_err(|names| StageError::UnknownStage { names })?;
+
+        let mut value = input;
+        for (index, s) in self.file.enabled_stages()? {
+            let f = registered(&self.deserialize_map, &s.name);
+            let stage =
+                s.args_or_empty()
+                    .and_then(f)
+                    .map_err(|source| StageError::Deserialize {
+                        stage_name: s.name.clone(),
+                        index,
+                        position: None,
+                        source,
+                    })?;
+            for _ in 0..s.repeat {
+                value = stage.run(value)?;
+            }
+        }
+        Ok(value)
+    }
+
+    pub fn register_named<S>(&mut self, name: &str) -> Result<&mut Self, RegisterError>
+    where
+        S: 'static + TransformStage<In = T, Out = T> + Deserialize<'de>,
+    {
+        if self.deserialize_map.contains_key(name) {
+            return Err(RegisterError::Duplicate(name.to_string()));
+        }
+        self.deserialize_map.insert(
+            name.to_string(),
+            Box::new(|v| {
+                let stage = S::deserialize(v).map_err(|e| e.to_string())?;
+                Ok(Box::new(stage))
+            }),
+        );
+        Ok(self)
+    }
+
+    pub fn register<S>(&mut self) -> Result<&mut Self, RegisterError>
+    where
+        S: 'static + TransformStage<In = T, Out = T> + StageName + Deserialize<'de>,
+    {
+        self.register_named::<S>(&registry_key::<S>())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use serde_yaml::Value;
+
+    use super::*;
+
+    #[derive(Deserialize)]
+    struct Upper {}
+
+    impl StageName for Upper {
+        fn stage_name() -> &'static str {
+            "upper"
+        }
+    }
+
+    impl TransformStage for Upper {
+        type In = String;
+        type Out = String;
+
+        fn run(&self, input: String) -> Result<String, StageError> {
+            Ok(input.to_uppercase())
+        }
+    }
+
+    #[derive(Deserialize)]
+    struct Suffix {
+        text: String,
+    }
+
+    impl StageName for Suffix {
+        fn stage_name() -> &'static str {
+            "suffix"
+        }
+    }
+
+    impl TransformStage for Suffix {
+        type In = String;
+        type Out = String;
+
+        fn run(&self, input: String) -> Result<String, StageError> {
+            Ok(input + &self.text)
+        }
+    }
+
+    #[test]
+    fn string_transforms() {
+        let yaml_str = r#"
+        stages:
+        - name: suffix
+          args:
+            text: ", world"
+        - name: upper
+          args: {}
+        - name: suffix
+          repeat: 2
+          args:
+            text: "!"
+        "#;
+
+        let file: StageFile<Value> = serde_yaml::from_str(yaml_str).unwrap();
+        let mut m = TransformManager::from_file(file);
+        m.register::<Upper>().unwrap().register::<Suffix>().unwrap();
+
+        let out = m.run("hello".to_string()).unwrap();
+        assert_eq!(out, "HELLO, WORLD!!");
+    }
+}
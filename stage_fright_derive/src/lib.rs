@@ -0,0 +1,89 @@
+use proc_macro::TokenStream;
+use quote::quote;
+use syn::{parse_macro_input, DeriveInput, Lit, Meta, NestedMeta};
+
+/// Implements `stage_fright::StageName`, defaulting to the snake_case type name.
+/// Use `#[stage(name = "...")]` to override it.
+///
+/// ```compile_fail
+/// #[derive(stage_fright_derive::StageName)]
+/// #[stage(name = 5)]
+/// struct Bad;
+/// ```
+#[proc_macro_derive(StageName, attributes(stage))]
+pub fn derive_stage_name(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+
+    let name = match stage_name(&input) {
+        Ok(name) => name,
+        Err(e) => return e.to_compile_error().into(),
+    };
+
+    let ident = &input.ident;
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+    let expanded = quote! {
+        impl #impl_generics ::stage_fright::StageName for #ident #ty_generics #where_clause {
+            fn stage_name() -> &'static str {
+                #name
+            }
+        }
+    };
+    expanded.into()
+}
+
+fn stage_name(input: &DeriveInput) -> syn::Result<String> {
+    let mut name = None;
+    for attr in input.attrs.iter().filter(|a| a.path.is_ident("stage")) {
+        let list = match attr.parse_meta()? {
+            Meta::List(list) => list,
+            meta => {
+                return Err(syn::Error::new_spanned(
+                    meta,
+                    "expected #[stage(name = \"...\")]",
+                ))
+            }
+        };
+
+        for nested in list.nested {
+            match nested {
+                NestedMeta::Meta(Meta::NameValue(nv)) if nv.path.is_ident("name") => match nv.lit {
+                    Lit::Str(s) => name = Some(s.value()),
+                    lit => return Err(syn::Error::new_spanned(lit, "stage name must be a string")),
+                },
+                nested => return Err(syn::Error::new_spanned(nested, "unknown stage attribute")),
+            }
+        }
+    }
+
+    Ok(name.unwrap_or_else(|| snake_case(&input.ident.to_string())))
+}
+
+fn snake_case(ident: &str) -> String {
+    let chars: Vec<char> = ident.chars().collect();
+    let mut out = String::with_capacity(ident.len() + 4);
+    for (i, &c) in chars.iter().enumerate() {
+        if c.is_uppercase() && i > 0 {
+            let prev = chars[i - 1];
+            let next_lower = chars.get(i + 1).is_some_and(|n| n.is_lowercase());
+            if prev != '_' && (prev.is_lowercase() || prev.is_numeric() || next_lower) {
+                out.push('_');
+            }
+        }
+        out.extend(c.to_lowercase());
+    }
+    out
+}
+
+#[cfg(test)]
+mod test {
+    use super::snake_case;
+
+    #[test]
+    fn snake_case_names() {
+        assert_eq!(snake_case("Add"), "add");
+        assert_eq!(snake_case("AddOne"), "add_one");
+        assert_eq!(snake_case("HTTPRequest"), "http_request");
+        assert_eq!(snake_case("Stage2Go"), "stage2_go");
+        assert_eq!(snake_case("already_snake"), "already_snake");
+    }
+}
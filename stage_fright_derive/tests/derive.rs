@@ -0,0 +1,62 @@
+use serde::Deserialize;
+use serde_yaml::Value;
+use stage_fright::{Stage, StageError, StageFile, StageManager, StageName};
+
+#[derive(Debug, Default)]
+struct CalcContext {
+    x: i64,
+}
+
+#[derive(Debug, Deserialize, StageName)]
+struct AddOne {}
+
+impl Stage for AddOne {
+    type C = CalcContext;
+
+    fn run(&self, c: &mut Self::C) -> Result<(), StageError> {
+        c.x += 1;
+        Ok(())
+    }
+}
+
+#[derive(Debug, Deserialize, StageName)]
+#[stage(name = "times")]
+struct Mul {
+    x: i64,
+}
+
+impl Stage for Mul {
+    type C = CalcContext;
+
+    fn run(&self, c: &mut Self::C) -> Result<(), StageError> {
+        c.x *= self.x;
+        Ok(())
+    }
+}
+
+#[test]
+fn derived_names() {
+    assert_eq!(AddOne::stage_name(), "add_one");
+    assert_eq!(Mul::stage_name(), "times");
+}
+
+#[test]
+fn derived_names_register() {
+    let yaml_str = r#"
+    stages:
+    - name: add_one
+      args: {}
+    - name: times
+      args:
+        x: 3
+    "#;
+
+    let file: StageFile<Value> = serde_yaml::from_str(yaml_str).unwrap();
+    let mut m = StageManager::from_file(file);
+    m.register::<AddOne>().unwrap().register::<Mul>().unwrap();
+
+    let mut c = CalcContext { x: 1 };
+    m.run(&mut c).unwrap();
+
+    assert_eq!(c.x, 6);
+}